@@ -0,0 +1,203 @@
+use crate::{Keyword, KeywordDefinition, ParserError, Value, ValueType};
+use anyhow::{anyhow, Result};
+use std::path::PathBuf;
+
+/// Conversion of parsed keywords into the instructions of a layer.
+/// Usually implemented with `#[derive(Keyword)]`, which generates the
+/// definitions and the conversion from the same enum.
+pub trait FromKeyword: Sized {
+    /// Definitions of all keywords the type can be converted from,
+    /// ready to be added to a [`crate::SysConfigParser`]
+    fn keyword_definitions() -> Vec<(String, KeywordDefinition)>;
+
+    fn from_keyword(keyword: &Keyword) -> Result<Self>;
+}
+
+/// Types an option value can be converted into
+pub trait FromValue: Sized {
+    /// The type options of this type are declared with
+    fn value_type() -> ValueType;
+
+    fn from_value(value: &Value) -> Option<Self>;
+
+    /// Convert raw text, e.g. the default of an option
+    fn from_raw(raw: &str) -> Option<Self> {
+        Value::coerce(raw, &Self::value_type()).and_then(|v| Self::from_value(&v))
+    }
+}
+
+impl FromValue for String {
+    fn value_type() -> ValueType {
+        ValueType::String
+    }
+
+    fn from_value(value: &Value) -> Option<Self> {
+        Some(value.to_string())
+    }
+}
+
+impl FromValue for bool {
+    fn value_type() -> ValueType {
+        ValueType::Boolean
+    }
+
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Boolean(b) => Some(*b),
+            Value::String(s) => Value::coerce(s, &ValueType::Boolean)?.as_bool(),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for PathBuf {
+    fn value_type() -> ValueType {
+        ValueType::Path
+    }
+
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Path(p) => Some(p.clone()),
+            Value::String(s) if !s.is_empty() => Some(s.into()),
+            _ => None,
+        }
+    }
+}
+
+macro_rules! integer_from_value {
+    ($($t:ty),*) => {
+        $(impl FromValue for $t {
+            fn value_type() -> ValueType {
+                ValueType::Integer
+            }
+
+            fn from_value(value: &Value) -> Option<Self> {
+                match value {
+                    Value::Integer(i) => <$t>::try_from(*i).ok(),
+                    Value::String(s) => s.parse().ok(),
+                    _ => None,
+                }
+            }
+        })*
+    };
+}
+
+integer_from_value!(i32, i64, u8, u16, u32, u64, usize);
+
+impl Keyword {
+    /// The value of the option `name` converted to `T`
+    pub fn value_of<T: FromValue>(&self, name: &str) -> Result<Option<T>> {
+        self.option(name)
+            .map(|opt| {
+                T::from_value(&opt.value).ok_or_else(|| {
+                    anyhow!(ParserError::InvalidOptionValue {
+                        keyword: self.name.clone(),
+                        option: name.into(),
+                        value: opt.value.to_string(),
+                        expected: T::value_type(),
+                        span: opt.span.clone(),
+                    })
+                })
+            })
+            .transpose()
+    }
+
+    /// The values of every occurrence of the option `name` converted to `T`
+    pub fn values_of<T: FromValue>(&self, name: &str) -> Result<Vec<T>> {
+        self.options
+            .iter()
+            .filter(|o| o.name == name)
+            .map(|opt| {
+                T::from_value(&opt.value).ok_or_else(|| {
+                    anyhow!(ParserError::InvalidOptionValue {
+                        keyword: self.name.clone(),
+                        option: name.into(),
+                        value: opt.value.to_string(),
+                        expected: T::value_type(),
+                        span: opt.span.clone(),
+                    })
+                })
+            })
+            .collect()
+    }
+}
+
+/// Helpers for the code generated by `#[derive(Keyword)]`
+#[doc(hidden)]
+pub mod derive_support {
+    use crate::{Keyword, ParserError};
+    pub use anyhow::Result;
+    use anyhow::{anyhow, Context};
+    use std::collections::HashMap;
+
+    use super::FromValue;
+
+    pub fn required<T>(value: Option<T>, keyword: &Keyword, option: &str) -> Result<T> {
+        value.ok_or_else(|| {
+            anyhow!(ParserError::MissingOption {
+                keyword: keyword.name.clone(),
+                option: option.into(),
+                span: keyword.span.clone(),
+            })
+        })
+    }
+
+    /// An option which may also be given as argument
+    pub fn required_value<T>(value: Option<T>, keyword: &Keyword, option: &str) -> Result<T> {
+        value.ok_or_else(|| {
+            anyhow!(ParserError::MissingValue {
+                keyword: keyword.name.clone(),
+                option: option.into(),
+                span: keyword.span.clone(),
+            })
+        })
+    }
+
+    pub fn or_default<T: FromValue>(value: Option<T>, option: &str, default: &str) -> Result<T> {
+        match value {
+            Some(value) => Ok(value),
+            None => T::from_raw(default)
+                .with_context(|| format!("default {} of option {} is not valid", default, option)),
+        }
+    }
+
+    pub fn argument(keyword: &Keyword, index: usize) -> Option<String> {
+        keyword.arguments.get(index).map(|a| a.value.clone())
+    }
+
+    pub fn required_argument(keyword: &Keyword, index: usize) -> Result<String> {
+        argument(keyword, index).ok_or_else(|| {
+            anyhow!(ParserError::ArgumentCount {
+                keyword: keyword.name.clone(),
+                expected: format!("at least {}", index + 1),
+                found: keyword.arguments.len(),
+                span: keyword.span.clone(),
+            })
+        })
+    }
+
+    pub fn arguments(keyword: &Keyword, from: usize) -> Vec<String> {
+        keyword
+            .arguments
+            .iter()
+            .skip(from)
+            .map(|a| a.value.clone())
+            .collect()
+    }
+
+    pub fn no_children() -> HashMap<String, crate::KeywordDefinition> {
+        HashMap::new()
+    }
+
+    pub fn unknown_keyword(keyword: &Keyword, valid: &[&str]) -> anyhow::Error {
+        let mut valid = valid.iter().map(|v| v.to_string()).collect::<Vec<String>>();
+        valid.sort();
+        let mut span = keyword.span.clone();
+        span.end = span.start + keyword.name.len();
+        anyhow!(ParserError::UnknownKeyword {
+            keyword: keyword.name.clone(),
+            valid,
+            span,
+        })
+    }
+}
@@ -0,0 +1,146 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// Location of a piece of configuration inside its source.
+/// `line` and `column` are 1-based, `start` and `end` are byte offsets.
+/// Lines end at `\n`, `\r\n` or a lone `\r` like in the grammar.
+#[derive(Default, Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Span {
+    pub file: Option<PathBuf>,
+    pub line: usize,
+    pub column: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub(crate) fn from_pest(span: pest::Span, file: Option<&PathBuf>) -> Self {
+        let (line, column) = line_col(span.get_input(), span.start());
+        Span {
+            file: file.cloned(),
+            line,
+            column,
+            start: span.start(),
+            end: span.end(),
+        }
+    }
+}
+
+/// The 1-based line and column of the byte offset `pos` in `source`.
+/// pest does not count a lone `\r` as line ending, the grammar does.
+pub(crate) fn line_col(source: &str, pos: usize) -> (usize, usize) {
+    let before = source.get(..pos).unwrap_or(source);
+    let mut line_col = (0, 1);
+    for line in source_lines(before) {
+        line_col = (line_col.0 + 1, line.chars().count() + 1);
+    }
+    line_col
+}
+
+/// The lines of `source` without their line endings, split at `\n`,
+/// `\r\n` and a lone `\r` like the grammar does
+pub(crate) fn source_lines(source: &str) -> impl Iterator<Item = &str> {
+    let mut rest = Some(source);
+    std::iter::from_fn(move || {
+        let text = rest?;
+        match text.find(['\n', '\r']) {
+            Some(i) => {
+                let next = if text[i..].starts_with("\r\n") {
+                    i + 2
+                } else {
+                    i + 1
+                };
+                rest = Some(&text[next..]);
+                Some(&text[..i])
+            }
+            None => {
+                rest = None;
+                Some(text)
+            }
+        }
+    })
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:{}:{}", file.display(), self.line, self.column)
+        } else {
+            write!(f, "{}:{}", self.line, self.column)
+        }
+    }
+}
+
+/// An error message attached to a location in a configuration file.
+/// Keyword layers wrap their errors in this so the binaries can point
+/// the operator to the offending line.
+#[derive(Error, Debug, Clone)]
+#[error("{span}: {message}")]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Span,
+}
+
+impl Diagnostic {
+    pub fn new<M: Display>(span: &Span, message: M) -> Self {
+        Diagnostic {
+            message: message.to_string(),
+            span: span.clone(),
+        }
+    }
+
+    /// Render the diagnostic with the offending line of `source`
+    /// and a caret under the location of the error
+    /// ```
+    /// use libcfgparser::{Diagnostic, Span};
+    ///
+    /// let span = Span { file: None, line: 2, column: 1, start: 13, end: 19 };
+    /// let diag = Diagnostic::new(&span, "keyword netwrk is not known");
+    /// let rendered = diag.render("locale en_US\nnetwrk e1000g0\n");
+    /// assert!(rendered.contains("2 | netwrk e1000g0"));
+    /// assert!(rendered.contains("  | ^^^^^^"));
+    /// ```
+    pub fn render(&self, source: &str) -> String {
+        let mut out = format!("error: {}\n", self.message);
+        let line = match source_lines(source).nth(self.span.line.saturating_sub(1)) {
+            Some(line) => line,
+            None => {
+                out += &format!(" --> {}\n", self.span);
+                return out;
+            }
+        };
+
+        let gutter = " ".repeat(self.span.line.to_string().len());
+        let column = self.span.column.max(1);
+        let available = line.chars().count().saturating_sub(column - 1);
+        let len = source
+            .get(self.span.start..self.span.end)
+            .map(|s| source_lines(s).next().unwrap_or_default().chars().count())
+            .unwrap_or_default()
+            .min(available)
+            .max(1);
+
+        out += &format!("{} --> {}\n", gutter, self.span);
+        out += &format!("{} |\n", gutter);
+        out += &format!("{} | {}\n", self.span.line, line);
+        let indent = line
+            .chars()
+            .take(column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect::<String>();
+        out += &format!("{} | {}{}\n", gutter, indent, "^".repeat(len));
+        out
+    }
+
+    /// Like [`Diagnostic::render`] but reads the source from the file
+    /// the span points to. Returns None if the span has no file or the
+    /// file can not be read anymore.
+    pub fn render_from_file(&self) -> Option<String> {
+        let file = self.span.file.as_ref()?;
+        let source = fs::read_to_string(file).ok()?;
+        Some(self.render(&source))
+    }
+}
@@ -0,0 +1,306 @@
+//! Help texts and reference documentation generated from the keyword
+//! definitions registered on a parser.
+
+use crate::{KeywordDefinition, OptionDefinition, ParserError, Span, ValueType};
+use anyhow::{anyhow, Result};
+
+/// Description of the syntax shared by every keyword set
+const SYNTAX: &[&str] = &[
+    "Each line holds one keyword followed by its options and arguments. \
+     Options are written as --name=value, boolean options as --name or --no-name. \
+     Values with spaces are quoted, long lines can be continued with a backslash \
+     and everything after # is a comment.",
+    "Keywords which accept a block take their options and child keywords between \
+     braces, one per line or separated by semicolons. Options in a block are \
+     written without the leading dashes.",
+    "set NAME=VALUE defines a variable which is used as ${NAME}, include FILE \
+     reads another file and if, else and endif select keywords by facts of the \
+     machine.",
+];
+
+/// A keyword set to document
+pub struct Reference<'a> {
+    /// Name of the file format, e.g. sysconfig
+    pub name: &'a str,
+    /// Manual section of the man page, 4 for file formats
+    pub section: &'a str,
+    /// One line summary of the format
+    pub summary: &'a str,
+    pub definitions: &'a [(String, KeywordDefinition)],
+}
+
+impl Reference<'_> {
+    /// The keywords to document sorted by name. Aliases are documented
+    /// together with the keyword they belong to.
+    fn keywords(&self) -> Vec<(&str, &KeywordDefinition)> {
+        let mut keywords: Vec<(&str, &KeywordDefinition)> = vec![];
+        for (name, def) in self.definitions {
+            let documented = keywords
+                .iter()
+                .any(|(_, known)| known.aliases.contains(name));
+            if !documented {
+                keywords.push((name, def));
+            }
+        }
+        keywords.sort_by_key(|(name, _)| *name);
+        keywords
+    }
+
+    /// The reference as Markdown
+    pub fn markdown(&self) -> String {
+        let mut out = format!("# {}\n\n{}\n\n## Syntax\n\n", self.name, self.summary);
+        for paragraph in SYNTAX {
+            out += &format!("{}\n\n", paragraph);
+        }
+        out += "## Keywords\n";
+        for (name, def) in self.keywords() {
+            out += &format!("\n### {}\n\n{}", name, keyword_markdown(name, def));
+        }
+        out
+    }
+
+    /// The reference as man page in man(7) format
+    pub fn man_page(&self) -> String {
+        let mut out = format!(
+            ".TH {} {} \"\" \"illumos-installer\" \"File Formats and Configurations\"\n",
+            self.name.to_uppercase(),
+            self.section
+        );
+        out += &format!(
+            ".SH NAME\n{} \\- {}\n.SH DESCRIPTION\n",
+            roff(self.name),
+            roff(self.summary)
+        );
+        for (i, paragraph) in SYNTAX.iter().enumerate() {
+            if i > 0 {
+                out += ".PP\n";
+            }
+            out += &format!("{}\n", roff(paragraph));
+        }
+        out += ".SH KEYWORDS\n";
+        for (name, def) in self.keywords() {
+            out += &format!(".SS {}\n", roff(name));
+            out += &format!(".nf\n{}\n.fi\n", roff(&signature(name, def)));
+            if let Some(description) = &def.description {
+                for paragraph in description.split("\n\n") {
+                    out += &format!(".PP\n{}\n", roff(paragraph));
+                }
+            }
+            if !def.aliases.is_empty() {
+                out += &format!(".PP\nAlso known as {}.\n", roff(&def.aliases.join(", ")));
+            }
+            for opt in &def.options {
+                out += &format!(".TP\n.B {}\n", roff(&option_signature(opt)));
+                out += &format!("{}\n", roff(&option_text(opt)));
+            }
+            for (child, child_def) in sorted_children(def) {
+                out += &format!(".TP\n.B {}\n", roff(&signature(child, child_def)));
+                out += &format!(
+                    "{}\n",
+                    roff(child_def.description.as_deref().unwrap_or("Block keyword."))
+                );
+            }
+            if !def.examples.is_empty() {
+                out += ".PP\nExample:\n.PP\n.RS 4\n.nf\n";
+                for example in &def.examples {
+                    out += &format!("{}\n", roff(example));
+                }
+                out += ".fi\n.RE\n";
+            }
+        }
+        out
+    }
+}
+
+/// Escape text for roff. Markdown code quotes are dropped.
+fn roff(text: &str) -> String {
+    text.lines()
+        .map(|line| {
+            let line = line
+                .replace('\\', "\\e")
+                .replace('-', "\\-")
+                .replace('`', "");
+            if line.starts_with('.') || line.starts_with('\'') {
+                format!("\\&{}", line)
+            } else {
+                line
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn sorted_children(def: &KeywordDefinition) -> Vec<(&str, &KeywordDefinition)> {
+    let mut children = def
+        .children
+        .iter()
+        .map(|(name, def)| (name.as_str(), def))
+        .collect::<Vec<(&str, &KeywordDefinition)>>();
+    children.sort_by_key(|(name, _)| *name);
+    children
+}
+
+/// How the keyword is written, e.g. `route [options] <name> <match> [gateway]`
+pub fn signature(name: &str, def: &KeywordDefinition) -> String {
+    let mut signature = name.to_string();
+    if !def.options.is_empty() {
+        signature += " [options]";
+    }
+    let count = def
+        .max_arguments
+        .unwrap_or(def.min_arguments)
+        .max(def.argument_names.len());
+    for i in 0..count {
+        let arg = def
+            .argument_names
+            .get(i)
+            .cloned()
+            .unwrap_or_else(|| format!("arg{}", i + 1));
+        signature += &if i < def.min_arguments {
+            format!(" <{}>", arg)
+        } else {
+            format!(" [{}]", arg)
+        };
+    }
+    if def.max_arguments.is_none() {
+        signature += " ...";
+    }
+    if !def.children.is_empty() {
+        signature += " { ... }";
+    }
+    signature
+}
+
+/// How the option is written, e.g. `--mtu=<integer>`
+pub fn option_signature(opt: &OptionDefinition) -> String {
+    match &opt.value_type {
+        ValueType::Boolean => format!("--{} | --no-{}", opt.name, opt.name),
+        ValueType::Enum(values) => format!("--{}={}", opt.name, values.join("|")),
+        value_type => format!("--{}=<{}>", opt.name, value_type),
+    }
+}
+
+/// The description of an option followed by whether it is required,
+/// repeatable and its default
+fn option_text(opt: &OptionDefinition) -> String {
+    let mut notes = vec![];
+    if opt.required {
+        notes.push("required".to_string());
+    }
+    if opt.repeatable {
+        notes.push("may be repeated".to_string());
+    }
+    if let Some(default) = &opt.default {
+        notes.push(format!("default {}", default));
+    }
+    let mut text = opt.description.clone().unwrap_or_default();
+    if !notes.is_empty() {
+        if !text.is_empty() {
+            text.push(' ');
+        }
+        text += &format!("({})", notes.join(", "));
+    }
+    text
+}
+
+/// Markdown documentation of one option
+pub fn option_markdown(opt: &OptionDefinition) -> String {
+    let text = option_text(opt);
+    if text.is_empty() {
+        format!("`{}`", option_signature(opt))
+    } else {
+        format!("`{}` {}", option_signature(opt), text)
+    }
+}
+
+/// Markdown documentation of one keyword
+pub fn keyword_markdown(name: &str, def: &KeywordDefinition) -> String {
+    let mut out = format!("```\n{}\n```\n", signature(name, def));
+    if let Some(description) = &def.description {
+        out += &format!("\n{}\n", description);
+    }
+    if !def.aliases.is_empty() {
+        let aliases = def
+            .aliases
+            .iter()
+            .map(|a| format!("`{}`", a))
+            .collect::<Vec<String>>();
+        out += &format!("\nAlso known as {}.\n", aliases.join(", "));
+    }
+    if !def.options.is_empty() {
+        out += "\nOptions:\n\n";
+        for opt in &def.options {
+            out += &format!("* {}\n", option_markdown(opt));
+        }
+    }
+    if !def.children.is_empty() {
+        out += "\nBlock keywords:\n\n";
+        for (child, child_def) in sorted_children(def) {
+            out += &format!("* `{}`", signature(child, child_def));
+            if let Some(description) = &child_def.description {
+                out += &format!(" {}", description);
+            }
+            out.push('\n');
+        }
+    }
+    if !def.examples.is_empty() {
+        out += &format!("\nExample:\n\n```\n{}\n```\n", def.examples.join("\n"));
+    }
+    out
+}
+
+/// Plain text help of one keyword for the terminal
+pub fn keyword_help(name: &str, def: &KeywordDefinition) -> String {
+    let mut out = format!("{}\n", signature(name, def));
+    if let Some(description) = &def.description {
+        out += &format!("\n{}\n", description);
+    }
+    if !def.aliases.is_empty() {
+        out += &format!("\nAlso known as {}.\n", def.aliases.join(", "));
+    }
+    if !def.options.is_empty() {
+        out += "\nOptions:\n";
+        for opt in &def.options {
+            out += &format!("  {}\n", option_signature(opt));
+            let text = option_text(opt);
+            if !text.is_empty() {
+                out += &format!("      {}\n", text);
+            }
+        }
+    }
+    if !def.children.is_empty() {
+        out += "\nBlock keywords:\n";
+        for (child, child_def) in sorted_children(def) {
+            out += &format!("  {}\n", signature(child, child_def));
+            if let Some(description) = &child_def.description {
+                out += &format!("      {}\n", description);
+            }
+        }
+    }
+    if !def.examples.is_empty() {
+        out += "\nExample:\n";
+        for example in &def.examples {
+            out += &format!("  {}\n", example);
+        }
+    }
+    out
+}
+
+/// Help of the keyword `name` for `--help-keyword`
+pub fn help_for(definitions: &[(String, KeywordDefinition)], name: &str) -> Result<String> {
+    match definitions.iter().find(|(n, _)| n == name) {
+        Some((name, def)) => Ok(keyword_help(name, def)),
+        None => {
+            let mut valid = definitions
+                .iter()
+                .map(|(n, _)| n.clone())
+                .collect::<Vec<String>>();
+            valid.sort();
+            Err(anyhow!(ParserError::UnknownKeyword {
+                keyword: name.into(),
+                valid,
+                span: Span::default(),
+            }))
+        }
+    }
+}
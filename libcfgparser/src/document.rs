@@ -0,0 +1,265 @@
+use crate::{
+    format::{normalize_newlines, render_keyword},
+    invalid_line_error, parse_value, with_errors, Config, Keyword, ParserError, Rule,
+    SysConfigParser,
+};
+use anyhow::Result;
+use pest::Parser;
+use std::fmt::{Display, Formatter};
+
+/// A node of the concrete syntax tree of a configuration file
+#[derive(Debug, Clone)]
+pub enum Node {
+    /// Blank lines, comments and indentation between statements
+    Trivia(String),
+    /// A keyword. `text` is the keyword as written in the file and is used
+    /// until the keyword is modified. `comment` is a comment at the end of
+    /// the line which is kept when the keyword is rewritten.
+    Keyword {
+        text: String,
+        keyword: Keyword,
+        comment: Option<String>,
+        modified: bool,
+    },
+    If(String),
+    Else(String),
+    EndIf(String),
+}
+
+/// Lossless representation of a configuration file. Printing a document
+/// which has not been changed gives back the exact input, including
+/// comments and layout. Keywords are not checked against any definition
+/// and variables are kept as written.
+#[derive(Debug, Clone, Default)]
+pub struct Document {
+    nodes: Vec<Node>,
+}
+
+impl Document {
+    pub fn parse(content: &str) -> Result<Self> {
+        let config = SysConfigParser::parse(Rule::config, content)
+            .map_err(|e| ParserError::from_pest(e, content, None))?
+            .next()
+            .unwrap();
+
+        let mut nodes = vec![];
+        let mut errors = vec![];
+        let mut pos = 0;
+        for pair in config.into_inner() {
+            let (start, end) = (pair.as_span().start(), pair.as_span().end());
+            if start > pos {
+                nodes.push(Node::Trivia(content[pos..start].into()));
+            }
+            let text = pair.as_str().to_string();
+            match pair.as_rule() {
+                Rule::command => {
+                    let inner_end = pair
+                        .clone()
+                        .into_inner()
+                        .last()
+                        .map(|p| p.as_span().end())
+                        .unwrap_or(start);
+                    let comment = Some(content[inner_end..end].trim())
+                        .filter(|c| c.starts_with('#'))
+                        .map(String::from);
+                    if let Config::Instruction(keyword) = parse_value(pair, None, None)? {
+                        nodes.push(Node::Keyword {
+                            text,
+                            keyword,
+                            comment,
+                            modified: false,
+                        });
+                    }
+                }
+                Rule::invalid => errors.push(invalid_line_error(&pair, None)),
+                Rule::if_statement => nodes.push(Node::If(text)),
+                Rule::else_statement => nodes.push(Node::Else(text)),
+                Rule::endif_statement => nodes.push(Node::EndIf(text)),
+                _ => {}
+            }
+            pos = end;
+        }
+        if pos < content.len() {
+            nodes.push(Node::Trivia(content[pos..].into()));
+        }
+
+        with_errors(Document { nodes }, errors)
+    }
+
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    /// All keywords of the document in order, including `set` and `include`
+    pub fn keywords(&self) -> impl Iterator<Item = &Keyword> {
+        self.nodes.iter().filter_map(|n| match n {
+            Node::Keyword { keyword, .. } => Some(keyword),
+            _ => None,
+        })
+    }
+
+    /// Index into `nodes` of the keyword with the given index
+    fn node_index(&self, index: usize) -> Option<usize> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, n)| matches!(n, Node::Keyword { .. }))
+            .nth(index)
+            .map(|(i, _)| i)
+    }
+
+    /// Change a keyword. The keyword is written in canonical form afterwards.
+    pub fn keyword_mut(&mut self, index: usize) -> Option<&mut Keyword> {
+        let i = self.node_index(index)?;
+        match &mut self.nodes[i] {
+            Node::Keyword {
+                keyword, modified, ..
+            } => {
+                *modified = true;
+                Some(keyword)
+            }
+            _ => None,
+        }
+    }
+
+    /// Append a keyword to the end of the document
+    pub fn add_keyword(&mut self, keyword: Keyword) {
+        if !self.to_string().ends_with('\n') && !self.nodes.is_empty() {
+            self.nodes.push(Node::Trivia("\n".into()));
+        }
+        self.nodes.push(Node::Keyword {
+            text: String::new(),
+            keyword,
+            comment: None,
+            modified: true,
+        });
+    }
+
+    /// Insert a keyword in front of the keyword with the given index.
+    /// Appends the keyword if the index is past the last keyword.
+    pub fn insert_keyword(&mut self, index: usize, keyword: Keyword) {
+        if let Some(i) = self.node_index(index) {
+            self.nodes.insert(
+                i,
+                Node::Keyword {
+                    text: String::new(),
+                    keyword,
+                    comment: None,
+                    modified: true,
+                },
+            );
+        } else {
+            self.add_keyword(keyword);
+        }
+    }
+
+    /// Remove the keyword with the given index together with its line
+    pub fn remove_keyword(&mut self, index: usize) -> Option<Keyword> {
+        let i = self.node_index(index)?;
+        // drop the indentation of the removed line as well
+        if let Some(Node::Trivia(text)) = i.checked_sub(1).and_then(|p| self.nodes.get_mut(p)) {
+            let trimmed = text.trim_end_matches([' ', '\t']);
+            if trimmed.is_empty() || trimmed.ends_with(['\n', '\r']) {
+                text.truncate(trimmed.len());
+            }
+        }
+        match self.nodes.remove(i) {
+            Node::Keyword { keyword, .. } => Some(keyword),
+            _ => None,
+        }
+    }
+
+    /// Render the document in canonical form: keywords are rewritten by
+    /// their canonical printer, blocks are indented by two spaces per level,
+    /// comments are kept and runs of blank lines are collapsed. Keyword
+    /// blocks with comments inside are kept as written.
+    pub fn formatted(&self) -> String {
+        let mut out = String::new();
+        let mut depth: usize = 0;
+        let indent = |depth: usize| "  ".repeat(depth);
+        for node in &self.nodes {
+            match node {
+                Node::Trivia(text) => {
+                    for line in normalize_newlines(text).split_inclusive('\n') {
+                        // an unterminated piece is the indentation of the
+                        // following statement or a comment at the end
+                        let terminated = line.ends_with('\n');
+                        let line = line.trim_start_matches('\u{feff}').trim();
+                        if line.is_empty() {
+                            if terminated && !out.is_empty() && !out.ends_with("\n\n") {
+                                out.push('\n');
+                            }
+                        } else {
+                            out += &format!("{}{}\n", indent(depth), line);
+                        }
+                    }
+                }
+                Node::Keyword {
+                    text,
+                    keyword,
+                    comment,
+                    modified,
+                } => {
+                    // Comments inside a block are not part of the keyword.
+                    // Such blocks are kept as written to not lose them.
+                    let block = &text[..text.rfind('}').unwrap_or_default()];
+                    if !modified && !keyword.children.is_empty() && block.contains('#') {
+                        let text = normalize_newlines(text.trim());
+                        out += &format!("{}{}\n", indent(depth), text);
+                        continue;
+                    }
+                    out += &format!("{}{}", indent(depth), render_keyword(keyword, depth, false));
+                    if let Some(comment) = comment {
+                        out += &format!(" {}", comment);
+                    }
+                    out.push('\n');
+                }
+                Node::If(text) => {
+                    out += &format!("{}{}\n", indent(depth), text.trim());
+                    depth += 1;
+                }
+                Node::Else(text) => {
+                    out += &format!("{}{}\n", indent(depth.saturating_sub(1)), text.trim());
+                }
+                Node::EndIf(text) => {
+                    depth = depth.saturating_sub(1);
+                    out += &format!("{}{}\n", indent(depth), text.trim());
+                }
+            }
+        }
+
+        while out.ends_with("\n\n") {
+            out.pop();
+        }
+        out
+    }
+}
+
+impl Display for Document {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for node in &self.nodes {
+            match node {
+                Node::Trivia(text) | Node::If(text) | Node::Else(text) | Node::EndIf(text) => {
+                    write!(f, "{}", text)?
+                }
+                Node::Keyword {
+                    text,
+                    keyword,
+                    comment,
+                    modified,
+                } => {
+                    if *modified {
+                        write!(f, "{}", keyword)?;
+                        if let Some(comment) = comment {
+                            write!(f, " {}", comment)?;
+                        }
+                        writeln!(f)?
+                    } else {
+                        write!(f, "{}", text)?
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,18 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+
+/// Source of facts about the machine like hostname, architecture or MAC
+/// addresses. `if` conditions in the configuration are evaluated against
+/// the facts of the provider registered on the parser.
+pub trait FactProvider: Debug {
+    /// All values of the fact `name`. A machine can have more than one
+    /// value for a fact, e.g. one MAC address per link.
+    /// None if the fact is not known to the provider.
+    fn fact(&self, name: &str) -> Option<Vec<String>>;
+}
+
+impl FactProvider for HashMap<String, Vec<String>> {
+    fn fact(&self, name: &str) -> Option<Vec<String>> {
+        self.get(name).cloned()
+    }
+}
@@ -0,0 +1,132 @@
+use crate::{Keyword, Rule, SysConfigParser, Value};
+use pest::Parser;
+use std::fmt::{Display, Formatter};
+
+/// Render keywords in the canonical form of the native format,
+/// one keyword per line
+pub fn format_keywords(keywords: &[Keyword]) -> String {
+    keywords.iter().map(|k| format!("{}\n", k)).collect()
+}
+
+/// Check that `input` matches `rule` completely
+fn matches_rule(rule: Rule, input: &str) -> bool {
+    SysConfigParser::parse(rule, input)
+        .ok()
+        .and_then(|mut pairs| pairs.next())
+        .is_some_and(|pair| pair.as_str().len() == input.len())
+}
+
+/// Turn CRLF and CR line endings into LF
+pub(crate) fn normalize_newlines(text: &str) -> String {
+    text.replace("\r\n", "\n").replace('\r', "\n")
+}
+
+/// Resolve the escape sequences of a quoted string
+pub(crate) fn unescape(raw: &str) -> String {
+    let mut out = String::new();
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('b') => out.push('\u{8}'),
+            Some('f') => out.push('\u{c}'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('t') => out.push('\t'),
+            Some('u') => {
+                let hex = chars.by_ref().take(4).collect::<String>();
+                out.push(
+                    u32::from_str_radix(&hex, 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                        .unwrap_or(char::REPLACEMENT_CHARACTER),
+                );
+            }
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// Quote a value so it reads back as the same value. Values are written
+/// without quotes where the grammar allows it. A multi-line value which
+/// ends the keyword is written as heredoc. A `${` of a `literal` value is
+/// escaped as `$${` so it does not read back as variable reference.
+fn quote(value: &str, last: bool, literal: bool) -> String {
+    // Heredocs are taken literally
+    if last && value.contains('\n') && value.ends_with('\n') {
+        let mut tag = String::from("EOF");
+        let mut counter = 0;
+        while value.lines().any(|l| l == tag) {
+            counter += 1;
+            tag = format!("EOF{}", counter);
+        }
+        return format!("<<{}\n{}{}", tag, value, tag);
+    }
+
+    let value = if literal {
+        value.replace("${", "$${")
+    } else {
+        value.to_string()
+    };
+    // quoteless_string on its own allows whitespace around variables,
+    // inside a keyword it ends the value
+    if !value.starts_with("--")
+        && !value.contains(char::is_whitespace)
+        && matches_rule(Rule::quoteless_string, &value)
+    {
+        return value;
+    }
+
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Render a keyword in canonical form. Children of a block are written
+/// one per line, indented by two spaces more than the keyword at `depth`.
+/// Values are `literal` if variables have been replaced already, else
+/// references in them are written as they are.
+pub(crate) fn render_keyword(keyword: &Keyword, depth: usize, literal: bool) -> String {
+    let count = keyword.options.len() + keyword.arguments.len();
+    // A heredoc can not be followed by the opening brace of a block
+    let block = !keyword.children.is_empty();
+    let mut tokens = vec![keyword.name.clone()];
+    for opt in &keyword.options {
+        let last = tokens.len() == count && !block;
+        tokens.push(match &opt.value {
+            Value::Boolean(true) => format!("--{}", opt.name),
+            Value::Boolean(false) => format!("--no-{}", opt.name),
+            value => format!(
+                "--{}={}",
+                opt.name,
+                quote(&value.to_string(), last, literal)
+            ),
+        });
+    }
+    for arg in &keyword.arguments {
+        let last = tokens.len() == count && !block;
+        tokens.push(quote(&arg.value, last, literal));
+    }
+    let mut out = tokens.join(" ");
+    if block {
+        out += " {\n";
+        for child in &keyword.children {
+            out += &format!(
+                "{}{}\n",
+                "  ".repeat(depth + 1),
+                render_keyword(child, depth + 1, literal)
+            );
+        }
+        out += &format!("{}}}", "  ".repeat(depth));
+    }
+    out
+}
+
+impl Display for Keyword {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", render_keyword(self, 0, true))
+    }
+}
@@ -0,0 +1,36 @@
+use crate::{ParserError, Span};
+use anyhow::{anyhow, Result};
+use std::path::{Path, PathBuf};
+
+/// Resolve the path given to an `include` to the files it names.
+/// Relative paths are resolved against the directory of the including
+/// file. Patterns containing `*`, `?` or `[` are expanded in sorted order
+/// and may match no file at all.
+pub(crate) fn resolve(
+    pattern: &str,
+    including: Option<&Path>,
+    span: &Span,
+) -> Result<Vec<PathBuf>> {
+    let path = match including.and_then(|p| p.parent()) {
+        Some(dir) if Path::new(pattern).is_relative() => dir.join(pattern),
+        _ => PathBuf::from(pattern),
+    };
+
+    if !pattern.contains(['*', '?', '[']) {
+        return Ok(vec![path]);
+    }
+
+    let include_error = |message: String| {
+        anyhow!(ParserError::Include {
+            path: path.clone(),
+            message,
+            span: span.clone(),
+        })
+    };
+    let mut files = glob::glob(&path.to_string_lossy())
+        .map_err(|e| include_error(e.to_string()))?
+        .collect::<std::result::Result<Vec<PathBuf>, glob::GlobError>>()
+        .map_err(|e| include_error(e.to_string()))?;
+    files.sort();
+    Ok(files)
+}
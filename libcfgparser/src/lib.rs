@@ -1,39 +1,305 @@
 extern crate pest;
 #[macro_use]
 extern crate pest_derive;
+// The code generated by #[derive(Keyword)] refers to ::libcfgparser
+extern crate self as libcfgparser;
 
-use anyhow::Result;
+mod convert;
+mod diagnostic;
+pub mod docs;
+mod document;
+mod facts;
+mod format;
+mod include;
+mod loader;
+mod schema;
+mod validate;
+mod variables;
+pub mod zfs;
+
+use anyhow::{anyhow, Result};
+pub use convert::{derive_support, FromKeyword, FromValue};
+pub use diagnostic::{Diagnostic, Span};
+pub use document::{Document, Node};
+pub use facts::FactProvider;
+pub use format::format_keywords;
+pub use libcfgparser_derive::Keyword;
+pub use loader::{to_json_value, Format, Loader};
+use pest::iterators::Pair;
 use pest::Parser;
+pub use schema::{KeywordDefinition, OptionDefinition, Value, ValueType};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+pub use validate::{from_json_value, json_schema, validate_json, SchemaErrors, SchemaViolation};
+pub use variables::parse_variable_definition;
 
 enum Config {
-    Instruction(String, Option<Vec<(String, String)>>, Vec<String>),
+    Instruction(Keyword),
+    Block(Vec<Keyword>),
     Command(String),
-    Option(String),
-    Argument(String, String),
+    Option(Argument),
+    Argument(KeywordOption),
     Value(String),
     EOI,
 }
 
-#[derive(Default, Debug, Deserialize, Serialize, Eq, PartialEq)]
+#[derive(Default, Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
 pub struct Keyword {
     pub name: String,
-    pub options: Option<HashMap<String, String>>,
-    pub arguments: Vec<String>,
+    /// Options in the order they were given. An option may appear
+    /// more than once.
+    pub options: Vec<KeywordOption>,
+    pub arguments: Vec<Argument>,
+    pub span: Span,
+    /// Keywords given in a `{ ... }` block after the arguments. Children
+    /// which name an option of the keyword are turned into options when
+    /// the keyword is checked against its definition.
+    #[serde(default)]
+    pub children: Vec<Keyword>,
+}
+
+/// A `--name="value"` option given to a keyword. Bare `--flag` and
+/// `--no-flag` options carry a boolean value.
+#[derive(Default, Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
+pub struct KeywordOption {
+    pub name: String,
+    pub value: Value,
+    pub span: Span,
 }
 
-#[derive(Default, Debug, Deserialize, Serialize)]
-pub struct KeywordDefinition {
-    pub options: Vec<String>,
+/// A positional argument given to a keyword
+#[derive(Default, Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
+pub struct Argument {
+    pub value: String,
+    pub span: Span,
+}
+
+impl Keyword {
+    /// The first occurrence of the option `name`
+    pub fn option(&self, name: &str) -> Option<&KeywordOption> {
+        self.options.iter().find(|o| o.name == name)
+    }
+
+    /// The values of every occurrence of the option `name` in order
+    pub fn values(&self, name: &str) -> Vec<&Value> {
+        self.options
+            .iter()
+            .filter(|o| o.name == name)
+            .map(|o| &o.value)
+            .collect()
+    }
+
+    /// The boolean value of an option. Set by `--flag`, `--no-flag` or
+    /// an explicit value for options declared as [`ValueType::Boolean`]
+    pub fn flag(&self, name: &str) -> Option<bool> {
+        self.option(name).and_then(|o| o.value.as_bool())
+    }
+
+    /// The child keywords with the given name in order
+    pub fn children(&self, name: &str) -> Vec<&Keyword> {
+        self.children.iter().filter(|c| c.name == name).collect()
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ParserError {
+    #[error("{message}")]
+    Syntax { message: String, span: Span },
+    #[error("keyword {keyword} is not known, valid keywords are: {}", .valid.join(", "))]
+    UnknownKeyword {
+        keyword: String,
+        valid: Vec<String>,
+        span: Span,
+    },
+    #[error("option {option} is not known for keyword {keyword}, valid options are: {}", .valid.join(", "))]
+    UnknownOption {
+        keyword: String,
+        option: String,
+        valid: Vec<String>,
+        span: Span,
+    },
+    #[error("keyword {keyword} expects {expected} arguments but {found} were given")]
+    ArgumentCount {
+        keyword: String,
+        expected: String,
+        found: usize,
+        span: Span,
+    },
+    #[error("value {value} of option {option} for keyword {keyword} is not a valid {expected}")]
+    InvalidOptionValue {
+        keyword: String,
+        option: String,
+        value: String,
+        expected: ValueType,
+        span: Span,
+    },
+    #[error("option {option} of keyword {keyword} needs a value")]
+    MissingOptionValue {
+        keyword: String,
+        option: String,
+        span: Span,
+    },
+    #[error("option {option} of keyword {keyword} can only be given once")]
+    DuplicateOption {
+        keyword: String,
+        option: String,
+        span: Span,
+    },
+    #[error("keyword {keyword} requires option {option}")]
+    MissingOption {
+        keyword: String,
+        option: String,
+        span: Span,
+    },
+    #[error("keyword {keyword} needs a {option} either as argument or as option")]
+    MissingValue {
+        keyword: String,
+        option: String,
+        span: Span,
+    },
+    #[error("variable {name} is not defined")]
+    UndefinedVariable { name: String, span: Span },
+    #[error("{reference} is not a valid variable reference")]
+    InvalidVariable { reference: String, span: Span },
+    #[error("set expects a variable name and a value")]
+    InvalidSet { span: Span },
+    #[error("fact {name} is not known")]
+    UnknownFact { name: String, span: Span },
+    #[error("{directive} without matching if")]
+    UnmatchedDirective { directive: String, span: Span },
+    #[error("if without matching endif")]
+    UnterminatedIf { span: Span },
+    #[error("include expects exactly one path")]
+    InvalidInclude { span: Span },
+    #[error("could not include {}: {message}", .path.display())]
+    Include {
+        path: PathBuf,
+        message: String,
+        span: Span,
+    },
+    #[error("include cycle detected: {}", .chain.iter().map(|p| p.display().to_string()).collect::<Vec<String>>().join(" -> "))]
+    IncludeCycle { chain: Vec<PathBuf>, span: Span },
+}
+
+impl ParserError {
+    pub fn span(&self) -> &Span {
+        match self {
+            ParserError::Syntax { span, .. } => span,
+            ParserError::UnknownKeyword { span, .. } => span,
+            ParserError::UnknownOption { span, .. } => span,
+            ParserError::ArgumentCount { span, .. } => span,
+            ParserError::InvalidOptionValue { span, .. } => span,
+            ParserError::MissingOptionValue { span, .. } => span,
+            ParserError::DuplicateOption { span, .. } => span,
+            ParserError::MissingOption { span, .. } => span,
+            ParserError::MissingValue { span, .. } => span,
+            ParserError::UndefinedVariable { span, .. } => span,
+            ParserError::InvalidVariable { span, .. } => span,
+            ParserError::InvalidSet { span } => span,
+            ParserError::UnknownFact { span, .. } => span,
+            ParserError::UnmatchedDirective { span, .. } => span,
+            ParserError::UnterminatedIf { span } => span,
+            ParserError::InvalidInclude { span } => span,
+            ParserError::Include { span, .. } => span,
+            ParserError::IncludeCycle { span, .. } => span,
+        }
+    }
+
+    fn from_pest(err: pest::error::Error<Rule>, input: &str, file: Option<&PathBuf>) -> Self {
+        use pest::error::InputLocation;
+
+        let (start, end) = match err.location {
+            InputLocation::Pos(p) => (p, p),
+            InputLocation::Span((s, e)) => (s, e),
+        };
+        let (line, column) = diagnostic::line_col(input, start);
+        ParserError::Syntax {
+            message: err.variant.message().to_string(),
+            span: Span {
+                file: file.cloned(),
+                line,
+                column,
+                start,
+                end,
+            },
+        }
+    }
+}
+
+/// All errors found in a configuration. Returned by the parser if more
+/// than one error was found, a single error is returned as is.
+#[derive(Error, Debug)]
+pub struct ParserErrors {
+    pub errors: Vec<ParserError>,
+}
+
+impl Display for ParserErrors {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} errors found", self.errors.len())?;
+        for err in &self.errors {
+            write!(f, "\n{}: {}", err.span(), err)?;
+        }
+        Ok(())
+    }
+}
+
+/// Find the location information in an error returned by the parser
+/// or by one of the keyword layers so it can be rendered for the user
+pub fn find_diagnostic(err: &anyhow::Error) -> Option<Diagnostic> {
+    for cause in err.chain() {
+        if let Some(diag) = cause.downcast_ref::<Diagnostic>() {
+            return Some(diag.clone());
+        }
+        if let Some(parser_err) = cause.downcast_ref::<ParserError>() {
+            return Some(Diagnostic::new(parser_err.span(), parser_err));
+        }
+        if let Some(parser_err) = cause
+            .downcast_ref::<ParserErrors>()
+            .and_then(|errs| errs.errors.first())
+        {
+            return Some(Diagnostic::new(parser_err.span(), parser_err));
+        }
+    }
+
+    None
+}
+
+/// Like [`find_diagnostic`] but returns every error if the parser found
+/// more than one
+pub fn find_diagnostics(err: &anyhow::Error) -> Vec<Diagnostic> {
+    for cause in err.chain() {
+        if let Some(parser_errs) = cause.downcast_ref::<ParserErrors>() {
+            return parser_errs
+                .errors
+                .iter()
+                .map(|e| Diagnostic::new(e.span(), e))
+                .collect();
+        }
+    }
+
+    find_diagnostic(err).into_iter().collect()
 }
 
 #[derive(Parser, Default, Debug)]
 #[grammar = "config.pest"]
 pub struct SysConfigParser {
     keywords: HashMap<String, KeywordDefinition>,
+    variables: HashMap<String, String>,
+    facts: Option<Box<dyn FactProvider>>,
+}
+
+/// State of an if block while parsing
+struct Conditional {
+    /// Whether the keywords in the current branch are used
+    active: bool,
+    /// Whether the condition of the if matched
+    taken: bool,
+    else_seen: bool,
+    span: Span,
 }
 
 impl SysConfigParser {
@@ -41,113 +307,475 @@ impl SysConfigParser {
         self.keywords.insert(name, k)
     }
 
+    /// Define a variable which can be referenced as `${NAME}` in the
+    /// configuration. Variables defined here take precedence over
+    /// `set NAME value` in the file so callers can override them.
+    pub fn set_variable(&mut self, name: &str, value: &str) -> Option<String> {
+        self.variables.insert(name.into(), value.into())
+    }
+
+    /// Set the provider `if` conditions in the configuration are evaluated
+    /// against. Without a provider every fact is unknown.
+    pub fn set_fact_provider(&mut self, provider: Box<dyn FactProvider>) {
+        self.facts = Some(provider);
+    }
+
+    /// Evaluate the condition of an if statement. An unquoted left hand side
+    /// is the name of a fact, which matches if any of its values matches.
+    /// Values are compared ignoring ASCII case.
+    fn evaluate_condition(
+        &self,
+        pair: Pair<Rule>,
+        path: Option<&PathBuf>,
+        vars: &HashMap<String, String>,
+    ) -> Result<bool> {
+        fn operand_value(
+            pair: Pair<Rule>,
+            path: Option<&PathBuf>,
+            vars: &HashMap<String, String>,
+        ) -> Result<String> {
+            let span = Span::from_pest(pair.as_span(), path);
+            let raw = match pair.as_rule() {
+                Rule::string => format::unescape(pair.into_inner().next().unwrap().as_str()),
+                _ => pair.as_str().to_string(),
+            };
+            variables::interpolate(&raw, Some(vars), &span)
+        }
+
+        let mut inner = pair.into_inner().skip(1);
+        let lhs = inner.next().unwrap().into_inner().next().unwrap();
+        let equal = inner.next().unwrap().as_str() == "==";
+        let rhs = inner.next().unwrap().into_inner().next().unwrap();
+        let expected = operand_value(rhs, path, vars)?;
+
+        let values = if lhs.as_rule() == Rule::quoteless_string && !lhs.as_str().contains("${") {
+            self.facts
+                .as_ref()
+                .and_then(|f| f.fact(lhs.as_str()))
+                .ok_or_else(|| {
+                    anyhow!(ParserError::UnknownFact {
+                        name: lhs.as_str().into(),
+                        span: Span::from_pest(lhs.as_span(), path),
+                    })
+                })?
+        } else {
+            vec![operand_value(lhs, path, vars)?]
+        };
+        let matched = values.iter().any(|v| v.eq_ignore_ascii_case(&expected));
+
+        Ok(matched == equal)
+    }
+
+    /// Check a parsed keyword against the registered definitions.
+    /// If no keywords have been registered every keyword is accepted
+    /// and all option values are kept as strings.
+    pub fn check_keyword(&self, keyword: &mut Keyword) -> Result<()> {
+        if self.keywords.is_empty() {
+            return Ok(());
+        }
+
+        if let Some(def) = self.keywords.get(&keyword.name) {
+            def.validate(keyword)
+        } else {
+            let mut valid = self.keywords.keys().cloned().collect::<Vec<String>>();
+            valid.sort();
+            let mut span = keyword.span.clone();
+            span.end = span.start + keyword.name.len();
+            Err(anyhow!(ParserError::UnknownKeyword {
+                keyword: keyword.name.clone(),
+                valid,
+                span,
+            }))
+        }
+    }
+
     pub fn parse_config_file<P: AsRef<Path>>(&self, file: P) -> Result<Vec<Keyword>> {
-        let content = fs::read_to_string(file)?;
-        self.parse_config(&content)
+        let content = fs::read_to_string(file.as_ref())?;
+        self.parse_source(&content, Some(file.as_ref().to_path_buf()))
     }
 
     pub fn parse_config(&self, file: &str) -> Result<Vec<Keyword>> {
-        let config = SysConfigParser::parse(Rule::config, file)?.next().unwrap();
+        self.parse_source(file, None)
+    }
+
+    /// Parse configuration which has not been saved to `path` yet, like
+    /// the buffer of an editor. Includes are resolved relative to `path`.
+    pub fn parse_config_with_path<P: AsRef<Path>>(
+        &self,
+        content: &str,
+        path: P,
+    ) -> Result<Vec<Keyword>> {
+        self.parse_source(content, Some(path.as_ref().to_path_buf()))
+    }
+
+    fn parse_source(&self, content: &str, path: Option<PathBuf>) -> Result<Vec<Keyword>> {
+        let mut vars = self.variables.clone();
+        let mut chain = vec![];
+        if let Some(p) = &path {
+            chain.push(fs::canonicalize(p).unwrap_or_else(|_| p.clone()));
+        }
+        let mut errors = vec![];
+        let keywords = self.parse_content(content, path, &mut vars, &mut chain, &mut errors)?;
+        with_errors(keywords, errors)
+    }
+
+    /// Parse the content of one file. `vars` are shared with the files it
+    /// includes and `chain` holds the files which are currently being
+    /// included to detect cycles. Errors in the configuration are added to
+    /// `errors` and parsing continues with the next statement.
+    fn parse_content(
+        &self,
+        content: &str,
+        path: Option<PathBuf>,
+        vars: &mut HashMap<String, String>,
+        chain: &mut Vec<PathBuf>,
+        errors: &mut Vec<ParserError>,
+    ) -> Result<Vec<Keyword>> {
+        let config = match SysConfigParser::parse(Rule::config, content) {
+            Ok(mut pairs) => pairs.next(),
+            Err(e) => {
+                errors.push(ParserError::from_pest(e, content, path.as_ref()));
+                None
+            }
+        };
+        let config = if let Some(config) = config {
+            config
+        } else {
+            return Ok(vec![]);
+        };
 
-        use pest::iterators::Pair;
+        let mut keywords: Vec<Keyword> = vec![];
+        let mut conditions: Vec<Conditional> = vec![];
 
-        fn parse_value(pair: Pair<Rule>) -> Config {
+        for pair in config.into_inner() {
+            let span = Span::from_pest(pair.as_span(), path.as_ref());
+            let active = conditions.iter().all(|c| c.active);
             match pair.as_rule() {
-                Rule::command => {
-                    let mut cmd_str = String::new();
-                    let mut arguments: Option<Vec<(String, String)>> = None;
-                    let mut options: Vec<String> = vec![];
-                    for pair in pair.into_inner() {
-                        match parse_value(pair) {
-                            Config::Command(s) => cmd_str = s,
-                            Config::Option(opt) => options.push(opt),
-                            Config::Argument(name, val) => {
-                                arguments = if let Some(mut args) = arguments.clone() {
-                                    args.push((name, val));
-                                    Some(args)
-                                } else {
-                                    Some(vec![(name, val)])
-                                };
-                            }
-                            _ => panic!(),
-                        }
-                    }
-                    Config::Instruction(cmd_str, arguments, options)
+                Rule::invalid => {
+                    errors.push(invalid_line_error(&pair, path.as_ref()));
+                    continue;
                 }
-                Rule::command_word => Config::Command(pair.as_str().into()),
-                Rule::command_argument => {
-                    let mut arg_name = String::new();
-                    let mut arg_value = String::new();
-                    for p in pair.into_inner() {
-                        match parse_value(p) {
-                            Config::Command(cmd) => arg_name = cmd,
-                            Config::Value(val) => arg_value = val,
-                            _ => panic!(),
-                        }
-                    }
-                    Config::Argument(arg_name, arg_value)
+                Rule::if_statement => {
+                    // A condition which can not be evaluated counts as not taken
+                    let taken = active
+                        && recover(self.evaluate_condition(pair, path.as_ref(), vars), errors)?
+                            .unwrap_or(false);
+                    conditions.push(Conditional {
+                        active: taken,
+                        taken,
+                        else_seen: false,
+                        span,
+                    });
+                    continue;
                 }
-                Rule::string => Config::Value(pair.into_inner().next().unwrap().as_str().into()),
-                Rule::command_option => {
-                    let inner_pair = pair.into_inner().next().unwrap();
-                    match inner_pair.as_rule() {
-                        Rule::quoteless_string => Config::Option(inner_pair.as_str().into()),
-                        Rule::string => {
-                            Config::Option(inner_pair.into_inner().next().unwrap().as_str().into())
+                Rule::else_statement => {
+                    match conditions.last_mut() {
+                        Some(cond) if !cond.else_seen => {
+                            cond.else_seen = true;
+                            cond.active = !cond.taken;
                         }
-                        _ => panic!(),
+                        _ => errors.push(ParserError::UnmatchedDirective {
+                            directive: "else".into(),
+                            span,
+                        }),
                     }
+                    continue;
                 }
-                Rule::config => {
-                    let inner_pair = pair.into_inner().next().unwrap();
-                    let config = parse_value(inner_pair);
-                    config
+                Rule::endif_statement => {
+                    if conditions.pop().is_none() {
+                        errors.push(ParserError::UnmatchedDirective {
+                            directive: "endif".into(),
+                            span,
+                        });
+                    }
+                    continue;
                 }
-                Rule::quoteless_string
-                | Rule::inner
-                | Rule::char
-                | Rule::COMMENT
-                | Rule::WHITESPACE => panic!(),
-                Rule::EOI => Config::EOI,
+                // Keywords in branches which are not taken are skipped
+                // before variables in them are resolved
+                Rule::command if !active => continue,
+                _ => {}
             }
-        }
 
-        let mut keywords: Vec<Keyword> = vec![];
-
-        for pair in config.into_inner() {
-            match parse_value(pair) {
-                Config::Instruction(name, opts, args) => {
-                    let opts = if let Some(o) = opts {
-                        let mut m = HashMap::<String, String>::new();
-                        for (key, value) in o {
-                            if !m.contains_key(&key) {
-                                m.insert(key, value);
+            let config = match recover(parse_value(pair, path.as_ref(), Some(vars)), errors)? {
+                Some(config) => config,
+                None => continue,
+            };
+            match config {
+                // set NAME value defines a variable for the following lines
+                Config::Instruction(kw) if kw.name == "set" => {
+                    let args = &kw.arguments;
+                    if args.len() != 2
+                        || !kw.options.is_empty()
+                        || !kw.children.is_empty()
+                        || !variables::is_valid_name(&args[0].value)
+                    {
+                        errors.push(ParserError::InvalidSet { span: kw.span });
+                    } else if !self.variables.contains_key(&args[0].value) {
+                        vars.insert(args[0].value.clone(), args[1].value.clone());
+                    }
+                }
+                // include "path" parses the keywords of other files in place
+                Config::Instruction(kw) if kw.name == "include" => {
+                    let span = kw.span;
+                    if kw.arguments.len() != 1 || !kw.options.is_empty() || !kw.children.is_empty()
+                    {
+                        errors.push(ParserError::InvalidInclude { span });
+                        continue;
+                    }
+                    let files = recover(
+                        include::resolve(&kw.arguments[0].value, path.as_deref(), &span),
+                        errors,
+                    )?;
+                    for file in files.unwrap_or_default() {
+                        let include_error = |e: std::io::Error| ParserError::Include {
+                            path: file.clone(),
+                            message: e.to_string(),
+                            span: span.clone(),
+                        };
+                        let canonical = match fs::canonicalize(&file) {
+                            Ok(canonical) => canonical,
+                            Err(e) => {
+                                errors.push(include_error(e));
+                                continue;
                             }
+                        };
+                        if chain.contains(&canonical) {
+                            let mut cycle = chain.clone();
+                            cycle.push(canonical);
+                            errors.push(ParserError::IncludeCycle {
+                                chain: cycle,
+                                span: span.clone(),
+                            });
+                            continue;
                         }
-                        Some(m)
-                    } else {
-                        None
-                    };
-                    // TODO check if Parser has keyword configured
-                    // Error if not
-                    keywords.push(Keyword {
-                        name,
-                        options: opts,
-                        arguments: args,
-                    })
+                        let included = match fs::read_to_string(&file) {
+                            Ok(included) => included,
+                            Err(e) => {
+                                errors.push(include_error(e));
+                                continue;
+                            }
+                        };
+                        chain.push(canonical);
+                        keywords.extend(self.parse_content(
+                            &included,
+                            Some(file),
+                            vars,
+                            chain,
+                            errors,
+                        )?);
+                        chain.pop();
+                    }
+                }
+                Config::Instruction(mut keyword) => {
+                    let checked = recover(self.check_keyword(&mut keyword), errors)?;
+                    if checked.is_some() {
+                        keywords.push(keyword)
+                    }
                 }
                 _ => {}
             }
         }
 
+        for cond in conditions {
+            errors.push(ParserError::UnterminatedIf { span: cond.span });
+        }
+
         Ok(keywords)
     }
 }
 
+/// The files an `include` of `pattern` in the file `including` refers to
+pub fn resolve_include(pattern: &str, including: &Path) -> Result<Vec<PathBuf>> {
+    include::resolve(pattern, Some(including), &Span::default())
+}
+
+/// Return `value` if no errors were found. A single error is returned as
+/// is, several errors are returned together as [`ParserErrors`].
+fn with_errors<T>(value: T, mut errors: Vec<ParserError>) -> Result<T> {
+    match errors.len() {
+        0 => Ok(value),
+        1 => Err(anyhow!(errors.remove(0))),
+        _ => Err(anyhow!(ParserErrors { errors })),
+    }
+}
+
+/// Move an error of the configuration into `errors` so parsing can go on.
+/// Other errors, like a broken keyword definition, are returned.
+fn recover<T>(result: Result<T>, errors: &mut Vec<ParserError>) -> Result<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(e) => {
+            errors.push(e.downcast::<ParserError>()?);
+            Ok(None)
+        }
+    }
+}
+
+/// Error for a pair the grammar should never produce at this place
+fn unexpected(rule: Rule, span: &Span) -> anyhow::Error {
+    anyhow!(ParserError::Syntax {
+        message: format!("unexpected {:?}", rule),
+        span: span.clone(),
+    })
+}
+
+/// The error of a line the grammar could not parse. The line is parsed
+/// again on its own to get the exact location and what was expected.
+fn invalid_line_error(pair: &Pair<Rule>, path: Option<&PathBuf>) -> ParserError {
+    let span = Span::from_pest(pair.as_span(), path);
+    match SysConfigParser::parse(Rule::statement, pair.as_str()) {
+        Err(e) => {
+            let mut err = ParserError::from_pest(e, pair.as_str(), path);
+            if let ParserError::Syntax { span: err_span, .. } = &mut err {
+                if err_span.line == 1 {
+                    err_span.column += span.column - 1;
+                }
+                err_span.line += span.line - 1;
+                err_span.start += span.start;
+                err_span.end += span.start;
+            }
+            err
+        }
+        Ok(_) => ParserError::Syntax {
+            message: "invalid statement".into(),
+            span,
+        },
+    }
+}
+
+/// Convert a pair of the grammar into the matching [`Config`]. Variables
+/// in values are resolved from `vars`, if it is None values are kept as
+/// they were written.
+fn parse_value(
+    pair: Pair<Rule>,
+    path: Option<&PathBuf>,
+    vars: Option<&HashMap<String, String>>,
+) -> Result<Config> {
+    let span = Span::from_pest(pair.as_span(), path);
+    let rule = pair.as_rule();
+    Ok(match rule {
+        Rule::command | Rule::block_command => {
+            let mut keyword = Keyword {
+                span: span.clone(),
+                ..Default::default()
+            };
+            for pair in pair.into_inner() {
+                match parse_value(pair, path, vars)? {
+                    Config::Command(s) => keyword.name = s,
+                    Config::Option(opt) => keyword.arguments.push(opt),
+                    Config::Argument(opt) => keyword.options.push(opt),
+                    Config::Block(children) => keyword.children = children,
+                    _ => return Err(unexpected(rule, &span)),
+                }
+            }
+            Config::Instruction(keyword)
+        }
+        Rule::block => {
+            let mut children = vec![];
+            for pair in pair.into_inner() {
+                match parse_value(pair, path, vars)? {
+                    Config::Instruction(child) => children.push(child),
+                    _ => return Err(unexpected(rule, &span)),
+                }
+            }
+            Config::Block(children)
+        }
+        Rule::command_word => Config::Command(pair.as_str().into()),
+        Rule::command_argument => {
+            let mut arg_name = String::new();
+            let mut arg_value: Option<String> = None;
+            for p in pair.into_inner() {
+                match p.as_rule() {
+                    Rule::quoteless_string => {
+                        arg_value = Some(variables::interpolate(p.as_str(), vars, &span)?)
+                    }
+                    _ => match parse_value(p, path, vars)? {
+                        Config::Command(cmd) => arg_name = cmd,
+                        Config::Value(val) => arg_value = Some(val),
+                        _ => return Err(unexpected(rule, &span)),
+                    },
+                }
+            }
+            let (name, value) = match arg_value {
+                Some(val) => (arg_name, Value::String(val)),
+                None => match arg_name.strip_prefix("no-") {
+                    Some(negated) => (negated.to_string(), Value::Boolean(false)),
+                    None => (arg_name, Value::Boolean(true)),
+                },
+            };
+            Config::Argument(KeywordOption { name, value, span })
+        }
+        Rule::string => {
+            let inner = pair
+                .into_inner()
+                .next()
+                .ok_or_else(|| unexpected(rule, &span))?;
+            Config::Value(variables::interpolate(
+                &format::unescape(inner.as_str()),
+                vars,
+                &span,
+            )?)
+        }
+        // Heredocs are taken literally so scripts can use ${} themselves
+        Rule::heredoc => {
+            let inner_pair = pair
+                .into_inner()
+                .next()
+                .ok_or_else(|| unexpected(rule, &span))?;
+            let indented = inner_pair.as_rule() == Rule::heredoc_indented;
+            // Lines end with \n whatever the file uses, so scripts from
+            // files with CRLF line endings still run
+            let body = format::normalize_newlines(
+                inner_pair
+                    .into_inner()
+                    .find(|p| {
+                        matches!(
+                            p.as_rule(),
+                            Rule::heredoc_body | Rule::heredoc_indented_body
+                        )
+                    })
+                    .map(|p| p.as_str())
+                    .unwrap_or_default(),
+            );
+            if indented {
+                Config::Value(
+                    body.split_inclusive('\n')
+                        .map(|l| l.trim_start_matches('\t'))
+                        .collect(),
+                )
+            } else {
+                Config::Value(body)
+            }
+        }
+        Rule::command_option => {
+            let inner_pair = pair
+                .into_inner()
+                .next()
+                .ok_or_else(|| unexpected(rule, &span))?;
+            let value = match inner_pair.as_rule() {
+                Rule::quoteless_string => variables::interpolate(inner_pair.as_str(), vars, &span)?,
+                Rule::string | Rule::heredoc => match parse_value(inner_pair, path, vars)? {
+                    Config::Value(val) => val,
+                    _ => return Err(unexpected(rule, &span)),
+                },
+                _ => return Err(unexpected(rule, &span)),
+            };
+            Config::Option(Argument { value, span })
+        }
+        Rule::EOI => Config::EOI,
+        _ => return Err(unexpected(rule, &span)),
+    })
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::{Keyword, KeywordDefinition, SysConfigParser};
+    use crate::{
+        docs, find_diagnostic, find_diagnostics, from_json_value, Argument, Document, Format,
+        FromKeyword, Keyword, KeywordDefinition, Loader, OptionDefinition, ParserError,
+        ParserErrors, SchemaErrors, SysConfigParser, Value, ValueType,
+    };
     use std::collections::HashMap;
+    use std::net::{IpAddr, Ipv4Addr};
 
     #[test]
     fn initial_test() {
@@ -157,35 +785,1114 @@ mod tests {
         parser.add_keyword(
             String::from("zpool-create"),
             KeywordDefinition {
-                options: vec![String::from("ashift")],
+                min_arguments: 2,
+                max_arguments: None,
+                options: vec![OptionDefinition::new("ashift", ValueType::Integer)],
+                children: HashMap::new(),
+                ..Default::default()
             },
         );
         parser.add_keyword(
             String::from("locale"),
-            KeywordDefinition { options: vec![] },
+            KeywordDefinition {
+                min_arguments: 1,
+                max_arguments: Some(1),
+                options: vec![],
+                children: HashMap::new(),
+                ..Default::default()
+            },
         );
         let config_ast = parser.parse_config(config_file).unwrap();
         //println!("{:?}", config_ast);
+        assert_eq!(config_ast[0].name, "zpool-create");
+        assert_eq!(config_ast[0].options.len(), 1);
         assert_eq!(
-            config_ast[0],
-            Keyword {
-                name: "zpool-create".to_string(),
-                options: Some(HashMap::from([("ashift".to_string(), "12".to_string())])),
-                arguments: vec![
-                    "mirror".to_string(),
-                    "c1t0d0s0".to_string(),
-                    "c2t0d0s0".to_string(),
-                    "c3t0d0s0".to_string()
-                ]
+            config_ast[0].option("ashift").unwrap().value,
+            Value::Integer(12)
+        );
+        assert_eq!(
+            config_ast[0]
+                .arguments
+                .iter()
+                .map(|a| a.value.as_str())
+                .collect::<Vec<&str>>(),
+            vec!["mirror", "c1t0d0s0", "c2t0d0s0", "c3t0d0s0"]
+        );
+        assert_eq!(config_ast[1].name, "locale");
+        assert!(config_ast[1].options.is_empty());
+        assert_eq!(config_ast[1].arguments[0].value, "en_US");
+    }
+
+    #[test]
+    fn span_test() {
+        let config_file =
+            "# a comment\nlocale en_US\n\tzpool-create --ashift=\"12\" rpool c1t0d0s0\n";
+        let parser = SysConfigParser::default();
+        let config_ast = parser.parse_config(config_file).unwrap();
+        assert_eq!(config_ast[0].span.line, 2);
+        assert_eq!(config_ast[0].span.column, 1);
+        assert_eq!(config_ast[0].arguments[0].span.column, 8);
+        assert_eq!(config_ast[1].span.line, 3);
+        assert_eq!(config_ast[1].span.column, 2);
+        let ashift = config_ast[1].option("ashift").unwrap();
+        assert_eq!(ashift.span.line, 3);
+        assert_eq!(ashift.span.column, 15);
+        assert_eq!(config_ast[1].arguments[1].span.column, 35);
+    }
+
+    fn network_parser() -> SysConfigParser {
+        let mut parser = SysConfigParser::default();
+        parser.add_keyword(
+            String::from("network_interface"),
+            KeywordDefinition {
+                min_arguments: 1,
+                max_arguments: Some(1),
+                options: vec![
+                    OptionDefinition::new("static", ValueType::IpAddress),
+                    OptionDefinition::new("primary", ValueType::Boolean).with_default("false"),
+                    OptionDefinition::new("mtu", ValueType::Integer),
+                    OptionDefinition::new("alias", ValueType::IpAddress).repeatable(),
+                    OptionDefinition::new("type", ValueType::Enum(vec!["ether".into()])).required(),
+                ],
+                children: HashMap::new(),
+                ..Default::default()
+            },
+        );
+        parser
+    }
+
+    #[test]
+    fn unknown_keyword_test() {
+        let parser = network_parser();
+        let err = parser
+            .parse_config("network_interfce --type=\"ether\" e1000g0\n")
+            .unwrap_err();
+        match err.downcast_ref::<ParserError>() {
+            Some(ParserError::UnknownKeyword { keyword, valid, .. }) => {
+                assert_eq!(keyword, "network_interfce");
+                assert_eq!(valid, &vec!["network_interface".to_string()]);
+            }
+            _ => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn unknown_option_test() {
+        let parser = network_parser();
+        let err = parser
+            .parse_config("network_interface --stattic=\"10.0.0.2/24\" e1000g0\n")
+            .unwrap_err();
+        match err.downcast_ref::<ParserError>() {
+            Some(ParserError::UnknownOption {
+                keyword,
+                option,
+                valid,
+                ..
+            }) => {
+                assert_eq!(keyword, "network_interface");
+                assert_eq!(option, "stattic");
+                assert_eq!(valid, &vec!["static", "primary", "mtu", "alias", "type"]);
             }
+            _ => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn render_diagnostic_test() {
+        let parser = network_parser();
+        let config_file =
+            "network_interface --type=\"ether\" e1000g0\nnetwork_interface --stattic=\"10.0.0.2/24\" e1000g1\n";
+        let err = parser.parse_config(config_file).unwrap_err();
+        let diag = find_diagnostic(&err).unwrap();
+        assert_eq!(diag.span.line, 2);
+        assert_eq!(diag.span.column, 19);
+        let rendered = diag.render(config_file);
+        assert_eq!(
+            rendered,
+            "error: option stattic is not known for keyword network_interface, valid options are: static, primary, mtu, alias, type\n  --> 2:19\n  |\n2 | network_interface --stattic=\"10.0.0.2/24\" e1000g1\n  |                   ^^^^^^^^^^^^^^^^^^^^^^^\n"
         );
+    }
+
+    #[test]
+    fn render_diagnostic_lone_cr_test() {
+        let parser = network_parser();
+        let config_file =
+            "network_interface --type=\"ether\" e1000g0\rnetwork_interface --stattic=\"10.0.0.2/24\" e1000g1\r";
+        let err = parser.parse_config(config_file).unwrap_err();
+        let diag = find_diagnostic(&err).unwrap();
+        assert_eq!(diag.span.line, 2);
+        assert_eq!(diag.span.column, 19);
+        let rendered = diag.render(config_file);
+        assert!(rendered.contains(
+            "2 | network_interface --stattic=\"10.0.0.2/24\" e1000g1\n  |                   ^^^^^^^^^^^^^^^^^^^^^^^\n"
+        ));
+
+        let config_file = "locale en_US\rlocale --name=\"unterminated\r";
+        let err = SysConfigParser::default()
+            .parse_config(config_file)
+            .unwrap_err();
+        let diag = find_diagnostic(&err).unwrap();
+        assert_eq!(diag.span.line, 2);
+        assert!(diag
+            .render(config_file)
+            .contains("2 | locale --name=\"unterminated\n"));
+    }
+
+    #[test]
+    fn syntax_error_span_test() {
+        let parser = SysConfigParser::default();
+        let err = parser
+            .parse_config("locale en_US\nlocale --name=\"unterminated\n")
+            .unwrap_err();
+        let diag = find_diagnostic(&err).unwrap();
+        assert_eq!(diag.span.line, 2);
+    }
+
+    #[test]
+    fn typed_options_test() {
+        let parser = network_parser();
+        let config_ast = parser
+            .parse_config("network_interface --type=\"ether\" --static=\"10.0.0.2/24\" --mtu=\"9000\" e1000g0\n")
+            .unwrap();
+        let kw = &config_ast[0];
         assert_eq!(
-            config_ast[1],
-            Keyword {
-                name: "locale".to_string(),
-                options: None,
-                arguments: vec!["en_US".to_string()]
+            kw.option("static").unwrap().value,
+            Value::IpAddress {
+                address: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)),
+                prefix: Some(24)
             }
         );
+        assert_eq!(
+            kw.option("static").unwrap().value.to_string(),
+            "10.0.0.2/24"
+        );
+        assert_eq!(kw.option("mtu").unwrap().value.as_integer(), Some(9000));
+        assert_eq!(kw.flag("primary"), Some(false));
+        assert_eq!(kw.option("type").unwrap().value.as_str(), Some("ether"));
+    }
+
+    #[test]
+    fn schema_errors_test() {
+        let parser = network_parser();
+
+        let err = parser.parse_config("network_interface\n").unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ParserError>(),
+            Some(ParserError::ArgumentCount { found: 0, .. })
+        ));
+
+        let err = parser
+            .parse_config("network_interface --type=\"ether\" e1000g0 e1000g1\n")
+            .unwrap_err();
+        match err.downcast_ref::<ParserError>() {
+            Some(ParserError::ArgumentCount { found, span, .. }) => {
+                assert_eq!(*found, 2);
+                assert_eq!(span.column, 42);
+            }
+            _ => panic!("unexpected error {:?}", err),
+        }
+
+        let err = parser
+            .parse_config("network_interface e1000g0\n")
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ParserError>(),
+            Some(ParserError::MissingOption { option, .. }) if option == "type"
+        ));
+
+        for bad in [
+            "--mtu=\"large\" --type=\"ether\"",
+            "--static=\"10.0.0.300\" --type=\"ether\"",
+            "--static=\"10.0.0.2/33\" --type=\"ether\"",
+            "--primary=\"maybe\" --type=\"ether\"",
+            "--type=\"wifi\"",
+        ] {
+            let err = parser
+                .parse_config(&format!("network_interface {} e1000g0\n", bad))
+                .unwrap_err();
+            assert!(
+                matches!(
+                    err.downcast_ref::<ParserError>(),
+                    Some(ParserError::InvalidOptionValue { .. })
+                ),
+                "{} was accepted",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn flag_options_test() {
+        let parser = SysConfigParser::default();
+        let config_ast = parser
+            .parse_config("zpool-create --no-uefi --ashift=12 --force rpool c1t0d0s0\n")
+            .unwrap();
+        assert_eq!(config_ast[0].flag("uefi"), Some(false));
+        assert_eq!(config_ast[0].flag("force"), Some(true));
+        assert_eq!(config_ast[0].flag("trim"), None);
+        assert_eq!(
+            config_ast[0].option("ashift").unwrap().value,
+            Value::String("12".into())
+        );
+        assert_eq!(config_ast[0].arguments.len(), 2);
+
+        let parser = network_parser();
+        let config_ast = parser
+            .parse_config(
+                "network_interface --type=ether --primary --static=10.0.0.2/24 e1000g0\nnetwork_interface --type=ether --no-primary e1000g1\nnetwork_interface --type=ether --primary=\"\" e1000g2\n",
+            )
+            .unwrap();
+        assert_eq!(config_ast[0].flag("primary"), Some(true));
+        assert_eq!(
+            config_ast[0].option("static").unwrap().value.to_string(),
+            "10.0.0.2/24"
+        );
+        assert_eq!(config_ast[1].flag("primary"), Some(false));
+        assert_eq!(config_ast[2].flag("primary"), Some(true));
+
+        let err = parser
+            .parse_config("network_interface --type=ether --mtu e1000g0\n")
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ParserError>(),
+            Some(ParserError::MissingOptionValue { option, .. }) if option == "mtu"
+        ));
+    }
+
+    #[test]
+    fn repeated_options_test() {
+        let parser = SysConfigParser::default();
+        let config_ast = parser
+            .parse_config("setup_dns --search=a.example.com --domain=example.com --search=\"b.example.com\" 10.0.0.1\n")
+            .unwrap();
+        let kw = &config_ast[0];
+        assert_eq!(
+            kw.options
+                .iter()
+                .map(|o| o.name.as_str())
+                .collect::<Vec<&str>>(),
+            vec!["search", "domain", "search"]
+        );
+        assert_eq!(
+            kw.values("search")
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<String>>(),
+            vec!["a.example.com", "b.example.com"]
+        );
+        assert_eq!(kw.values("nameserver").len(), 0);
+
+        let parser = network_parser();
+        let config_ast = parser
+            .parse_config(
+                "network_interface --type=ether --alias=10.0.0.3 --alias=10.0.0.4 e1000g0\n",
+            )
+            .unwrap();
+        assert_eq!(config_ast[0].values("alias").len(), 2);
+
+        let err = parser
+            .parse_config("network_interface --type=ether --mtu=1500 --mtu=9000 e1000g0\n")
+            .unwrap_err();
+        match err.downcast_ref::<ParserError>() {
+            Some(ParserError::DuplicateOption { option, span, .. }) => {
+                assert_eq!(option, "mtu");
+                assert_eq!(span.column, 43);
+            }
+            _ => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn multiline_values_test() {
+        let parser = SysConfigParser::default();
+        let config_ast = parser
+            .parse_config(
+                "file /etc/motd --mode=0644 \\\n    --contents=<<EOF\nWelcome\n  # not a comment\nEOF\nfile /etc/issue <<-END\n\tindented\n\tEND\nkeyboard US-English\n",
+            )
+            .unwrap();
+        assert_eq!(config_ast.len(), 3);
+        assert_eq!(config_ast[0].arguments[0].value, "/etc/motd");
+        assert_eq!(
+            config_ast[0].option("mode").unwrap().value.as_str(),
+            Some("0644")
+        );
+        assert_eq!(
+            config_ast[0].option("contents").unwrap().value.as_str(),
+            Some("Welcome\n  # not a comment\n")
+        );
+        assert_eq!(config_ast[1].arguments[1].value, "indented\n");
+        assert_eq!(config_ast[2].name, "keyboard");
+        assert_eq!(config_ast[2].span.line, 9);
+
+        // A heredoc without terminator is a syntax error
+        assert!(parser
+            .parse_config("file /etc/motd <<EOF\nWelcome\n")
+            .is_err());
+    }
+
+    #[test]
+    fn variables_test() {
+        std::env::set_var("LIBCFGPARSER_TEST_DOMAIN", "example.com");
+        let mut parser = SysConfigParser::default();
+        parser.set_variable("POOL", "tank");
+        let config_ast = parser
+            .parse_config(
+                "set POOL rpool\nset HOST \"db-${POOL}\"\nimage ${POOL}/ROOT --name=\"${HOST}.${env:LIBCFGPARSER_TEST_DOMAIN}\" $${POOL}\n",
+            )
+            .unwrap();
+        assert_eq!(config_ast.len(), 1);
+        assert_eq!(config_ast[0].arguments[0].value, "tank/ROOT");
+        assert_eq!(config_ast[0].arguments[1].value, "${POOL}");
+        assert_eq!(
+            config_ast[0].option("name").unwrap().value.as_str(),
+            Some("db-tank.example.com")
+        );
+
+        let err = parser
+            .parse_config("keyboard US\nimage ${MISSING}\n")
+            .unwrap_err();
+        match err.downcast_ref::<ParserError>() {
+            Some(ParserError::UndefinedVariable { name, span }) => {
+                assert_eq!(name, "MISSING");
+                assert_eq!(span.line, 2);
+            }
+            _ => panic!("unexpected error {:?}", err),
+        }
+        assert!(parser.parse_config("set POOL\n").is_err());
+    }
+
+    #[test]
+    fn literal_variable_round_trip_test() {
+        let mut parser = SysConfigParser::default();
+        parser.set_variable("POOL", "tank");
+        let source = "image $${POOL} --name=\"db $${HOST}\" ${POOL}\n";
+        let keywords = parser.parse_config(source).unwrap();
+        assert_eq!(keywords[0].arguments[0].value, "${POOL}");
+        assert_eq!(
+            keywords[0].option("name").unwrap().value.as_str(),
+            Some("db ${HOST}")
+        );
+
+        // A literal ${ is escaped again so it reads back as the same value
+        let printed = crate::format_keywords(&keywords);
+        assert_eq!(printed, "image --name=\"db $${HOST}\" $${POOL} tank\n");
+        let reparsed = parser.parse_config(&printed).unwrap();
+        assert_eq!(reparsed[0].arguments[0].value, "${POOL}");
+        assert_eq!(reparsed[0].arguments[1].value, "tank");
+        assert_eq!(
+            reparsed[0].option("name").unwrap().value.as_str(),
+            Some("db ${HOST}")
+        );
+
+        // The formatter keeps references and escapes as written
+        assert_eq!(
+            Document::parse(source).unwrap().formatted(),
+            "image --name=\"db $${HOST}\" $${POOL} ${POOL}\n"
+        );
+    }
+
+    #[test]
+    fn conditional_test() {
+        let mut parser = SysConfigParser::default();
+        let facts: HashMap<String, Vec<String>> = HashMap::from([
+            ("hostname".to_string(), vec!["db1".to_string()]),
+            (
+                "mac".to_string(),
+                vec![
+                    "02:08:20:aa:bb:01".to_string(),
+                    "02:08:20:aa:bb:02".to_string(),
+                ],
+            ),
+        ]);
+        parser.set_fact_provider(Box::new(facts));
+        parser.set_variable("ROLE", "web");
+
+        let config_ast = parser
+            .parse_config(
+                "if hostname == db1\n  set POOL tank\n  if mac == \"02:08:20:AA:BB:02\"\n    keyboard German\n  else\n    keyboard ${MISSING}\n  endif\nelse\n  set POOL rpool\nendif\nif \"${ROLE}\" != web\n  timezone UTC\nendif\nimage ${POOL}\n",
+            )
+            .unwrap();
+        let names: Vec<(&str, &str)> = config_ast
+            .iter()
+            .map(|k| (k.name.as_str(), k.arguments[0].value.as_str()))
+            .collect();
+        assert_eq!(names, vec![("keyboard", "German"), ("image", "tank")]);
+
+        let err = parser.parse_config("if arch == i386\nendif\n").unwrap_err();
+        match err.downcast_ref::<ParserError>() {
+            Some(ParserError::UnknownFact { name, span }) => {
+                assert_eq!(name, "arch");
+                assert_eq!(span.column, 4);
+            }
+            _ => panic!("unexpected error {:?}", err),
+        }
+        let err = parser.parse_config("keyboard US\nendif\n").unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ParserError>(),
+            Some(ParserError::UnmatchedDirective { .. })
+        ));
+        let err = parser
+            .parse_config("if hostname == db1\nkeyboard US\n")
+            .unwrap_err();
+        match err.downcast_ref::<ParserError>() {
+            Some(ParserError::UnterminatedIf { span }) => assert_eq!(span.line, 1),
+            _ => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn include_test() {
+        let dir = std::env::temp_dir().join(format!("libcfgparser-include-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("conf.d")).unwrap();
+        std::fs::write(
+            dir.join("main.sycfg"),
+            "set LAYOUT US-English\ninclude \"conf.d/*.sycfg\"\ntimezone UTC\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("conf.d/10-keyboard.sycfg"), "keyboard ${LAYOUT}\n").unwrap();
+        std::fs::write(dir.join("conf.d/20-locale.sycfg"), "locale en_US\n").unwrap();
+
+        let parser = SysConfigParser::default();
+        let config_ast = parser.parse_config_file(dir.join("main.sycfg")).unwrap();
+        let names: Vec<&str> = config_ast.iter().map(|k| k.name.as_str()).collect();
+        assert_eq!(names, vec!["keyboard", "locale", "timezone"]);
+        assert_eq!(config_ast[0].arguments[0].value, "US-English");
+        assert_eq!(
+            config_ast[0].span.file.as_deref(),
+            Some(dir.join("conf.d/10-keyboard.sycfg").as_path())
+        );
+
+        std::fs::write(dir.join("a.sycfg"), "include \"b.sycfg\"\n").unwrap();
+        std::fs::write(dir.join("b.sycfg"), "keyboard US\ninclude a.sycfg\n").unwrap();
+        let err = parser.parse_config_file(dir.join("a.sycfg")).unwrap_err();
+        match err.downcast_ref::<ParserError>() {
+            Some(ParserError::IncludeCycle { chain, span }) => {
+                assert_eq!(chain.len(), 3);
+                assert!(chain[2].ends_with("a.sycfg"));
+                assert!(span.file.as_ref().unwrap().ends_with("b.sycfg"));
+                assert_eq!(span.line, 2);
+            }
+            _ => panic!("unexpected error {:?}", err),
+        }
+
+        assert!(parser
+            .parse_config_file(dir.join("conf.d/missing.sycfg"))
+            .is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn document_test() {
+        let source = "# installer profile\n\nkeyboard   US-English # layout\n\n\nif hostname == db1\n        locale \"en_US.UTF-8\"\nendif\nfile /etc/motd --contents=<<EOF\nhello \"world\"\nEOF\n";
+        let mut doc = Document::parse(source).unwrap();
+        assert_eq!(doc.to_string(), source);
+        assert_eq!(doc.keywords().count(), 3);
+
+        let formatted = doc.formatted();
+        assert_eq!(
+            formatted,
+            "# installer profile\n\nkeyboard US-English # layout\n\nif hostname == db1\n  locale en_US.UTF-8\nendif\nfile --contents=\"hello \\\"world\\\"\n\" /etc/motd\n"
+        );
+        // The canonical form reads back as the same keywords
+        let strip_spans = |keywords: Vec<Keyword>| {
+            keywords
+                .into_iter()
+                .map(|k| {
+                    (
+                        k.name,
+                        k.options
+                            .into_iter()
+                            .map(|o| (o.name, o.value))
+                            .collect::<Vec<_>>(),
+                        k.arguments.into_iter().map(|a| a.value).collect::<Vec<_>>(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        let facts: HashMap<String, Vec<String>> =
+            HashMap::from([("hostname".to_string(), vec!["db1".to_string()])]);
+        let mut parser = SysConfigParser::default();
+        parser.set_fact_provider(Box::new(facts));
+        assert_eq!(
+            strip_spans(parser.parse_config(&formatted).unwrap()),
+            strip_spans(parser.parse_config(source).unwrap())
+        );
+        assert_eq!(Document::parse(&formatted).unwrap().formatted(), formatted);
+
+        doc.keyword_mut(0).unwrap().arguments[0].value = "German".into();
+        doc.remove_keyword(1);
+        doc.add_keyword(Keyword {
+            name: "timezone".into(),
+            arguments: vec![Argument {
+                value: "Europe/Zurich".into(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        });
+        assert_eq!(
+            doc.to_string(),
+            "# installer profile\n\nkeyboard German # layout\n\n\nif hostname == db1\nendif\nfile /etc/motd --contents=<<EOF\nhello \"world\"\nEOF\ntimezone Europe/Zurich\n"
+        );
+    }
+
+    #[test]
+    fn block_test() {
+        let source = "network_interface --type=ether e1000g0 {\n  static 10.0.0.2/24; primary\n  alias 10.0.0.3 # first alias\n  alias 10.0.0.4;\n}\nnetwork_interface --type=ether e1000g1 { no-primary }\n";
+        let config_ast = SysConfigParser::default().parse_config(source).unwrap();
+        assert_eq!(config_ast[0].children.len(), 4);
+        assert_eq!(config_ast[0].children[0].name, "static");
+        assert_eq!(config_ast[0].children[2].span.line, 3);
+        assert_eq!(config_ast[0].children("alias").len(), 2);
+        let config_ast = SysConfigParser::default()
+            .parse_config("zpool-create tank { raidz2 c1t0d0 c2t0d0; }\n")
+            .unwrap();
+        assert_eq!(config_ast[0].children[0].name, "raidz2");
+        assert_eq!(config_ast[0].children[0].arguments.len(), 2);
+
+        let parser = network_parser();
+        let config_ast = parser.parse_config(source).unwrap();
+        let kw = &config_ast[0];
+        assert!(kw.children.is_empty());
+        assert_eq!(
+            kw.option("static").unwrap().value.to_string(),
+            "10.0.0.2/24"
+        );
+        assert_eq!(kw.flag("primary"), Some(true));
+        assert_eq!(kw.values("alias").len(), 2);
+        assert_eq!(config_ast[1].flag("primary"), Some(false));
+
+        let err = parser
+            .parse_config("network_interface --type=ether e1000g0 {\n  mtu 1500 9000\n}\n")
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ParserError>(),
+            Some(ParserError::ArgumentCount { span, .. }) if span.line == 2
+        ));
+        let err = parser
+            .parse_config("network_interface --type=ether e1000g0 {\n  statc 10.0.0.2\n}\n")
+            .unwrap_err();
+        match err.downcast_ref::<ParserError>() {
+            Some(ParserError::UnknownKeyword { keyword, span, .. }) => {
+                assert_eq!(keyword, "statc");
+                assert_eq!((span.line, span.column), (2, 3));
+            }
+            _ => panic!("unexpected error {:?}", err),
+        }
+
+        // Children with their own definition are kept as children
+        let mut parser = SysConfigParser::default();
+        parser.add_keyword(
+            String::from("zpool-create"),
+            KeywordDefinition {
+                min_arguments: 1,
+                max_arguments: None,
+                options: vec![OptionDefinition::new("ashift", ValueType::Integer)],
+                children: HashMap::from([(
+                    String::from("mirror"),
+                    KeywordDefinition {
+                        min_arguments: 2,
+                        max_arguments: None,
+                        options: vec![],
+                        children: HashMap::new(),
+                        ..Default::default()
+                    },
+                )]),
+                ..Default::default()
+            },
+        );
+        let config_ast = parser
+            .parse_config("zpool-create rpool { ashift 12; mirror c1t0d0 c2t0d0 }\n")
+            .unwrap();
+        assert_eq!(
+            config_ast[0].option("ashift").unwrap().value,
+            Value::Integer(12)
+        );
+        assert_eq!(config_ast[0].children("mirror")[0].arguments.len(), 2);
+        assert!(parser
+            .parse_config("zpool-create rpool { mirror c1t0d0 }\n")
+            .is_err());
+
+        // A broken block is reported once and parsing goes on after it
+        let err = parser
+            .parse_config("zpool-create rpool {\n  mirror ?? c2t0d0\n}\nzpool-create\n")
+            .unwrap_err();
+        let errors = &err.downcast_ref::<ParserErrors>().unwrap().errors;
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].span().line, 2);
+        assert_eq!(errors[1].span().line, 4);
+
+        let doc = Document::parse(source).unwrap();
+        assert_eq!(doc.to_string(), source);
+        let formatted = Document::parse("zpool-create rpool {ashift 12;mirror c1t0d0 c2t0d0}\n")
+            .unwrap()
+            .formatted();
+        assert_eq!(
+            formatted,
+            "zpool-create rpool {\n  ashift 12\n  mirror c1t0d0 c2t0d0\n}\n"
+        );
+        assert_eq!(Document::parse(&formatted).unwrap().formatted(), formatted);
+        // The comment inside the block is kept
+        assert_eq!(
+            doc.formatted().lines().nth(2),
+            Some("  alias 10.0.0.3 # first alias")
+        );
+    }
+
+    #[derive(Debug, PartialEq, Keyword)]
+    enum Instruction {
+        /// Set the keyboard
+        /// layout.
+        ///
+        /// See kbd(1).
+        #[keyword(name = "keyboard", alias = "keymap", example = "keyboard German")]
+        SetKeymap(String),
+        #[keyword(name = "terminal")]
+        SetupTerminal {
+            #[keyword(option = "module")]
+            modules: Option<String>,
+            #[keyword(option = "type", arg)]
+            terminal_type: String,
+        },
+        #[keyword(name = "network_interface")]
+        ConfigureNetworkAdapter {
+            #[keyword(arg)]
+            device: String,
+            #[keyword(option = "static", value_type = "ip")]
+            address: Option<String>,
+            /// Maximum size of a packet
+            #[keyword(option)]
+            mtu: Option<u32>,
+            #[keyword(option)]
+            primary: bool,
+            #[keyword(option, default = "ether")]
+            link: String,
+        },
+        #[keyword(name = "setup_dns", options = "dns_options")]
+        SetupDns {
+            #[keyword(option)]
+            search: Vec<String>,
+            #[keyword(arg)]
+            nameservers: Vec<String>,
+            #[keyword(with = "dns_domain")]
+            domain: Option<String>,
+        },
+        #[allow(dead_code)]
+        #[keyword(skip)]
+        Reboot,
+    }
+
+    fn dns_options() -> Vec<OptionDefinition> {
+        vec![OptionDefinition::new("domain", ValueType::String)]
+    }
+
+    fn dns_domain(keyword: &Keyword) -> anyhow::Result<Option<String>> {
+        Ok(keyword
+            .option("domain")
+            .map(|o| o.value.to_string().to_uppercase()))
+    }
+
+    #[test]
+    fn derive_test() {
+        let mut parser = SysConfigParser::default();
+        let definitions = Instruction::keyword_definitions();
+        let names: Vec<&str> = definitions.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "keyboard",
+                "keymap",
+                "terminal",
+                "network_interface",
+                "setup_dns"
+            ]
+        );
+        for (name, def) in definitions {
+            parser.add_keyword(name, def);
+        }
+
+        let instructions = parser
+            .parse_config("keymap German\nterminal vt100\nterminal --type=xterm --module=serial\nnetwork_interface e1000g0 { static 10.0.0.2/24; mtu 9000; primary }\nsetup_dns --domain=example.com --search=a --search=b 10.0.0.1 10.0.0.2\n")
+            .unwrap()
+            .iter()
+            .map(Instruction::from_keyword)
+            .collect::<anyhow::Result<Vec<Instruction>>>()
+            .unwrap();
+        assert_eq!(
+            instructions,
+            vec![
+                Instruction::SetKeymap("German".into()),
+                Instruction::SetupTerminal {
+                    modules: None,
+                    terminal_type: "vt100".into()
+                },
+                Instruction::SetupTerminal {
+                    modules: Some("serial".into()),
+                    terminal_type: "xterm".into()
+                },
+                Instruction::ConfigureNetworkAdapter {
+                    device: "e1000g0".into(),
+                    address: Some("10.0.0.2/24".into()),
+                    mtu: Some(9000),
+                    primary: true,
+                    link: "ether".into(),
+                },
+                Instruction::SetupDns {
+                    search: vec!["a".into(), "b".into()],
+                    nameservers: vec!["10.0.0.1".into(), "10.0.0.2".into()],
+                    domain: Some("EXAMPLE.COM".into()),
+                },
+            ]
+        );
+
+        // The definitions are checked by the parser
+        assert!(parser.parse_config("keyboard\n").is_err());
+        assert!(parser
+            .parse_config("network_interface --static=10.0.0.300 e1000g0\n")
+            .is_err());
+        let keywords = parser.parse_config("terminal\n").unwrap();
+        let err = Instruction::from_keyword(&keywords[0]).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ParserError>(),
+            Some(ParserError::MissingValue { option, .. }) if option == "type"
+        ));
+        let err = Instruction::from_keyword(&Keyword {
+            name: "reboot".into(),
+            ..Default::default()
+        })
+        .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ParserError>(),
+            Some(ParserError::UnknownKeyword { .. })
+        ));
+    }
+
+    #[test]
+    fn docs_test() {
+        let definitions = Instruction::keyword_definitions();
+        let (_, keyboard) = &definitions[0];
+        assert_eq!(
+            keyboard.description.as_deref(),
+            Some("Set the keyboard layout.\n\nSee kbd(1).")
+        );
+        assert_eq!(keyboard.aliases, vec!["keymap"]);
+        assert_eq!(keyboard.examples, vec!["keyboard German"]);
+        assert_eq!(keyboard.argument_names, vec!["value"]);
+        let (_, keymap) = &definitions[1];
+        assert_eq!(keymap.aliases, vec!["keyboard"]);
+        let (_, network) = &definitions[3];
+        assert_eq!(
+            docs::signature("network_interface", network),
+            "network_interface [options] <device>"
+        );
+        assert_eq!(
+            network.option("mtu").unwrap().description.as_deref(),
+            Some("Maximum size of a packet")
+        );
+
+        let help = docs::help_for(&definitions, "network_interface").unwrap();
+        assert!(help.contains("  --mtu=<integer>\n      Maximum size of a packet\n"));
+        assert!(help.contains("  --link=<string>\n      (default ether)\n"));
+        assert!(docs::help_for(&definitions, "netwrk").is_err());
+
+        let reference = docs::Reference {
+            name: "test",
+            section: "4",
+            summary: "keywords for tests",
+            definitions: &definitions,
+        };
+        // Aliases are documented once with their keyword
+        let markdown = reference.markdown();
+        assert!(markdown.contains("### keyboard\n"));
+        assert!(!markdown.contains("### keymap\n"));
+        assert!(markdown.contains("Also known as `keymap`."));
+        let man = reference.man_page();
+        assert!(man.starts_with(".TH TEST 4 "));
+        assert!(man.contains(".TP\n.B \\-\\-mtu=<integer>\nMaximum size of a packet\n"));
+    }
+
+    #[derive(Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+    enum Step {
+        Locale { name: String, unicode: bool },
+        Network(Address),
+        Reboot,
+    }
+
+    #[derive(Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+    enum Address {
+        Dhcp,
+        Static(String),
+    }
+
+    #[test]
+    fn schema_validation_test() {
+        let steps: Vec<Step> = from_json_value(serde_json::json!([
+            {"Locale": {"name": "C", "unicode": false}},
+            {"Network": {"Static": "10.0.0.2/24"}},
+            {"Network": "Dhcp"},
+            "Reboot"
+        ]))
+        .unwrap();
+        assert_eq!(
+            steps[0],
+            Step::Locale {
+                name: "C".into(),
+                unicode: false
+            }
+        );
+
+        let err = from_json_value::<Vec<Step>>(serde_json::json!([
+            {"Locale": {"name": "C", "unicode": "yes"}},
+            {"Locale": {"name": "C"}},
+            {"Network": {"Static": 12}},
+            {"Reboots": null}
+        ]))
+        .unwrap_err();
+        let errors = &err.downcast_ref::<SchemaErrors>().unwrap().errors;
+        let paths: Vec<&str> = errors.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec!["/0/Locale/unicode", "/1/Locale", "/2/Network/Static", "/3"]
+        );
+        assert_eq!(errors[0].message, "\"yes\" is not of type \"boolean\"");
+        assert_eq!(errors[1].message, "\"unicode\" is a required property");
+        assert_eq!(errors[3].message, "unknown variant \"Reboots\"");
+    }
+
+    #[test]
+    fn loader_test() {
+        fn from_keywords(keywords: Vec<Keyword>) -> anyhow::Result<Vec<Step>> {
+            Ok(keywords
+                .into_iter()
+                .map(|k| match k.name.as_str() {
+                    "locale" => Step::Locale {
+                        name: k.arguments[0].value.clone(),
+                        unicode: true,
+                    },
+                    _ => Step::Network(Address::Dhcp),
+                })
+                .collect())
+        }
+        let parser = SysConfigParser::default();
+        let loader = Loader::new(&parser, from_keywords);
+        let expected = vec![
+            Step::Locale {
+                name: "C".into(),
+                unicode: true,
+            },
+            Step::Network(Address::Dhcp),
+        ];
+        let corpus = [
+            (Format::Native, "# steps\nlocale C\nnetwork dhcp\n"),
+            (Format::Native, "\u{feff}locale C\r\nnetwork dhcp"),
+            (
+                Format::Json,
+                "[{\"Locale\": {\"name\": \"C\", \"unicode\": true}}, {\"Network\": \"Dhcp\"}]",
+            ),
+            (
+                Format::Json,
+                "\n[\n  {\"Locale\": {\"name\": \"C\", \"unicode\": true}},\n  {\"Network\": \"Dhcp\"}\n]\n",
+            ),
+            (
+                Format::Yaml,
+                "# steps\n- Locale:\n    name: C\n    unicode: true\n- Network: Dhcp\n",
+            ),
+            (
+                Format::Yaml,
+                "---\n- Locale: {name: C, unicode: true}\n- Network: Dhcp\n",
+            ),
+            (
+                Format::Ron,
+                "// steps\n[\n  Locale(name: \"C\", unicode: true),\n  Network(Dhcp),\n]\n",
+            ),
+            (
+                Format::Toml,
+                "# steps\n[[instructions]]\nLocale = { name = \"C\", unicode = true }\n\n[[instructions]]\nNetwork = \"Dhcp\"\n",
+            ),
+            (
+                Format::Toml,
+                "instructions = [\n  { Locale = { name = \"C\", unicode = true } },\n  { Network = \"Dhcp\" },\n]\n",
+            ),
+        ];
+        for (format, content) in corpus {
+            assert_eq!(Format::sniff(content), format, "{}", content);
+            let steps = loader
+                .load_str(content, format, None)
+                .unwrap_or_else(|e| panic!("{}: {:?}", format, e));
+            assert_eq!(steps, expected, "{}", format);
+        }
+
+        // Syntax errors point at the offending line
+        for (format, content) in [
+            (Format::Json, "[\n  {\"Locale\": }\n]\n"),
+            (Format::Yaml, "- Locale:\n    name: [C\n- Network: Dhcp\n"),
+            (Format::Ron, "[\n  Locale(name: C),\n]\n"),
+            (Format::Toml, "[[instructions]]\nLocale = {\n"),
+        ] {
+            let err = loader.load_str(content, format, None).unwrap_err();
+            assert!(err.to_string().starts_with("invalid configuration"));
+            let diag = find_diagnostic(&err).unwrap_or_else(|| panic!("{}: {:?}", format, err));
+            assert!(!diag.message.contains(" at line "), "{}", diag.message);
+            assert!(diag.span.line >= 2, "{}: {:?}", format, diag);
+        }
+        let err = loader
+            .load_str("Network = \"Dhcp\"\n", Format::Toml, None)
+            .unwrap_err();
+        assert!(format!("{:#}", err).contains("no instructions array"));
+
+        // Files are read by extension and sniffed without one
+        let dir = std::env::temp_dir().join(format!("libcfgparser-loader-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("steps.json"), corpus[2].1).unwrap();
+        std::fs::write(dir.join("steps"), corpus[4].1).unwrap();
+        std::fs::write(dir.join("steps.sycfg"), corpus[0].1).unwrap();
+        for name in ["steps.json", "steps", "steps.sycfg"] {
+            assert_eq!(loader.load_file(dir.join(name)).unwrap(), expected);
+        }
+        assert_eq!(
+            loader.load_reader(corpus[6].1.as_bytes()).unwrap(),
+            expected
+        );
+        std::fs::write(dir.join("broken.yaml"), "- Network: Dhcp\n- Locale: [\n").unwrap();
+        let err = loader.load_file(dir.join("broken.yaml")).unwrap_err();
+        let diag = find_diagnostic(&err).unwrap();
+        assert!(diag.span.file.unwrap().ends_with("broken.yaml"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn error_recovery_test() {
+        let parser = network_parser();
+        let err = parser
+            .parse_config(
+                "network_interface --type=ether e1000g0\nnetwork_interface ?? e1000g1\nnetwrk e1000g2\nnetwork_interface --type=ether --mtu=big e1000g3\nelse\nnetwork_interface --type=ether e1000g4\nif hostname == db1\n",
+            )
+            .unwrap_err();
+        let errors = &err.downcast_ref::<ParserErrors>().unwrap().errors;
+        let found: Vec<(usize, usize)> = errors
+            .iter()
+            .map(|e| (e.span().line, e.span().column))
+            .collect();
+        assert_eq!(
+            found,
+            vec![(2, 19), (3, 1), (4, 32), (5, 1), (7, 4), (7, 1)]
+        );
+        assert!(matches!(errors[0], ParserError::Syntax { .. }));
+        assert!(matches!(errors[1], ParserError::UnknownKeyword { .. }));
+        assert!(matches!(errors[2], ParserError::InvalidOptionValue { .. }));
+        assert!(matches!(errors[3], ParserError::UnmatchedDirective { .. }));
+        assert!(matches!(errors[4], ParserError::UnknownFact { .. }));
+        assert!(matches!(errors[5], ParserError::UnterminatedIf { .. }));
+        assert_eq!(find_diagnostics(&err).len(), 6);
+
+        // Broken input never panics
+        for input in [
+            "",
+            "\n\n",
+            "\"",
+            "--",
+            "keyboard <<EOF\n",
+            "keyboard \"\\u12\"\n",
+            "if\nelse\nendif\nendif\n",
+            "set\ninclude\n",
+            "network_interface --type=<<-X\n\tX\n",
+            "\u{feff}keyboard US",
+        ] {
+            let _ = parser.parse_config(input);
+            let _ = Document::parse(input);
+        }
+    }
+
+    #[test]
+    fn line_endings_test() {
+        let reference = "keyboard US-English\nnetwork_interface e1000g0 { static 10.0.0.2/24; primary }\nif hostname == db1\nlocale en_US\nendif\nfile /etc/motd <<EOF\nhello\nEOF\n";
+        let unterminated = reference.trim_end_matches('\n');
+        let crlf = reference.replace('\n', "\r\n");
+        let commented = "# profile\n\nkeyboard US-English # layout\n\n\nnetwork_interface e1000g0 { static 10.0.0.2/24; primary }\n# only db1\nif hostname == db1\n  locale en_US\nendif\n\nfile /etc/motd <<EOF\nhello\nEOF\n# end\n";
+        let corpus = [
+            ("LF", reference.to_string()),
+            ("no newline at the end", unterminated.to_string()),
+            ("CRLF", crlf.clone()),
+            ("CRLF, no newline at the end", unterminated.replace('\n', "\r\n")),
+            ("CR", reference.replace('\n', "\r")),
+            ("comments", commented.to_string()),
+            ("comments, CRLF", commented.replace('\n', "\r\n")),
+            ("comments, CR", commented.replace('\n', "\r")),
+            ("comments, BOM", format!("\u{feff}{}", commented)),
+            ("comments, BOM, CRLF", format!("\u{feff}{}", commented.replace('\n', "\r\n"))),
+            ("comments, BOM, CR", format!("\u{feff}{}", commented.replace('\n', "\r"))),
+            (
+                "comments, CRLF, no newline at the end",
+                commented.trim_end_matches('\n').replace('\n', "\r\n"),
+            ),
+            ("BOM", format!("\u{feff}{}", reference)),
+            ("BOM, CRLF", format!("\u{feff}{}", crlf)),
+            ("BOM, no newline at the end", format!("\u{feff}{}", unterminated)),
+            ("comment at the end", format!("{}# end", reference)),
+            ("comment at the end, CRLF", format!("{}# end\r\n", crlf)),
+            (
+                "blank and comment lines",
+                "\n# profile\r\n  \t\n\nkeyboard US-English # layout\n\n\r\nnetwork_interface e1000g0 {\r\n  # address\r\n\r\n  static 10.0.0.2/24\r\n  primary\r\n}\r\n# only db1\nif hostname == db1 # fact\n  \n  locale en_US\nendif\t\n\nfile /etc/motd <<EOF\r\nhello\r\nEOF\r\n\n  # trailing\n\n".to_string(),
+            ),
+            ("last line is a keyword", "keyboard US-English\nnetwork_interface e1000g0 { static 10.0.0.2/24; primary }\nif hostname == db1\nlocale en_US\nendif\nfile /etc/motd \"hello\n\"".to_string()),
+        ];
+
+        let facts: HashMap<String, Vec<String>> =
+            HashMap::from([("hostname".to_string(), vec!["db1".to_string()])]);
+        let mut parser = SysConfigParser::default();
+        parser.set_fact_provider(Box::new(facts));
+        let render = |keywords: Vec<Keyword>| {
+            keywords
+                .iter()
+                .map(|k| k.to_string())
+                .collect::<Vec<String>>()
+        };
+        let expected = render(parser.parse_config(reference).unwrap());
+        assert_eq!(expected.len(), 4);
+        assert_eq!(
+            Document::parse(commented).unwrap().formatted(),
+            "# profile\n\nkeyboard US-English # layout\n\nnetwork_interface e1000g0 {\n  static 10.0.0.2/24\n  primary\n}\n# only db1\nif hostname == db1\n  locale en_US\nendif\n\nfile /etc/motd <<EOF\nhello\nEOF\n# end\n"
+        );
+        for (name, input) in &corpus {
+            let keywords = parser
+                .parse_config(input)
+                .unwrap_or_else(|e| panic!("{}: {}", name, e));
+            assert_eq!(render(keywords), expected, "{}", name);
+
+            let doc = Document::parse(input).unwrap_or_else(|e| panic!("{}: {}", name, e));
+            assert_eq!(&doc.to_string(), input, "{}", name);
+            let formatted = doc.formatted();
+            assert!(
+                !formatted.contains(['\r', '\u{feff}']),
+                "{}: {:?}",
+                name,
+                formatted
+            );
+            assert_eq!(
+                formatted.matches('#').count(),
+                input.matches('#').count(),
+                "{}: {:?}",
+                name,
+                formatted
+            );
+            // The formatter gives the same result for every line ending
+            let lf = crate::format::normalize_newlines(input.trim_start_matches('\u{feff}'));
+            assert_eq!(
+                formatted,
+                Document::parse(&lf).unwrap().formatted(),
+                "{}",
+                name
+            );
+        }
+
+        // The BOM is not part of the first keyword
+        let keywords = parser.parse_config("\u{feff}keyboard US").unwrap();
+        assert_eq!(keywords[0].name, "keyboard");
+        assert_eq!(keywords[0].span.start, 3);
+        // Errors on the last line are still found without a newline
+        let err = parser.parse_config("keyboard US\nlocale ??").unwrap_err();
+        assert_eq!(find_diagnostic(&err).unwrap().span.line, 2);
+        assert!(parser
+            .parse_config("if hostname == db1\nlocale en_US")
+            .is_err());
     }
 }
@@ -0,0 +1,263 @@
+use crate::{from_json_value, Diagnostic, Keyword, Span, SysConfigParser};
+use anyhow::{anyhow, bail, Context, Result};
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde_json::Value as JsonValue;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+/// The formats a configuration can be written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// The keyword format of [`SysConfigParser`]
+    Native,
+    Json,
+    Yaml,
+    Ron,
+    /// A TOML table with the instructions in the array `instructions`
+    Toml,
+}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Format::Native => write!(f, "native"),
+            Format::Json => write!(f, "JSON"),
+            Format::Yaml => write!(f, "YAML"),
+            Format::Ron => write!(f, "RON"),
+            Format::Toml => write!(f, "TOML"),
+        }
+    }
+}
+
+impl Format {
+    /// The format of a file by its extension. None if the extension does
+    /// not name one of the structured formats.
+    pub fn from_extension<P: AsRef<Path>>(path: P) -> Option<Format> {
+        match path.as_ref().extension()?.to_str()? {
+            "json" => Some(Format::Json),
+            "yml" | "yaml" => Some(Format::Yaml),
+            "ron" => Some(Format::Ron),
+            "toml" => Some(Format::Toml),
+            _ => None,
+        }
+    }
+
+    /// Guess the format from the first line which is not blank or a
+    /// comment. Anything which does not look like one of the structured
+    /// formats is taken as native format.
+    /// ```
+    /// use libcfgparser::Format;
+    ///
+    /// assert_eq!(Format::sniff("[{\"SetLocale\": {}}]"), Format::Json);
+    /// assert_eq!(Format::sniff("# profile\n- SetLocale:\n"), Format::Yaml);
+    /// assert_eq!(Format::sniff("[SetLocale(name: \"C\")]"), Format::Ron);
+    /// assert_eq!(Format::sniff("[[instructions]]\n"), Format::Toml);
+    /// assert_eq!(Format::sniff("keyboard US-English\n"), Format::Native);
+    /// ```
+    pub fn sniff(content: &str) -> Format {
+        let content = content.trim_start_matches('\u{feff}');
+        let mut offset = 0;
+        let mut line = "";
+        for l in content.split_inclusive('\n') {
+            offset += l.len();
+            let l = l.trim();
+            // #![enable(...)] is a RON attribute, not a comment
+            let comment = (l.starts_with('#') && !l.starts_with("#![")) || l.starts_with("//");
+            if !l.is_empty() && !comment {
+                line = l;
+                break;
+            }
+        }
+        let is_key = |key: &str| {
+            !key.is_empty()
+                && key
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "_-.\"".contains(c))
+        };
+
+        if line.starts_with('{') {
+            Format::Json
+        } else if line.starts_with("[[") {
+            Format::Toml
+        } else if let Some(rest) = line.strip_prefix('[') {
+            // The first value of a list tells JSON and RON apart
+            let first = rest.trim_start().chars().next().or_else(|| {
+                content[offset.min(content.len())..]
+                    .trim_start()
+                    .chars()
+                    .next()
+            });
+            if matches!(first, Some('{' | '"' | ']' | '0'..='9' | '-') | None) {
+                Format::Json
+            } else if rest.ends_with(']') && is_key(&rest[..rest.len() - 1]) {
+                Format::Toml
+            } else {
+                Format::Ron
+            }
+        } else if line.starts_with('(') || line.starts_with("#![") {
+            Format::Ron
+        } else if line == "---" || line == "-" || line.starts_with("- ") {
+            Format::Yaml
+        } else if line
+            .split_once('=')
+            .is_some_and(|(key, _)| is_key(key.trim()))
+        {
+            Format::Toml
+        } else if line
+            .split_once(':')
+            .is_some_and(|(key, rest)| is_key(key) && (rest.is_empty() || rest.starts_with(' ')))
+        {
+            Format::Yaml
+        } else {
+            Format::Native
+        }
+    }
+
+    /// The format of `path` by its extension or else by its content
+    pub fn detect<P: AsRef<Path>>(path: P, content: &str) -> Format {
+        Format::from_extension(path).unwrap_or_else(|| Format::sniff(content))
+    }
+}
+
+/// The span of a position given by line and column in `content`, both
+/// 1-based as reported by the deserializers
+fn span_at(content: &str, path: Option<&Path>, line: usize, column: usize) -> Span {
+    let line = line.max(1);
+    let column = column.max(1);
+    let line_start = content
+        .split_inclusive('\n')
+        .take(line - 1)
+        .map(str::len)
+        .sum::<usize>();
+    let text = content[line_start.min(content.len())..]
+        .lines()
+        .next()
+        .unwrap_or_default();
+    let mut chars = text.char_indices().skip(column - 1);
+    let start = line_start + chars.next().map(|(i, _)| i).unwrap_or(text.len());
+    let end = line_start + chars.next().map(|(i, _)| i).unwrap_or(text.len());
+    Span {
+        file: path.map(Path::to_path_buf),
+        line,
+        column,
+        start,
+        end: end.max(start),
+    }
+}
+
+/// A syntax error of a structured format. The deserializers append the
+/// location to their messages, the span holds it instead.
+fn syntax_error<E: Display>(
+    error: E,
+    content: &str,
+    path: Option<&Path>,
+    location: Option<(usize, usize)>,
+) -> anyhow::Error {
+    let message = error.to_string();
+    match location {
+        Some((line, column)) => {
+            let message = match message.rsplit_once(" at line ") {
+                Some((message, _)) => message.to_string(),
+                None => message,
+            };
+            anyhow!(Diagnostic {
+                message,
+                span: span_at(content, path, line, column),
+            })
+        }
+        None => anyhow!(message),
+    }
+}
+
+/// Parse a document in one of the formats which map to JSON. Syntax
+/// errors are returned as [`Diagnostic`] pointing at the error.
+pub fn to_json_value(content: &str, format: Format, path: Option<&Path>) -> Result<JsonValue> {
+    match format {
+        Format::Json => serde_json::from_str(content)
+            .map_err(|e| syntax_error(&e, content, path, Some((e.line(), e.column())))),
+        Format::Yaml => serde_yaml::from_str(content).map_err(|e| {
+            let location = e.location().map(|l| (l.line(), l.column()));
+            syntax_error(e, content, path, location)
+        }),
+        Format::Toml => {
+            let mut value: JsonValue = toml::from_str(content).map_err(|e| {
+                let location = e.line_col().map(|(line, col)| (line + 1, col + 1));
+                syntax_error(e, content, path, location)
+            })?;
+            // A TOML document is always a table
+            match value.get_mut("instructions") {
+                Some(instructions) => Ok(instructions.take()),
+                None => bail!("TOML configuration has no instructions array"),
+            }
+        }
+        Format::Native | Format::Ron => {
+            bail!("{} configuration can not be represented as JSON", format)
+        }
+    }
+}
+
+/// Reads configuration in every supported format. Keywords of the native
+/// format are turned into the configuration by `from_keywords`, the
+/// structured formats are checked against the schema of `T` first.
+pub struct Loader<'a, T> {
+    parser: &'a SysConfigParser,
+    from_keywords: fn(Vec<Keyword>) -> Result<T>,
+}
+
+impl<'a, T: DeserializeOwned + JsonSchema> Loader<'a, T> {
+    pub fn new(parser: &'a SysConfigParser, from_keywords: fn(Vec<Keyword>) -> Result<T>) -> Self {
+        Loader {
+            parser,
+            from_keywords,
+        }
+    }
+
+    /// Read the file at `path`. The format is taken from the extension,
+    /// files without a known extension are sniffed.
+    pub fn load_file<P: AsRef<Path>>(&self, path: P) -> Result<T> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("could not read configuration {}", path.display()))?;
+        self.load_str(&content, Format::detect(path, &content), Some(path))
+    }
+
+    /// Read configuration from a stream like stdin, the format is sniffed
+    pub fn load_reader<R: Read>(&self, mut reader: R) -> Result<T> {
+        let mut content = String::new();
+        reader.read_to_string(&mut content)?;
+        self.load_str(&content, Format::sniff(&content), None)
+    }
+
+    /// Parse `content` in `format`. `path` is used for error messages and
+    /// to resolve includes of the native format.
+    pub fn load_str(&self, content: &str, format: Format, path: Option<&Path>) -> Result<T> {
+        let invalid = || match path {
+            Some(path) => format!("invalid configuration {}", path.display()),
+            None => "invalid configuration".to_string(),
+        };
+        match format {
+            Format::Native => {
+                let keywords = match path {
+                    Some(path) => self.parser.parse_config_with_path(content, path)?,
+                    None => self.parser.parse_config(content)?,
+                };
+                (self.from_keywords)(keywords)
+            }
+            // RON enums can not be represented as JSON, the deserializer
+            // checks them instead of the schema
+            Format::Ron => ron::from_str(content)
+                .map_err(|e| {
+                    let location =
+                        Some((e.position.line, e.position.col)).filter(|(line, _)| *line > 0);
+                    syntax_error(&e.code, content, path, location)
+                })
+                .with_context(invalid),
+            Format::Json | Format::Yaml | Format::Toml => to_json_value(content, format, path)
+                .and_then(from_json_value)
+                .with_context(invalid),
+        }
+    }
+}
@@ -0,0 +1,381 @@
+use crate::{Keyword, KeywordOption, ParserError};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+/// The type an option value must have. The parser checks the value
+/// against the type and converts it into the matching [`Value`]
+#[derive(Default, Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
+pub enum ValueType {
+    #[default]
+    String,
+    Integer,
+    /// true/false, yes/no, on/off or 1/0. An empty value counts as true
+    /// so `--primary=""` keeps working.
+    Boolean,
+    /// One of the listed strings
+    Enum(Vec<String>),
+    /// An IPv4 or IPv6 address with an optional `/prefix`
+    IpAddress,
+    Path,
+}
+
+impl Display for ValueType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValueType::String => write!(f, "string"),
+            ValueType::Integer => write!(f, "integer"),
+            ValueType::Boolean => write!(f, "boolean"),
+            ValueType::Enum(values) => write!(f, "one of {}", values.join(", ")),
+            ValueType::IpAddress => write!(f, "ip address"),
+            ValueType::Path => write!(f, "path"),
+        }
+    }
+}
+
+/// A checked option value
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    IpAddress { address: IpAddr, prefix: Option<u8> },
+    Path(PathBuf),
+}
+
+impl Default for Value {
+    fn default() -> Self {
+        Self::String(String::new())
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::String(s) => write!(f, "{}", s),
+            Value::Integer(i) => write!(f, "{}", i),
+            Value::Boolean(b) => write!(f, "{}", b),
+            Value::IpAddress {
+                address,
+                prefix: Some(prefix),
+            } => write!(f, "{}/{}", address, prefix),
+            Value::IpAddress {
+                address,
+                prefix: None,
+            } => write!(f, "{}", address),
+            Value::Path(p) => write!(f, "{}", p.display()),
+        }
+    }
+}
+
+impl Value {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            Value::Integer(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Boolean(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    /// Convert the raw text of an option into the given type
+    pub fn coerce(raw: &str, value_type: &ValueType) -> Option<Value> {
+        match value_type {
+            ValueType::String => Some(Value::String(raw.into())),
+            ValueType::Integer => raw.parse::<i64>().ok().map(Value::Integer),
+            ValueType::Boolean => match raw.to_lowercase().as_str() {
+                "" | "true" | "yes" | "on" | "1" => Some(Value::Boolean(true)),
+                "false" | "no" | "off" | "0" => Some(Value::Boolean(false)),
+                _ => None,
+            },
+            ValueType::Enum(values) => {
+                if values.iter().any(|v| v == raw) {
+                    Some(Value::String(raw.into()))
+                } else {
+                    None
+                }
+            }
+            ValueType::IpAddress => {
+                let (address, prefix) = if let Some((addr, prefix)) = raw.split_once('/') {
+                    (addr, Some(prefix.parse::<u8>().ok()?))
+                } else {
+                    (raw, None)
+                };
+                let address = address.parse::<IpAddr>().ok()?;
+                let max_prefix = if address.is_ipv4() { 32 } else { 128 };
+                if prefix.unwrap_or_default() > max_prefix {
+                    return None;
+                }
+                Some(Value::IpAddress { address, prefix })
+            }
+            ValueType::Path => {
+                if raw.is_empty() {
+                    None
+                } else {
+                    Some(Value::Path(raw.into()))
+                }
+            }
+        }
+    }
+}
+
+/// Declaration of an option a keyword accepts
+#[derive(Default, Debug, Clone, Deserialize, Serialize)]
+pub struct OptionDefinition {
+    pub name: String,
+    pub value_type: ValueType,
+    pub required: bool,
+    /// Whether the option may be given more than once
+    pub repeatable: bool,
+    /// Raw value used when the option is not given
+    pub default: Option<String>,
+    /// Help text for the option
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+impl OptionDefinition {
+    pub fn new(name: &str, value_type: ValueType) -> Self {
+        OptionDefinition {
+            name: name.into(),
+            value_type,
+            required: false,
+            repeatable: false,
+            default: None,
+            description: None,
+        }
+    }
+
+    pub fn required(mut self) -> Self {
+        self.required = true;
+        self
+    }
+
+    pub fn repeatable(mut self) -> Self {
+        self.repeatable = true;
+        self
+    }
+
+    pub fn with_default(mut self, default: &str) -> Self {
+        self.default = Some(default.into());
+        self
+    }
+
+    pub fn with_description(mut self, description: &str) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+}
+
+/// Declaration of a keyword: how many positional arguments it takes
+/// and which options and child keywords it accepts
+#[derive(Default, Debug, Clone, Deserialize, Serialize)]
+pub struct KeywordDefinition {
+    pub min_arguments: usize,
+    /// None means there is no upper bound
+    pub max_arguments: Option<usize>,
+    pub options: Vec<OptionDefinition>,
+    /// Keywords which may be given in a block after the arguments. Options
+    /// may always be given in the block as well, `static 10.0.0.2/24;` is
+    /// the same as `--static=10.0.0.2/24` and `primary;` the same as
+    /// `--primary`.
+    #[serde(default)]
+    pub children: HashMap<String, KeywordDefinition>,
+    /// Help text for the keyword
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Names of the positional arguments for help texts
+    #[serde(default)]
+    pub argument_names: Vec<String>,
+    /// Other names the keyword is registered under
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    /// Example lines showing how the keyword is used
+    #[serde(default)]
+    pub examples: Vec<String>,
+}
+
+impl KeywordDefinition {
+    pub fn option(&self, name: &str) -> Option<&OptionDefinition> {
+        self.options.iter().find(|o| o.name == name)
+    }
+
+    pub fn option_names(&self) -> Vec<String> {
+        self.options.iter().map(|o| o.name.clone()).collect()
+    }
+
+    fn expected_arguments(&self) -> String {
+        match self.max_arguments {
+            Some(max) if max == self.min_arguments => format!("exactly {}", max),
+            Some(max) => format!("between {} and {}", self.min_arguments, max),
+            None => format!("at least {}", self.min_arguments),
+        }
+    }
+
+    /// Turn a child keyword which names an option into that option
+    fn child_option(&self, parent: &str, child: Keyword) -> Result<KeywordOption> {
+        if child.arguments.len() > 1 || !child.children.is_empty() {
+            let span = child
+                .arguments
+                .get(1)
+                .map(|a| a.span.clone())
+                .unwrap_or_else(|| child.span.clone());
+            return Err(anyhow!(ParserError::ArgumentCount {
+                keyword: format!("{} {}", parent, child.name),
+                expected: "at most 1".into(),
+                found: child.arguments.len(),
+                span,
+            }));
+        }
+        let (name, value) = match child.arguments.into_iter().next() {
+            Some(arg) => (child.name, Value::String(arg.value)),
+            None => match child.name.strip_prefix("no-") {
+                Some(negated) if self.option(negated).is_some() => {
+                    (negated.to_string(), Value::Boolean(false))
+                }
+                _ => (child.name, Value::Boolean(true)),
+            },
+        };
+        Ok(KeywordOption {
+            name,
+            value,
+            span: child.span,
+        })
+    }
+
+    /// Check the child keywords of a block. Children naming an option are
+    /// moved to the options of the keyword, the others are checked against
+    /// their own definitions.
+    fn validate_children(&self, keyword: &mut Keyword) -> Result<()> {
+        for mut child in std::mem::take(&mut keyword.children) {
+            let option_name = child.name.strip_prefix("no-").unwrap_or(&child.name);
+            if self.option(&child.name).is_some() || self.option(option_name).is_some() {
+                let option = self.child_option(&keyword.name, child)?;
+                keyword.options.push(option);
+            } else if let Some(def) = self.children.get(&child.name) {
+                def.validate(&mut child)?;
+                keyword.children.push(child);
+            } else {
+                let mut valid = self.option_names();
+                valid.extend(self.children.keys().cloned());
+                valid.sort();
+                let mut span = child.span.clone();
+                span.end = span.start + child.name.len();
+                return Err(anyhow!(ParserError::UnknownKeyword {
+                    keyword: child.name.clone(),
+                    valid,
+                    span,
+                }));
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the keyword against this definition. Option values are
+    /// converted to their declared type and defaults are filled in.
+    pub(crate) fn validate(&self, keyword: &mut Keyword) -> Result<()> {
+        let name = keyword.name.clone();
+        let keyword_span = keyword.span.clone();
+        let count = keyword.arguments.len();
+        if count < self.min_arguments || self.max_arguments.is_some_and(|max| count > max) {
+            let span = if count > self.min_arguments {
+                keyword.arguments[self.max_arguments.unwrap_or_default()]
+                    .span
+                    .clone()
+            } else {
+                keyword.span.clone()
+            };
+            return Err(anyhow!(ParserError::ArgumentCount {
+                keyword: keyword.name.clone(),
+                expected: self.expected_arguments(),
+                found: count,
+                span,
+            }));
+        }
+
+        self.validate_children(keyword)?;
+
+        let mut seen: Vec<String> = vec![];
+        for opt in keyword.options.iter_mut() {
+            let def = if let Some(def) = self.option(&opt.name) {
+                def
+            } else {
+                return Err(anyhow!(ParserError::UnknownOption {
+                    keyword: name.clone(),
+                    option: opt.name.clone(),
+                    valid: self.option_names(),
+                    span: opt.span.clone(),
+                }));
+            };
+            if seen.contains(&opt.name) && !def.repeatable {
+                return Err(anyhow!(ParserError::DuplicateOption {
+                    keyword: name.clone(),
+                    option: opt.name.clone(),
+                    span: opt.span.clone(),
+                }));
+            }
+            seen.push(opt.name.clone());
+            if matches!(opt.value, Value::Boolean(_)) && def.value_type != ValueType::Boolean {
+                return Err(anyhow!(ParserError::MissingOptionValue {
+                    keyword: name.clone(),
+                    option: opt.name.clone(),
+                    span: opt.span.clone(),
+                }));
+            }
+            let raw = opt.value.to_string();
+            opt.value = Value::coerce(&raw, &def.value_type).ok_or_else(|| {
+                anyhow!(ParserError::InvalidOptionValue {
+                    keyword: name.clone(),
+                    option: opt.name.clone(),
+                    value: raw.clone(),
+                    expected: def.value_type.clone(),
+                    span: opt.span.clone(),
+                })
+            })?;
+        }
+
+        for def in &self.options {
+            if seen.contains(&def.name) {
+                continue;
+            }
+            if let Some(default) = &def.default {
+                let value = Value::coerce(default, &def.value_type).ok_or_else(|| {
+                    anyhow!(
+                        "default {} of option {} is not a valid {}",
+                        default,
+                        def.name,
+                        def.value_type
+                    )
+                })?;
+                keyword.options.push(KeywordOption {
+                    name: def.name.clone(),
+                    value,
+                    span: keyword_span.clone(),
+                });
+            } else if def.required {
+                return Err(anyhow!(ParserError::MissingOption {
+                    keyword: name.clone(),
+                    option: def.name.clone(),
+                    span: keyword_span.clone(),
+                }));
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,161 @@
+use anyhow::{anyhow, Result};
+use jsonschema::error::ValidationErrorKind;
+use jsonschema::{JSONSchema, ValidationError};
+use schemars::{schema_for, JsonSchema};
+use serde::de::DeserializeOwned;
+use serde_json::Value as JsonValue;
+use std::fmt::{Display, Formatter};
+use thiserror::Error;
+
+/// A value in a document which does not match the schema. `path` is a
+/// JSON pointer to the value like `/0/SetLocale/name`.
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+#[error("{path}: {message}")]
+pub struct SchemaViolation {
+    pub path: String,
+    pub message: String,
+}
+
+/// Every value of a document which does not match the schema
+#[derive(Debug, Error)]
+pub struct SchemaErrors {
+    pub errors: Vec<SchemaViolation>,
+}
+
+impl Display for SchemaErrors {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "document does not match the schema:")?;
+        for e in &self.errors {
+            write!(f, "\n  {}", e)?;
+        }
+        Ok(())
+    }
+}
+
+/// The JSON schema of `T`
+pub fn json_schema<T: JsonSchema>() -> JsonValue {
+    serde_json::to_value(schema_for!(T)).expect("schemas can always be serialized")
+}
+
+/// Check `instance` against `schema` and return all mismatches as
+/// [`SchemaErrors`]
+pub fn validate_json(schema: &JsonValue, instance: &JsonValue) -> Result<()> {
+    let compiled = JSONSchema::compile(schema).map_err(|e| anyhow!("invalid schema: {}", e))?;
+    let mut errors = vec![];
+    collect_violations(schema, &compiled, instance, "", &mut errors);
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!(SchemaErrors { errors }))
+    }
+}
+
+/// Deserialize a document after checking it against the schema of `T`
+pub fn from_json_value<T: DeserializeOwned + JsonSchema>(value: JsonValue) -> Result<T> {
+    validate_json(&json_schema::<T>(), &value)?;
+    Ok(serde_json::from_value(value)?)
+}
+
+/// The variant an enum value names. Externally tagged enums are objects
+/// with the variant as only key, internally tagged ones name it in `t`.
+enum Tag<'a> {
+    External(&'a str),
+    Internal(&'a str),
+}
+
+fn tag(instance: &JsonValue) -> Option<Tag<'_>> {
+    let object = instance.as_object()?;
+    if let Some(t) = object.get("t").and_then(|t| t.as_str()) {
+        return Some(Tag::Internal(t));
+    }
+    match object.keys().collect::<Vec<&String>>()[..] {
+        [key] => Some(Tag::External(key)),
+        _ => None,
+    }
+}
+
+/// The schema of the enum variant named by `tag` anywhere in `schema`
+fn variant_schema<'a>(schema: &'a JsonValue, tag: &Tag) -> Option<&'a JsonValue> {
+    let matches = |branch: &JsonValue| match tag {
+        Tag::External(name) => {
+            branch["required"] == serde_json::json!([name])
+                && branch["properties"].get(*name).is_some()
+        }
+        Tag::Internal(name) => branch["properties"]["t"]["enum"] == serde_json::json!([name]),
+    };
+    match schema {
+        JsonValue::Object(object) => {
+            for key in ["oneOf", "anyOf"] {
+                if let Some(branch) = object
+                    .get(key)
+                    .and_then(|b| b.as_array())
+                    .and_then(|branches| branches.iter().find(|b| matches(b)))
+                {
+                    return Some(branch);
+                }
+            }
+            object.values().find_map(|v| variant_schema(v, tag))
+        }
+        JsonValue::Array(items) => items.iter().find_map(|v| variant_schema(v, tag)),
+        _ => None,
+    }
+}
+
+fn message(error: &ValidationError) -> String {
+    match (&error.kind, tag(&error.instance)) {
+        (ValidationErrorKind::OneOfNotValid | ValidationErrorKind::AnyOf, Some(tag)) => {
+            let name = match tag {
+                Tag::External(name) | Tag::Internal(name) => name,
+            };
+            format!("unknown variant \"{}\"", name)
+        }
+        (ValidationErrorKind::OneOfNotValid | ValidationErrorKind::AnyOf, None) => {
+            format!("{} does not match any of the allowed forms", error.instance)
+        }
+        _ => error.to_string(),
+    }
+}
+
+/// Validate `instance` and collect the errors. A value which matches no
+/// variant of an enum is checked against the variant it names instead,
+/// so the error points at the wrong field instead of the whole enum.
+fn collect_violations(
+    root: &JsonValue,
+    schema: &JSONSchema,
+    instance: &JsonValue,
+    prefix: &str,
+    out: &mut Vec<SchemaViolation>,
+) {
+    let errors = match schema.validate(instance) {
+        Ok(()) => return,
+        Err(errors) => errors,
+    };
+    for error in errors {
+        let path = format!("{}{}", prefix, error.instance_path);
+        if matches!(
+            error.kind,
+            ValidationErrorKind::OneOfNotValid | ValidationErrorKind::AnyOf
+        ) {
+            let variant = tag(&error.instance).and_then(|tag| variant_schema(root, &tag));
+            if let Some(variant) = variant {
+                let mut variant = variant.clone();
+                if let (Some(object), Some(definitions)) =
+                    (variant.as_object_mut(), root.get("definitions"))
+                {
+                    object.insert("definitions".into(), definitions.clone());
+                }
+                if let Ok(compiled) = JSONSchema::compile(&variant) {
+                    let found = out.len();
+                    collect_violations(root, &compiled, &error.instance, &path, out);
+                    if out.len() > found {
+                        continue;
+                    }
+                }
+            }
+        }
+        out.push(SchemaViolation {
+            path: if path.is_empty() { "/".into() } else { path },
+            message: message(&error),
+        });
+    }
+}
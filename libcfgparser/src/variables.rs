@@ -0,0 +1,71 @@
+use crate::{ParserError, Span};
+use anyhow::{anyhow, bail, Result};
+use std::collections::HashMap;
+use std::env;
+
+/// Split a `NAME=VALUE` definition as given on the command line with `-D`
+pub fn parse_variable_definition(s: &str) -> Result<(String, String)> {
+    let (name, value) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow!("variable definition {} is not in the form NAME=VALUE", s))?;
+    if !is_valid_name(name) {
+        bail!("{} is not a valid variable name", name);
+    }
+    Ok((name.to_string(), value.to_string()))
+}
+
+pub(crate) fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Replace every `${NAME}` in `raw` with the value of the variable and
+/// every `${env:NAME}` with the environment variable of the process.
+/// `$${` produces a literal `${`. Without variables the raw text is kept.
+pub(crate) fn interpolate(
+    raw: &str,
+    variables: Option<&HashMap<String, String>>,
+    span: &Span,
+) -> Result<String> {
+    let variables = if let Some(variables) = variables {
+        variables
+    } else {
+        return Ok(raw.to_string());
+    };
+    let mut out = String::new();
+    let mut rest = raw;
+    while let Some(idx) = rest.find("${") {
+        if rest[..idx].ends_with('$') {
+            out += &rest[..idx - 1];
+            out += "${";
+            rest = &rest[idx + 2..];
+            continue;
+        }
+        out += &rest[..idx];
+        let end = rest[idx..].find('}').ok_or_else(|| {
+            anyhow!(ParserError::InvalidVariable {
+                reference: rest[idx..].to_string(),
+                span: span.clone(),
+            })
+        })?;
+        let reference = &rest[idx + 2..idx + end];
+        let value = if let Some(name) = reference.strip_prefix("env:") {
+            env::var(name).ok()
+        } else if is_valid_name(reference) {
+            variables.get(reference).cloned()
+        } else {
+            return Err(anyhow!(ParserError::InvalidVariable {
+                reference: format!("${{{}}}", reference),
+                span: span.clone(),
+            }));
+        };
+        out += &value.ok_or_else(|| {
+            anyhow!(ParserError::UndefinedVariable {
+                name: reference.to_string(),
+                span: span.clone(),
+            })
+        })?;
+        rest = &rest[idx + end + 1..];
+    }
+    out += rest;
+    Ok(out)
+}
@@ -0,0 +1,74 @@
+//! Option definitions for ZFS properties, shared by the keywords of
+//! sysconfig and the installer so both accept the same values.
+
+use crate::{OptionDefinition, ValueType};
+
+fn string_options(names: &[&str]) -> Vec<OptionDefinition> {
+    names
+        .iter()
+        .map(|n| OptionDefinition::new(n, ValueType::String))
+        .collect()
+}
+
+fn on_off_options(names: &[&str]) -> Vec<OptionDefinition> {
+    names
+        .iter()
+        .map(|n| OptionDefinition::new(n, ValueType::Enum(vec!["on".into(), "off".into()])))
+        .collect()
+}
+
+/// The options a dataset accepts. All of them end up as ZFS properties.
+pub fn dataset_options() -> Vec<OptionDefinition> {
+    [
+        on_off_options(&[
+            "atime", "devices", "exec", "nbmand", "readonly", "setuid", "vscan", "utf8only",
+        ]),
+        vec![
+            OptionDefinition::new(
+                "copies",
+                ValueType::Enum(vec!["1".into(), "2".into(), "3".into()]),
+            ),
+            OptionDefinition::new("pbkdf2iters", ValueType::Integer),
+            OptionDefinition::new("property", ValueType::String)
+                .repeatable()
+                .with_description("Any ZFS property given as name=value"),
+        ],
+        string_options(&[
+            "aclinherit",
+            "aclmode",
+            "canmount",
+            "checksum",
+            "compression",
+            "encryption",
+            "keyformat",
+            "keylocation",
+            "filesystem_limit",
+            "special_small_blocks",
+            "mountpoint",
+            "primarycache",
+            "quota",
+            "snapshot_limit",
+            "recordsize",
+            "redundant_metadata",
+            "refquota",
+            "refreservation",
+            "reservation",
+            "secondarycache",
+            "sharesmb",
+            "sharenfs",
+            "logbias",
+            "snapdir",
+            "sync",
+            "xattr",
+            "casesensitivity",
+            "normalization",
+        ]),
+    ]
+    .concat()
+    .into_iter()
+    .map(|o| match o.description {
+        Some(_) => o,
+        None => o.with_description("ZFS property of the same name"),
+    })
+    .collect()
+}
@@ -0,0 +1,516 @@
+//! `#[derive(Keyword)]` for enums with one variant per keyword of the
+//! native configuration format. It implements `libcfgparser::FromKeyword`,
+//! generating the keyword definitions for the parser and the conversion of
+//! a parsed keyword into the variant from the same enum.
+//!
+//! ```ignore
+//! #[derive(Keyword)]
+//! enum Instruction {
+//!     #[keyword(name = "keyboard")]
+//!     SetKeymap(String),
+//!     #[keyword(name = "image", alias = "install_image")]
+//!     InstallImage {
+//!         #[keyword(arg)]
+//!         src: String,
+//!         #[keyword(option, default = "rpool")]
+//!         pool: String,
+//!     },
+//!     #[keyword(skip)]
+//!     Devfsadm,
+//! }
+//! ```
+//!
+//! Variant attributes:
+//!
+//! * `name = "..."` the keyword, defaults to the variant name in snake case
+//! * `alias = "..."` another name for the keyword, may be repeated
+//! * `skip` the variant has no keyword
+//! * `arguments = "1..3"` the number of arguments (`"2"`, `"1.."`) if it
+//!   differs from the argument fields
+//! * `options = "path"` a function returning more `OptionDefinition`s,
+//!   usually read by a `with` function
+//! * `children = "path"` a function returning the definitions of the
+//!   keywords allowed in a block
+//! * `argument_names = "name gateway"` names of the arguments for help
+//!   texts, defaults to the names of the argument fields
+//! * `example = "..."` a line showing how the keyword is used, may be repeated
+//!
+//! Doc comments of variants become the description of the keyword, doc
+//! comments of option fields the description of the option.
+//!
+//! Field attributes:
+//!
+//! * `arg` or `arg = N` the next or the Nth argument. `Option<String>` is an
+//!   optional argument and `Vec<String>` takes all remaining arguments.
+//!   Fields of tuple variants are arguments by default.
+//! * `option` or `option = "name"` the option named like the field. The
+//!   type of the field must implement `FromValue`, `Option<T>` makes the
+//!   option optional and `Vec<T>` repeatable. `bool` fields default to false.
+//!   Together with `arg` the value may be given either way.
+//! * `default = "..."` the value used if the option is not given
+//! * `value_type = "ip"` declare the option as `string`, `integer`,
+//!   `boolean`, `ip` or `path` instead of the type of the field
+//! * `with = "path"` compute the field with `fn(&Keyword) -> Result<T>`
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::spanned::Spanned;
+use syn::{
+    parse_macro_input, Attribute, Data, DeriveInput, Error, Expr, ExprLit, Fields, GenericArgument,
+    Lit, LitInt, LitStr, Meta, MetaNameValue, Path, PathArguments, Type,
+};
+
+#[proc_macro_derive(Keyword, attributes(keyword))]
+pub fn derive_keyword(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+#[derive(Default)]
+struct VariantAttrs {
+    name: Option<String>,
+    aliases: Vec<String>,
+    skip: bool,
+    arguments: Option<(usize, Option<usize>)>,
+    options: Option<Path>,
+    children: Option<Path>,
+    argument_names: Option<Vec<String>>,
+    examples: Vec<String>,
+}
+
+#[derive(Default)]
+struct FieldAttrs {
+    /// Some(None) is the next argument
+    arg: Option<Option<usize>>,
+    /// Some(None) is the option named like the field
+    option: Option<Option<String>>,
+    default: Option<String>,
+    value_type: Option<String>,
+    with: Option<Path>,
+}
+
+enum Shape<'a> {
+    Plain(&'a Type),
+    Optional(&'a Type),
+    Repeated(&'a Type),
+}
+
+/// Split `Option<T>` and `Vec<T>` into the wrapper and `T`
+fn shape(ty: &Type) -> Shape<'_> {
+    if let Type::Path(path) = ty {
+        if let Some(segment) = path.path.segments.last() {
+            if let PathArguments::AngleBracketed(args) = &segment.arguments {
+                if let Some(GenericArgument::Type(inner)) = args.args.first() {
+                    if segment.ident == "Option" {
+                        return Shape::Optional(inner);
+                    }
+                    if segment.ident == "Vec" {
+                        return Shape::Repeated(inner);
+                    }
+                }
+            }
+        }
+    }
+    Shape::Plain(ty)
+}
+
+fn is_bool(ty: &Type) -> bool {
+    matches!(ty, Type::Path(p) if p.path.is_ident("bool"))
+}
+
+fn snake_case(ident: &str) -> String {
+    let mut out = String::new();
+    for (i, c) in ident.chars().enumerate() {
+        if c.is_uppercase() {
+            if i > 0 {
+                out.push('_');
+            }
+            out.extend(c.to_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Parse `"2"`, `"1..3"` or `"1.."` into the minimum and maximum
+fn parse_range(lit: &LitStr) -> syn::Result<(usize, Option<usize>)> {
+    let value = lit.value();
+    let number = |s: &str| {
+        s.trim()
+            .parse::<usize>()
+            .map_err(|_| Error::new(lit.span(), "expected a number of arguments like 1..3"))
+    };
+    match value.split_once("..") {
+        Some((min, "")) => Ok((number(min)?, None)),
+        Some((min, max)) => Ok((number(min)?, Some(number(max)?))),
+        None => {
+            let n = number(&value)?;
+            Ok((n, Some(n)))
+        }
+    }
+}
+
+/// The doc comment of an item with lines joined into paragraphs
+fn doc_comment(attrs: &[Attribute]) -> Option<String> {
+    let mut paragraphs: Vec<String> = vec![String::new()];
+    for attr in attrs.iter().filter(|a| a.path().is_ident("doc")) {
+        let line = match &attr.meta {
+            Meta::NameValue(MetaNameValue {
+                value:
+                    Expr::Lit(ExprLit {
+                        lit: Lit::Str(lit), ..
+                    }),
+                ..
+            }) => lit.value(),
+            _ => continue,
+        };
+        let line = line.trim();
+        let last = paragraphs.last_mut().unwrap();
+        if line.is_empty() {
+            if !last.is_empty() {
+                paragraphs.push(String::new());
+            }
+        } else {
+            if !last.is_empty() {
+                last.push(' ');
+            }
+            last.push_str(line);
+        }
+    }
+    let doc = paragraphs
+        .into_iter()
+        .filter(|p| !p.is_empty())
+        .collect::<Vec<String>>()
+        .join("\n\n");
+    Some(doc).filter(|d| !d.is_empty())
+}
+
+fn parse_variant_attrs(attrs: &[Attribute]) -> syn::Result<VariantAttrs> {
+    let mut out = VariantAttrs::default();
+    for attr in attrs.iter().filter(|a| a.path().is_ident("keyword")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                out.name = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else if meta.path.is_ident("alias") {
+                out.aliases.push(meta.value()?.parse::<LitStr>()?.value());
+            } else if meta.path.is_ident("skip") {
+                out.skip = true;
+            } else if meta.path.is_ident("arguments") {
+                out.arguments = Some(parse_range(&meta.value()?.parse::<LitStr>()?)?);
+            } else if meta.path.is_ident("options") {
+                out.options = Some(meta.value()?.parse::<LitStr>()?.parse()?);
+            } else if meta.path.is_ident("children") {
+                out.children = Some(meta.value()?.parse::<LitStr>()?.parse()?);
+            } else if meta.path.is_ident("argument_names") {
+                let names = meta.value()?.parse::<LitStr>()?.value();
+                out.argument_names = Some(names.split_whitespace().map(String::from).collect());
+            } else if meta.path.is_ident("example") {
+                out.examples.push(meta.value()?.parse::<LitStr>()?.value());
+            } else {
+                return Err(meta.error("unknown keyword attribute"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(out)
+}
+
+fn parse_field_attrs(attrs: &[Attribute]) -> syn::Result<FieldAttrs> {
+    let mut out = FieldAttrs::default();
+    for attr in attrs.iter().filter(|a| a.path().is_ident("keyword")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("arg") {
+                out.arg = Some(if meta.input.peek(syn::Token![=]) {
+                    Some(meta.value()?.parse::<LitInt>()?.base10_parse()?)
+                } else {
+                    None
+                });
+            } else if meta.path.is_ident("option") {
+                out.option = Some(if meta.input.peek(syn::Token![=]) {
+                    Some(meta.value()?.parse::<LitStr>()?.value())
+                } else {
+                    None
+                });
+            } else if meta.path.is_ident("default") {
+                out.default = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else if meta.path.is_ident("value_type") {
+                out.value_type = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else if meta.path.is_ident("with") {
+                out.with = Some(meta.value()?.parse::<LitStr>()?.parse()?);
+            } else {
+                return Err(meta.error("unknown keyword attribute"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(out)
+}
+
+/// The generated pieces of one variant
+struct Variant {
+    names: Vec<String>,
+    /// Builds the definition, `aliases` holds the other names
+    definition: TokenStream2,
+    construct: TokenStream2,
+}
+
+fn optional(value: &Option<String>) -> TokenStream2 {
+    match value {
+        Some(value) => quote!(Some(String::from(#value))),
+        None => quote!(None),
+    }
+}
+
+fn expand_variant(variant: &syn::Variant, attrs: VariantAttrs) -> syn::Result<Variant> {
+    let ident = &variant.ident;
+    let name = attrs
+        .name
+        .clone()
+        .unwrap_or_else(|| snake_case(&ident.to_string()));
+
+    let tuple = matches!(variant.fields, Fields::Unnamed(_));
+    let mut next_arg = 0;
+    let mut min_arguments = 0;
+    let mut max_arguments = Some(0);
+    let mut options = vec![];
+    let mut argument_names: Vec<String> = vec![];
+    let mut values = vec![];
+    for (i, field) in variant.fields.iter().enumerate() {
+        let mut fattrs = parse_field_attrs(&field.attrs)?;
+        if tuple && fattrs.arg.is_none() && fattrs.option.is_none() && fattrs.with.is_none() {
+            fattrs.arg = Some(None);
+        }
+        let field_name = field
+            .ident
+            .as_ref()
+            .map(|i| i.to_string())
+            .unwrap_or_else(|| i.to_string());
+
+        let value = if let Some(with) = &fattrs.with {
+            quote!(#with(keyword)?)
+        } else {
+            let index = fattrs.arg.map(|index| {
+                let index = index.unwrap_or(next_arg);
+                next_arg = index + 1;
+                index
+            });
+            let option = fattrs
+                .option
+                .clone()
+                .map(|o| o.unwrap_or_else(|| field_name.clone()));
+            if index.is_none() && option.is_none() {
+                return Err(Error::new(
+                    field.span(),
+                    "field needs #[keyword(arg)], #[keyword(option)] or #[keyword(with = \"...\")]",
+                ));
+            }
+
+            if let Some(index) = index {
+                if argument_names.len() <= index {
+                    argument_names.resize(index + 1, String::from("arg"));
+                }
+                argument_names[index] = match &field.ident {
+                    Some(ident) => ident.to_string(),
+                    None => String::from("value"),
+                };
+                max_arguments = match (max_arguments, shape(&field.ty)) {
+                    (_, Shape::Repeated(_)) | (None, _) => None,
+                    (Some(max), _) => Some(max.max(index + 1)),
+                };
+                if option.is_none() && matches!(shape(&field.ty), Shape::Plain(_)) {
+                    min_arguments = min_arguments.max(index + 1);
+                }
+            }
+
+            if let Some(option) = &option {
+                let value_ty = match shape(&field.ty) {
+                    Shape::Plain(ty) | Shape::Optional(ty) | Shape::Repeated(ty) => ty,
+                };
+                let value_type = match fattrs.value_type.as_deref() {
+                    None => quote!(<#value_ty as ::libcfgparser::FromValue>::value_type()),
+                    Some("string") => quote!(::libcfgparser::ValueType::String),
+                    Some("integer") => quote!(::libcfgparser::ValueType::Integer),
+                    Some("boolean") => quote!(::libcfgparser::ValueType::Boolean),
+                    Some("ip") => quote!(::libcfgparser::ValueType::IpAddress),
+                    Some("path") => quote!(::libcfgparser::ValueType::Path),
+                    Some(other) => {
+                        return Err(Error::new(
+                            field.span(),
+                            format!("unknown value type {}", other),
+                        ))
+                    }
+                };
+                let default = match (&fattrs.default, shape(&field.ty)) {
+                    (Some(default), _) => Some(default.clone()),
+                    (None, Shape::Plain(ty)) if is_bool(ty) => Some("false".into()),
+                    _ => None,
+                };
+                let mut definition =
+                    quote!(::libcfgparser::OptionDefinition::new(#option, #value_type));
+                match shape(&field.ty) {
+                    Shape::Repeated(_) => definition = quote!(#definition.repeatable()),
+                    Shape::Plain(_) if default.is_none() && index.is_none() => {
+                        definition = quote!(#definition.required())
+                    }
+                    _ => {}
+                }
+                if let Some(default) = &default {
+                    definition = quote!(#definition.with_default(#default));
+                }
+                if let Some(description) = doc_comment(&field.attrs) {
+                    definition = quote!(#definition.with_description(#description));
+                }
+                options.push(definition);
+
+                let support = quote!(::libcfgparser::derive_support);
+                match (shape(&field.ty), index) {
+                    (Shape::Repeated(ty), _) => quote!(keyword.values_of::<#ty>(#option)?),
+                    (Shape::Optional(ty), None) => quote!(keyword.value_of::<#ty>(#option)?),
+                    (Shape::Optional(ty), Some(index)) => quote!(keyword
+                        .value_of::<#ty>(#option)?
+                        .or_else(|| #support::argument(keyword, #index))),
+                    (Shape::Plain(ty), None) => match &default {
+                        Some(default) => quote!(#support::or_default(
+                            keyword.value_of::<#ty>(#option)?,
+                            #option,
+                            #default
+                        )?),
+                        None => quote!(#support::required(
+                            keyword.value_of::<#ty>(#option)?,
+                            keyword,
+                            #option
+                        )?),
+                    },
+                    (Shape::Plain(ty), Some(index)) => quote!(#support::required_value(
+                        keyword
+                            .value_of::<#ty>(#option)?
+                            .or_else(|| #support::argument(keyword, #index)),
+                        keyword,
+                        #option
+                    )?),
+                }
+            } else {
+                let index = index.unwrap_or_default();
+                let support = quote!(::libcfgparser::derive_support);
+                match shape(&field.ty) {
+                    Shape::Plain(_) => quote!(#support::required_argument(keyword, #index)?),
+                    Shape::Optional(_) => quote!(#support::argument(keyword, #index)),
+                    Shape::Repeated(_) => quote!(#support::arguments(keyword, #index)),
+                }
+            }
+        };
+
+        values.push(match &field.ident {
+            Some(ident) => quote!(#ident: #value),
+            None => value,
+        });
+    }
+
+    let (min_arguments, max_arguments) = attrs.arguments.unwrap_or((min_arguments, max_arguments));
+    let max_arguments = match max_arguments {
+        Some(max) => quote!(Some(#max)),
+        None => quote!(None),
+    };
+    let extra_options = attrs
+        .options
+        .as_ref()
+        .map(|path| quote!(options.extend(#path());));
+    let children = match &attrs.children {
+        Some(path) => quote!(#path()),
+        None => quote!(::libcfgparser::derive_support::no_children()),
+    };
+    let argument_names = attrs.argument_names.clone().unwrap_or(argument_names);
+    let description = optional(&doc_comment(&variant.attrs));
+    let examples = &attrs.examples;
+    let definition = quote! {{
+        #[allow(unused_mut)]
+        let mut options: Vec<::libcfgparser::OptionDefinition> = vec![#(#options),*];
+        #extra_options
+        ::libcfgparser::KeywordDefinition {
+            min_arguments: #min_arguments,
+            max_arguments: #max_arguments,
+            options,
+            children: #children,
+            description: #description,
+            argument_names: vec![#(String::from(#argument_names)),*],
+            aliases,
+            examples: vec![#(String::from(#examples)),*],
+        }
+    }};
+
+    let construct = match &variant.fields {
+        Fields::Named(_) => quote!(Self::#ident { #(#values),* }),
+        Fields::Unnamed(_) => quote!(Self::#ident ( #(#values),* )),
+        Fields::Unit => quote!(Self::#ident),
+    };
+
+    let mut names = vec![name];
+    names.extend(attrs.aliases);
+    Ok(Variant {
+        names,
+        definition,
+        construct,
+    })
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let data = match &input.data {
+        Data::Enum(data) => data,
+        _ => {
+            return Err(Error::new(
+                input.span(),
+                "Keyword can only be derived for enums",
+            ))
+        }
+    };
+
+    let mut definitions = vec![];
+    let mut arms = vec![];
+    let mut all_names = vec![];
+    for variant in &data.variants {
+        let attrs = parse_variant_attrs(&variant.attrs)?;
+        if attrs.skip {
+            continue;
+        }
+        let Variant {
+            names,
+            definition,
+            construct,
+        } = expand_variant(variant, attrs)?;
+        for name in &names {
+            let aliases = names.iter().filter(|n| *n != name);
+            definitions.push(quote!((String::from(#name), {
+                let aliases: Vec<String> = vec![#(String::from(#aliases)),*];
+                #definition
+            })));
+        }
+        arms.push(quote!(#(#names)|* => Ok(#construct)));
+        all_names.extend(names);
+    }
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::libcfgparser::FromKeyword for #ident #ty_generics #where_clause {
+            fn keyword_definitions() -> Vec<(String, ::libcfgparser::KeywordDefinition)> {
+                vec![#(#definitions),*]
+            }
+
+            fn from_keyword(
+                keyword: &::libcfgparser::Keyword,
+            ) -> ::libcfgparser::derive_support::Result<Self> {
+                match keyword.name.as_str() {
+                    #(#arms,)*
+                    _ => Err(::libcfgparser::derive_support::unknown_keyword(
+                        keyword,
+                        &[#(#all_names),*],
+                    )),
+                }
+            }
+        }
+    })
+}
@@ -1,5 +1,70 @@
-use libcfgparser::KeywordDefinition;
+use crate::Instruction;
+use libcfgparser::{FromKeyword, KeywordDefinition, OptionDefinition, ValueType};
+use std::collections::HashMap;
 
+fn string_options(names: &[&str]) -> Vec<OptionDefinition> {
+    names
+        .iter()
+        .map(|n| OptionDefinition::new(n, ValueType::String))
+        .collect()
+}
+
+/// Options of zpool-create which are passed on as pool properties,
+/// together with every `--property="key=value"`
+pub(crate) fn pool_options() -> Vec<OptionDefinition> {
+    let mut options = string_options(&[
+        "autoexpand",
+        "autoreplace",
+        "autotrim",
+        "cachefile",
+        "comment",
+        "delegation",
+        "failmode",
+        "listsnapshots",
+        "multihost",
+        "version",
+    ])
+    .into_iter()
+    .map(|o| o.with_description("Pool property of the same name"))
+    .collect::<Vec<OptionDefinition>>();
+    options.push(
+        OptionDefinition::new("property", ValueType::String)
+            .repeatable()
+            .with_description("Any pool property given as name=value"),
+    );
+    options
+}
+
+/// The vdevs of a pool can be given as block, one vdev per child keyword.
+/// `disks` adds devices without redundancy.
+pub(crate) fn vdev_children() -> HashMap<String, KeywordDefinition> {
+    [
+        ("disks", "Devices without redundancy"),
+        ("mirror", "Devices mirroring each other"),
+        ("raidz", "Devices with single parity"),
+        ("raidz1", "Devices with single parity"),
+        ("raidz2", "Devices with double parity"),
+        ("raidz3", "Devices with triple parity"),
+    ]
+    .iter()
+    .map(|(name, description)| {
+        (
+            name.to_string(),
+            KeywordDefinition {
+                min_arguments: 1,
+                max_arguments: None,
+                options: vec![],
+                children: HashMap::new(),
+                description: Some(description.to_string()),
+                argument_names: vec!["devices".into()],
+                ..Default::default()
+            },
+        )
+    })
+    .collect()
+}
+
+/// The definitions of all keywords, generated from [`Instruction`]
 pub fn get_supported_keywords() -> Vec<(String, KeywordDefinition)> {
-    vec![]
+    Instruction::keyword_definitions()
 }
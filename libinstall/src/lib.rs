@@ -4,9 +4,11 @@ mod zfs;
 
 use crate::keywords::get_supported_keywords;
 use anyhow::{anyhow, bail, format_err, Context, Error, Result};
-use libcfgparser::Keyword;
+use libcfgparser::{Diagnostic, FromKeyword, Keyword, Loader};
+use libshadow::{parse_group_file, parse_passwd_file, GROUP_FILE, PASSWD_FILE};
 use log::{debug, info, trace};
 use reqwest::Client;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::cmp::min;
@@ -16,7 +18,7 @@ use std::fs::File;
 use std::io::Write;
 use std::path::Path;
 use std::sync::mpsc::{channel, Sender};
-use std::{fs, path, thread};
+use std::{path, thread};
 use thiserror::Error;
 use uuid::Uuid;
 
@@ -62,105 +64,175 @@ impl Display for ImageDownloadProgress {
 
 pub type InstructionsSet = Vec<Instruction>;
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Keyword, JsonSchema)]
 #[serde(rename_all = "snake_case", tag = "t")]
 pub enum Instruction {
+    /// Create a ZFS pool and a boot environment on it. The vdevs follow the
+    /// name like for zpool(8), a vdev type like mirror or raidz followed by
+    /// its devices, or are given in a block with one vdev per line.
+    #[keyword(
+        name = "zpool-create",
+        arguments = "1..",
+        argument_names = "name vdevs",
+        options = "keywords::pool_options",
+        children = "keywords::vdev_children",
+        example = "zpool-create --ashift=12 rpool mirror c1t0d0 c2t0d0",
+        example = "zpool-create rpool { mirror c1t0d0 c2t0d0; mirror c3t0d0 c4t0d0 }"
+    )]
     CreatePool {
+        #[keyword(arg)]
         name: String,
+        #[keyword(with = "pool_vdevs")]
         vdevs: Vec<VDEVConfiguration>,
+        /// Sector size of the devices as power of 2
+        #[keyword(option)]
         ashift: Option<i32>,
+        /// Create the pool with an EFI system partition
+        #[keyword(option, default = "true")]
         uefi: bool,
+        /// Name of the boot environment to create
+        #[keyword(option)]
         be_name: Option<String>,
+        #[keyword(with = "pool_properties")]
         pool_options: Option<Vec<(String, String)>>,
     },
+    /// Create a ZFS dataset. Every option is set as ZFS property of the
+    /// dataset, `--property=name=value` sets properties without an option.
+    #[keyword(
+        name = "ds",
+        alias = "dataset",
+        options = "libcfgparser::zfs::dataset_options",
+        example = "ds --compression=lz4 --mountpoint=/export rpool/export"
+    )]
     CreateDataset {
+        #[keyword(arg)]
         name: String,
         #[serde(flatten)]
+        #[keyword(with = "dataset_properties")]
         properties: HashMap<String, Value>,
     },
+    /// Create a boot environment on the pool
+    #[keyword(name = "create_be", example = "create_be rpool openindiana")]
+    CreateBootEnvironment {
+        #[keyword(arg)]
+        pool_name: String,
+        #[keyword(arg)]
+        name: Option<String>,
+    },
+    /// Install the system image from the given URL or file into the pool
+    #[keyword(
+        name = "image",
+        alias = "install_image",
+        example = "image --pool=rpool https://example.com/images/openindiana.zfs.xz"
+    )]
     InstallImage {
+        #[keyword(arg)]
         src: String,
+        /// The pool to install into
+        #[keyword(option, default = "rpool")]
         pool: String,
     },
-    Include {
-        name: String,
-    },
-    MakeBootable {
-        pool: String,
-        be_name: String,
-    },
+    #[keyword(skip)]
+    Include { name: String },
+    #[keyword(skip)]
+    MakeBootable { pool: String, be_name: String },
+    /// Create a file in the installed system. The contents are copied from
+    /// a file or given as second argument, usually a heredoc.
+    #[keyword(
+        name = "file",
+        example = "file --mode=0600 /etc/motd <<EOF\nWelcome\nEOF"
+    )]
     EnsureFile {
+        /// File on the installation media to copy
+        #[keyword(option)]
         src: Option<String>,
+        /// File in the installed image to copy
+        #[keyword(option)]
         image_src: Option<String>,
+        /// The contents of the file
+        #[keyword(option, arg = 1)]
         contents: Option<String>,
+        #[keyword(arg = 0)]
         file: String,
+        /// User owning the file
+        #[keyword(option, default = "root")]
         owner: String,
+        /// Group owning the file
+        #[keyword(option, default = "root")]
         group: String,
+        /// Permissions of the file in octal
+        #[keyword(option, default = "0644")]
         mode: String,
     },
+    /// Create a file in the installed system from a template. The template is
+    /// read from a file or given as second argument, usually a heredoc.
+    #[keyword(name = "template")]
     TemplateFile {
+        /// Template on the installation media
+        #[keyword(option)]
         src: Option<String>,
+        /// The template
+        #[keyword(option, arg = 1)]
         contents: Option<String>,
+        #[keyword(arg = 0)]
         file: String,
+        /// User owning the file
+        #[keyword(option, default = "root")]
         owner: String,
+        /// Group owning the file
+        #[keyword(option, default = "root")]
         group: String,
+        /// Permissions of the file in octal
+        #[keyword(option, default = "0644")]
         mode: String,
     },
+    #[keyword(skip)]
     EnsureSymlink {
         link: String,
         target: String,
         owner: String,
         group: String,
     },
+    #[keyword(skip)]
     EnsureDir {
         dir: String,
         owner: String,
         group: String,
         mode: String,
     },
-    RemoveFiles {
-        dir: String,
-    },
+    #[keyword(skip)]
+    RemoveFiles { dir: String },
+    #[keyword(skip)]
     Devfsadm,
-    Shadow {
-        username: String,
-        password: String,
-    },
+    #[keyword(skip)]
+    Shadow { username: String, password: String },
+    #[keyword(skip)]
     AssembleFiles {
         dir: String,
         output: String,
         prefix: String,
     },
-    PkgImageCreate {
-        publisher: String,
-        uri: String,
-    },
-    PkgInstall {
-        pkgs: Vec<String>,
-    },
-    PkgUninstall {
-        pkgs: Vec<String>,
-    },
-    PkgSetProperty {
-        name: String,
-        value: String,
-    },
-    PkgChangeVariant {
-        variant: String,
-        value: String,
-    },
+    #[keyword(skip)]
+    PkgImageCreate { publisher: String, uri: String },
+    #[keyword(skip)]
+    PkgInstall { pkgs: Vec<String> },
+    #[keyword(skip)]
+    PkgUninstall { pkgs: Vec<String> },
+    #[keyword(skip)]
+    PkgSetProperty { name: String, value: String },
+    #[keyword(skip)]
+    PkgChangeVariant { variant: String, value: String },
+    #[keyword(skip)]
     PkgSetMediator {
         implementation: Option<String>,
         version: Option<String>,
         mediator: String,
     },
-    PkgUnsetMediator {
-        mediator: String,
-    },
-    PkgChangeFacet {
-        facet: String,
-        value: Option<bool>,
-    },
+    #[keyword(skip)]
+    PkgUnsetMediator { mediator: String },
+    #[keyword(skip)]
+    PkgChangeFacet { facet: String, value: Option<bool> },
+    #[keyword(skip)]
     PkgSetPublisher {
         publisher: String,
         uri: String,
@@ -168,15 +240,17 @@ pub enum Instruction {
         sticky: bool,
         search_first: bool,
     },
-    PkgUnsetPublisher {
-        publisher: String,
-    },
+    #[keyword(skip)]
+    PkgUnsetPublisher { publisher: String },
+    #[keyword(skip)]
     PkgPurgeHistory,
+    #[keyword(skip)]
     PkgRebuildIndex,
+    #[keyword(skip)]
     SeedSmf,
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub enum VDEVType {
     Empty,
     Mirror,
@@ -203,7 +277,7 @@ impl Default for VDEVType {
     }
 }
 
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct VDEVConfiguration {
     pub vdev_type: VDEVType,
     pub devices: Vec<String>,
@@ -211,150 +285,141 @@ pub struct VDEVConfiguration {
 
 #[derive(Error, Debug)]
 enum InstructionError {
-    #[error("keyword {0} is not known")]
-    UnknownInstruction(String),
+    #[error("property {0} is not in the form key=value")]
+    InvalidProperty(String),
+    #[error("keyword {0} needs one of {1}")]
+    MissingSource(String, String),
+    #[error("pool {0} has no devices")]
+    MissingDevices(String),
+    #[error("mode {0} is not an octal number")]
+    InvalidMode(String),
 }
 
-pub fn parse_keywords(keywords: Vec<Keyword>) -> Result<InstructionsSet> {
-    let mut set = InstructionsSet::new();
-    for c in keywords {
-        match c.name.as_str() {
-            "zpool-create" => {
-                let mut vdevs: Vec<VDEVConfiguration> = vec![];
-                let pool_options = if let Some(opts) = c.options.clone() {
-                    Some(
-                        opts.into_iter()
-                            .filter(|(k, _)| k != "ashift" || k != "uefi")
-                            .map(|(k, v)| (k, v))
-                            .collect::<Vec<(String, String)>>(),
-                    )
-                } else {
-                    None
-                };
-
-                let (ashift, uefi, be_name) = if let Some(opts) = c.options {
-                    (
-                        if opts.contains_key("ashift") {
-                            let ashift = opts["ashift"].clone();
-                            Some(ashift.parse::<i32>().context("ashift is not an integer")?)
-                        } else {
-                            None
-                        },
-                        if opts.contains_key("uefi") {
-                            true
-                        } else {
-                            false
-                        },
-                        if opts.contains_key("be_name") {
-                            Some(opts["be_name"].clone())
-                        } else {
-                            None
-                        },
-                    )
-                } else {
-                    (None, true, None)
-                };
-
-                let mut name = String::new();
-                let mut vdev_config = VDEVConfiguration::default();
-                for (i, opt) in c.arguments.into_iter().enumerate() {
-                    if i == 0 {
-                        name = opt.clone();
-                        continue;
-                    }
-
-                    match opt.as_str() {
-                        "mirror" => {
-                            if vdev_config.vdev_type != VDEVType::Empty {
-                                vdevs.push(vdev_config.clone());
-                                vdev_config = VDEVConfiguration::default();
-                            }
-                            vdev_config.vdev_type = VDEVType::Mirror
-                        }
-                        "raidz" | "raidz1" => {
-                            if vdev_config.vdev_type != VDEVType::Empty {
-                                vdevs.push(vdev_config.clone());
-                                vdev_config = VDEVConfiguration::default();
-                            }
-                            vdev_config.vdev_type = VDEVType::RaidZ1
-                        }
-                        "raidz2" => {
-                            if vdev_config.vdev_type != VDEVType::Empty {
-                                vdevs.push(vdev_config.clone());
-                                vdev_config = VDEVConfiguration::default();
-                            }
-                            vdev_config.vdev_type = VDEVType::RaidZ2
-                        }
-                        "raidz3" => {
-                            if vdev_config.vdev_type != VDEVType::Empty {
-                                vdevs.push(vdev_config.clone());
-                                vdev_config = VDEVConfiguration::default();
-                            }
-                            vdev_config.vdev_type = VDEVType::RaidZ3
-                        }
-                        _ => vdev_config.devices.push(opt),
-                    }
-                }
-                vdevs.push(vdev_config.clone());
-                set.push(Instruction::CreatePool {
-                    name,
-                    vdevs,
-                    ashift,
-                    uefi,
-                    be_name,
-                    pool_options,
-                });
-            }
-            "create_be" => {
-                if c.arguments.len() == 1 {
-                    set.push(Instruction::CreateBootEnvironment {
-                        pool_name: c.arguments[0].clone(),
-                        name: None,
-                    });
-                } else {
-                    set.push(Instruction::CreateBootEnvironment {
-                        pool_name: c.arguments[0].clone(),
-                        name: Some(c.arguments[1].clone()),
-                    });
-                }
-            }
-            "image" | "install_image" => {
-                let pool_name = if let Some(opts) = c.options {
-                    if opts.contains_key("pool") {
-                        opts["pool"].clone()
-                    } else {
-                        "rpool".into()
-                    }
-                } else {
-                    "rpool".into()
-                };
-                set.push(Instruction::InstallImage {
-                    src: c.arguments[0].clone(),
-                    pool,
-                });
-            }
-            "ds" | "dataset" => {
-                let opts: HashMap<String, Value> = if let Some(opts) = c.options {
-                    opts.into_iter()
-                        .map(|(k, v)| (k, Value::String(v)))
-                        .collect()
-                } else {
-                    HashMap::new()
-                };
-                set.push(Instruction::CreateDataset {
-                    name: c.arguments[0].clone(),
-                    properties: opts,
-                });
+/// Collect the options of a keyword as ZFS properties in the order they
+/// were given. `--property="key=value"` can be repeated for properties
+/// which have no option of their own.
+fn keyword_properties(c: &Keyword, skip: &[&str]) -> Result<Vec<(String, String)>> {
+    let mut properties = vec![];
+    for opt in &c.options {
+        if skip.contains(&opt.name.as_str()) {
+            continue;
+        }
+        if opt.name == "property" {
+            let raw = opt.value.to_string();
+            if let Some((key, value)) = raw.split_once('=') {
+                properties.push((key.to_string(), value.to_string()));
+            } else {
+                return Err(anyhow!(Diagnostic::new(
+                    &opt.span,
+                    InstructionError::InvalidProperty(raw)
+                )));
             }
-            _ => {
-                return Err(anyhow!(InstructionError::UnknownInstruction(
-                    c.name.clone()
-                )))
+        } else {
+            properties.push((opt.name.clone(), opt.value.to_string()));
+        }
+    }
+    Ok(properties)
+}
+
+fn pool_properties(c: &Keyword) -> Result<Option<Vec<(String, String)>>> {
+    let properties = keyword_properties(c, &["ashift", "uefi", "be_name"])?;
+    Ok(if properties.is_empty() {
+        None
+    } else {
+        Some(properties)
+    })
+}
+
+fn dataset_properties(c: &Keyword) -> Result<HashMap<String, Value>> {
+    Ok(keyword_properties(c, &[])?
+        .into_iter()
+        .map(|(k, v)| (k, Value::String(v)))
+        .collect())
+}
+
+fn vdev_type(name: &str) -> Option<VDEVType> {
+    match name {
+        "mirror" => Some(VDEVType::Mirror),
+        "raidz" | "raidz1" => Some(VDEVType::RaidZ1),
+        "raidz2" => Some(VDEVType::RaidZ2),
+        "raidz3" => Some(VDEVType::RaidZ3),
+        _ => None,
+    }
+}
+
+/// The vdevs of a pool from the arguments after the pool name, where
+/// mirror or raidz start a new vdev, and from the children of a block
+fn pool_vdevs(c: &Keyword) -> Result<Vec<VDEVConfiguration>> {
+    let mut vdevs: Vec<VDEVConfiguration> = vec![];
+    let mut vdev_config = VDEVConfiguration::default();
+    for arg in c.arguments.iter().skip(1) {
+        if let Some(vdev_type) = vdev_type(&arg.value) {
+            if vdev_config.vdev_type != VDEVType::Empty {
+                vdevs.push(vdev_config);
+                vdev_config = VDEVConfiguration::default();
             }
+            vdev_config.vdev_type = vdev_type
+        } else {
+            vdev_config.devices.push(arg.value.clone())
         }
     }
+    if vdev_config.vdev_type != VDEVType::Empty || !vdev_config.devices.is_empty() {
+        vdevs.push(vdev_config);
+    }
+    // vdevs given as block e.g. zpool-create rpool { mirror c1t0d0 c2t0d0 }
+    for child in &c.children {
+        vdevs.push(VDEVConfiguration {
+            vdev_type: vdev_type(&child.name).unwrap_or_default(),
+            devices: child.arguments.iter().map(|a| a.value.clone()).collect(),
+        });
+    }
+    if vdevs.is_empty() {
+        let name = c.arguments.first().map(|a| a.value.clone());
+        return Err(anyhow!(Diagnostic::new(
+            &c.span,
+            InstructionError::MissingDevices(name.unwrap_or_default())
+        )));
+    }
+    Ok(vdevs)
+}
+
+/// Files need something to take their contents from
+fn check_file_source(c: &Keyword, instruction: &Instruction) -> Result<()> {
+    let sources = match instruction {
+        Instruction::EnsureFile {
+            src: None,
+            image_src: None,
+            contents: None,
+            ..
+        } => "src, image_src or contents",
+        Instruction::TemplateFile {
+            src: None,
+            contents: None,
+            ..
+        } => "src or contents",
+        _ => return Ok(()),
+    };
+    Err(anyhow!(Diagnostic::new(
+        &c.span,
+        InstructionError::MissingSource(c.name.clone(), sources.into())
+    )))
+}
+
+pub fn parse_keywords(keywords: Vec<Keyword>) -> Result<InstructionsSet> {
+    keywords
+        .iter()
+        .map(|c| {
+            let instruction = Instruction::from_keyword(c)?;
+            check_file_source(c, &instruction)?;
+            Ok(instruction)
+        })
+        .collect()
+}
 
-    Ok(set)
+/// The JSON schema of [`InstructionsSet`] which JSON, YAML and TOML
+/// configurations are checked against
+pub fn instructions_schema() -> Value {
+    libcfgparser::json_schema::<InstructionsSet>()
 }
 
 pub fn read_instructions_file<P: AsRef<Path>>(path: P) -> Result<InstructionsSet> {
@@ -367,32 +432,7 @@ pub fn read_instructions_file<P: AsRef<Path>>(path: P) -> Result<InstructionsSet
     }
 
     debug!(target: "libinstall", "Parsing config file");
-    if let Some(ext) = path.extension() {
-        if ext == "json" {
-            let f = File::open(path)?;
-            debug!(target: "libinstall", "Parsing JSON config");
-            let set: InstructionsSet = serde_json::from_reader(f)?;
-            Ok(set)
-        } else if ext == "yml" || ext == "yaml" {
-            let f = File::open(path)?;
-            debug!(target: "libinstall", "Parsing YAML config");
-            let set: InstructionsSet = serde_yaml::from_reader(f)?;
-            Ok(set)
-        } else if ext == "ron" {
-            let file_content = fs::read_to_string(path)?;
-            debug!(target: "libinstall", "Parsing RON config");
-            let set: InstructionsSet = ron::from_str(&file_content)?;
-            Ok(set)
-        } else {
-            debug!(target: "libinstall", "Parsing Custom kickstart format");
-            let keywords = parser.parse_config_file(path)?;
-            parse_keywords(keywords)
-        }
-    } else {
-        debug!(target: "libinstall", "Parsing Custom kickstart format");
-        let keywords = parser.parse_config_file(path)?;
-        parse_keywords(keywords)
-    }
+    Loader::new(&parser, parse_keywords).load_file(path)
 }
 
 /*
@@ -400,36 +440,114 @@ pub fn read_instructions_file<P: AsRef<Path>>(path: P) -> Result<InstructionsSet
  */
 const ROOT: u32 = 0;
 
+/*
+ * The boot environment being installed is mounted here, see create_be().  Paths
+ * of the file instructions are relative to it.
+ */
+static TARGET_ROOT: &str = "/a";
+
 /*
  * We cannot correctly use the name service switch to translate user IDs for use
  * in the target image, as the database within the target may not match the
- * build system.  For now, assume we only need to deal with a handful of
- * hard-coded user names.
+ * build system.  Read the passwd and group files of the image mounted at root
+ * instead.
  */
-fn translate_uid(user: &str) -> Result<u32> {
-    Ok(match user {
-        "root" => ROOT,
-        "daemon" => 1,
-        "bin" => 2,
-        "sys" => 3,
-        "adm" => 4,
-        n => bail!("unknown user \"{}\"", n),
-    })
+fn translate_uid<P: AsRef<Path>>(root: P, user: &str) -> Result<u32> {
+    parse_passwd_file(&read_image_file(root, PASSWD_FILE)?)?
+        .get_entry(user)
+        .map(|e| e.uid)
+        .ok_or_else(|| anyhow!("unknown user \"{}\"", user))
 }
 
 /*
  * The situation is the same for group IDs as it is for user IDs.  See comments
  * for translate_uid().
  */
-fn translate_gid(group: &str) -> Result<u32> {
-    Ok(match group {
-        "root" => ROOT,
-        "other" => 1,
-        "bin" => 2,
-        "sys" => 3,
-        "adm" => 4,
-        n => bail!("unknown group \"{}\"", n),
-    })
+fn translate_gid<P: AsRef<Path>>(root: P, group: &str) -> Result<u32> {
+    parse_group_file(&read_image_file(root, GROUP_FILE)?)?
+        .get_entry(group)
+        .map(|e| e.gid)
+        .ok_or_else(|| anyhow!("unknown group \"{}\"", group))
+}
+
+/*
+ * Only the file needed for a lookup is read, /etc/shadow is not readable
+ * for everyone and has nothing to do with IDs.
+ */
+fn read_image_file<P: AsRef<Path>>(root: P, file: &str) -> Result<String> {
+    let path = root.as_ref().join(file.trim_start_matches('/'));
+    std::fs::read_to_string(&path).with_context(|| format!("could not read {}", path.display()))
+}
+
+fn target_path(path: &str) -> path::PathBuf {
+    Path::new(TARGET_ROOT).join(path.trim_start_matches('/'))
+}
+
+fn parse_mode(mode: &str) -> Result<u32> {
+    u32::from_str_radix(mode, 8).map_err(|_| anyhow!(InstructionError::InvalidMode(mode.into())))
+}
+
+/*
+ * Owner and group are names in the target image, they are looked up in its
+ * passwd and group files.  A relative src is taken from the bundle.
+ */
+fn ensure_file<P: AsRef<Path>>(
+    bundle_path: P,
+    src: Option<String>,
+    image_src: Option<String>,
+    contents: Option<String>,
+    file: &str,
+    owner: &str,
+    group: &str,
+    mode: &str,
+) -> Result<()> {
+    let dst = target_path(file);
+    let owner = translate_uid(TARGET_ROOT, owner)?;
+    let group = translate_gid(TARGET_ROOT, group)?;
+    let mode = parse_mode(mode)?;
+    match (src, image_src, contents) {
+        (Some(src), _, _) => ensure::file(
+            bundle_path.as_ref().join(src),
+            &dst,
+            owner,
+            group,
+            mode,
+            ensure::Create::Always,
+        ),
+        (None, Some(image_src), _) => ensure::file(
+            target_path(&image_src),
+            &dst,
+            owner,
+            group,
+            mode,
+            ensure::Create::Always,
+        ),
+        (None, None, Some(contents)) => {
+            ensure::filestr(&contents, &dst, owner, group, mode, ensure::Create::Always)
+        }
+        (None, None, None) => bail!("file {} has no source", file),
+    }?;
+    Ok(())
+}
+
+fn ensure_dir(dir: &str, owner: &str, group: &str, mode: &str) -> Result<()> {
+    ensure::directory(
+        target_path(dir),
+        translate_uid(TARGET_ROOT, owner)?,
+        translate_gid(TARGET_ROOT, group)?,
+        parse_mode(mode)?,
+    )?;
+    Ok(())
+}
+
+fn ensure_symlink(link: &str, target: &str, owner: &str, group: &str) -> Result<()> {
+    ensure::symlink(
+        target_path(link),
+        target,
+        translate_uid(TARGET_ROOT, owner)?,
+        translate_gid(TARGET_ROOT, group)?,
+    )?;
+    Ok(())
 }
 
 fn installer_pool_name(name: &str) -> String {
@@ -451,6 +569,9 @@ pub fn apply_instruction<P: AsRef<Path>>(bundle_path: P, instruction: Instructio
             pool_options,
         } => create_pool(&name, vdevs, ashift, uefi, be_name, pool_options),
         Instruction::CreateDataset { name, properties } => create_dataset(&name, properties),
+        Instruction::CreateBootEnvironment { pool_name, name } => {
+            create_be(pool_name, name).map(|_| ())
+        }
         Instruction::InstallImage { src, pool } => install_image(&src, &pool),
         Instruction::Include { name } => include_file(&bundle_path, name),
         Instruction::MakeBootable { pool, be_name } => make_bootable(&pool, &be_name),
@@ -462,10 +583,29 @@ pub fn apply_instruction<P: AsRef<Path>>(bundle_path: P, instruction: Instructio
             owner,
             group,
             mode,
-        } => {}
+        } => ensure_file(
+            &bundle_path,
+            src,
+            image_src,
+            contents,
+            &file,
+            &owner,
+            &group,
+            &mode,
+        ),
         Instruction::TemplateFile { .. } => {}
-        Instruction::EnsureSymlink { .. } => {}
-        Instruction::EnsureDir { .. } => {}
+        Instruction::EnsureSymlink {
+            link,
+            target,
+            owner,
+            group,
+        } => ensure_symlink(&link, &target, &owner, &group),
+        Instruction::EnsureDir {
+            dir,
+            owner,
+            group,
+            mode,
+        } => ensure_dir(&dir, &owner, &group, &mode),
         Instruction::RemoveFiles { .. } => {}
         Instruction::Devfsadm => {}
         Instruction::Shadow { .. } => {}
@@ -728,8 +868,8 @@ fn create_be(pool_name: String, name: Option<String>) -> Result<String, Error> {
     /*
      * Mount that BE:
      */
-    ensure::directory("/a", ROOT, ROOT, 0o755)?;
-    illumos::run(&["/sbin/mount", "-F", "zfs", &beds, "/a"], None)?;
+    ensure::directory(TARGET_ROOT, ROOT, ROOT, 0o755)?;
+    illumos::run(&["/sbin/mount", "-F", "zfs", &beds, TARGET_ROOT], None)?;
 
     /*
      * Set some BE properties...
@@ -0,0 +1,299 @@
+use crate::{days_since_epoch, optional_field, ShadowEntry};
+use chrono::{NaiveDate, TimeDelta};
+use serde::Serialize;
+use std::fmt::{Display, Formatter};
+
+/// 1970-01-01, day 0 of the date fields in /etc/shadow
+fn epoch() -> NaiveDate {
+    NaiveDate::from_ymd_opt(1970, 1, 1).unwrap()
+}
+
+/// The date of a day count of /etc/shadow. None if it is out of range.
+pub fn date_from_days(days: i64) -> Option<NaiveDate> {
+    epoch().checked_add_signed(TimeDelta::try_days(days)?)
+}
+
+/// The day count of /etc/shadow for `date`
+pub fn days_from_date(date: NaiveDate) -> i64 {
+    (date - epoch()).num_days()
+}
+
+/// Today in UTC, like passwd(1) records it
+pub fn today() -> NaiveDate {
+    date_from_days(days_since_epoch()).unwrap_or_else(epoch)
+}
+
+/// The password state reported by `passwd -s`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum PasswordState {
+    /// PS, the account has a password
+    #[serde(rename = "PS")]
+    Password,
+    /// LK, the account is locked
+    #[serde(rename = "LK")]
+    Locked,
+    /// NP, the account has no password
+    #[serde(rename = "NP")]
+    NoPassword,
+    /// NL, the account can not log in but can run cron jobs
+    #[serde(rename = "NL")]
+    NoLogin,
+}
+
+impl Display for PasswordState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PasswordState::Password => write!(f, "PS"),
+            PasswordState::Locked => write!(f, "LK"),
+            PasswordState::NoPassword => write!(f, "NP"),
+            PasswordState::NoLogin => write!(f, "NL"),
+        }
+    }
+}
+
+/// The password and aging state of an account at a given day. The
+/// Display implementation prints the line of `passwd -s`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AccountStatus {
+    pub username: String,
+    pub state: PasswordState,
+    /// Day of the last password change, None if it is unknown or the
+    /// password has to be changed at the next login
+    pub last_changed: Option<NaiveDate>,
+    pub min_days: Option<i64>,
+    pub max_days: Option<i64>,
+    pub warn_days: Option<i64>,
+    pub inactive_days: Option<i64>,
+    /// Last day the password is valid
+    pub password_expires: Option<NaiveDate>,
+    /// Day the account expires
+    pub account_expires: Option<NaiveDate>,
+    /// The password has to be changed at the next login
+    pub must_change_password: bool,
+    pub password_expired: bool,
+    pub account_expired: bool,
+    /// The password expired longer than the inactive days ago, the
+    /// account is locked
+    pub inactive: bool,
+    /// The password expires within the warn days
+    pub warn: bool,
+}
+
+impl Display for AccountStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}  {}", self.username, self.state)?;
+        match self.last_changed {
+            Some(date) => write!(f, "  {}", date.format("%m/%d/%y"))?,
+            None if self.must_change_password => write!(f, "  00/00/00")?,
+            None => return Ok(()),
+        }
+        for days in [self.min_days, self.max_days, self.warn_days] {
+            match days {
+                Some(days) => write!(f, "  {}", days)?,
+                None => break,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ShadowEntry {
+    /// The state `passwd -s` reports for the entry
+    pub fn password_state(&self) -> PasswordState {
+        if self.password_locked {
+            PasswordState::Locked
+        } else if self.no_login {
+            PasswordState::NoLogin
+        } else if self.no_password || self.password_hash.is_empty() {
+            PasswordState::NoPassword
+        } else {
+            PasswordState::Password
+        }
+    }
+
+    /// Whether the password has to be changed at the next login, see
+    /// [`ShadowEntry::expire_password`]
+    pub fn must_change_password(&self) -> bool {
+        self.password_last_changed == 0
+    }
+
+    /// Day of the last password change. None if it is not recorded or
+    /// the password has to be changed at the next login.
+    pub fn last_changed_on(&self) -> Option<NaiveDate> {
+        self.last_changed()
+            .filter(|days| *days > 0)
+            .and_then(date_from_days)
+    }
+
+    pub fn set_last_changed_on(&mut self, date: Option<NaiveDate>) {
+        self.set_last_changed(date.map(days_from_date));
+    }
+
+    /// Day the account expires
+    pub fn expires_on(&self) -> Option<NaiveDate> {
+        self.expire_date()
+            .filter(|days| *days > 0)
+            .and_then(date_from_days)
+    }
+
+    pub fn set_expires_on(&mut self, date: Option<NaiveDate>) {
+        self.set_expire_date(date.map(days_from_date));
+    }
+
+    /// Last day the password is valid, from the last change and the
+    /// maximum days
+    pub fn password_expires_on(&self) -> Option<NaiveDate> {
+        let last_changed = self.last_changed().filter(|days| *days > 0)?;
+        date_from_days(last_changed + optional_field(self.max)?)
+    }
+
+    /// The status of the account at `today` like `passwd -s` shows it.
+    /// The day is passed in so audits can be done for any day.
+    /// ```
+    /// use chrono::NaiveDate;
+    /// use libshadow::{parse_shadow_file, PasswordState};
+    ///
+    /// let shadow = parse_shadow_file("jdoe:$5$salt$hash:19000:0:90:7:::").unwrap();
+    /// let jdoe = shadow.get_entry("jdoe").unwrap();
+    /// let status = jdoe.status(NaiveDate::from_ymd_opt(2022, 4, 3).unwrap());
+    /// assert_eq!(status.state, PasswordState::Password);
+    /// assert!(status.warn && !status.password_expired);
+    /// assert_eq!(status.to_string(), "jdoe  PS  01/08/22  0  90  7");
+    /// ```
+    pub fn status(&self, today: NaiveDate) -> AccountStatus {
+        let password_expires = self.password_expires_on();
+        let password_expired =
+            self.must_change_password() || password_expires.is_some_and(|d| today > d);
+        let inactive = match (password_expires, self.inactive_days()) {
+            (Some(expires), Some(days)) if days > 0 => (today - expires).num_days() > days,
+            _ => false,
+        };
+        let warn = match (password_expires, self.warn_days()) {
+            (Some(expires), Some(days)) if days > 0 && !password_expired => {
+                (expires - today).num_days() < days
+            }
+            _ => false,
+        };
+        let account_expires = self.expires_on();
+
+        AccountStatus {
+            username: self.username.clone(),
+            state: self.password_state(),
+            last_changed: self.last_changed_on(),
+            min_days: self.min_days(),
+            max_days: self.max_days(),
+            warn_days: self.warn_days(),
+            inactive_days: self.inactive_days(),
+            password_expires,
+            account_expires,
+            must_change_password: self.must_change_password(),
+            password_expired,
+            account_expired: account_expires.is_some_and(|d| today >= d),
+            inactive,
+            warn,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{parse_shadow_file, PasswordState};
+    use chrono::NaiveDate;
+
+    static AGING_SHADOW: &str = r#"root:$5$salt$hash:19000::::::
+daemon:NP:6445::::::
+nuucp::6445::::::
+listen:*LK*:::::::
+jenkins:NL:18676::::::
+expired:$5$salt$hash:19000:1:30:7:10::
+forced:$5$salt$hash:0::::::
+gone:$5$salt$hash:19000:::::19050:"#;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn status_example() {
+        let shadow_file = parse_shadow_file(AGING_SHADOW).unwrap();
+        assert!(shadow_file.warnings().is_empty());
+        let today = date(2022, 3, 1);
+        let status = |name: &str| shadow_file.get_entry(name).unwrap().status(today);
+
+        let states: Vec<PasswordState> = shadow_file
+            .entries()
+            .iter()
+            .map(|e| e.password_state())
+            .collect();
+        assert_eq!(
+            &states[..5],
+            &[
+                PasswordState::Password,
+                PasswordState::NoPassword,
+                PasswordState::NoPassword,
+                PasswordState::Locked,
+                PasswordState::NoLogin
+            ]
+        );
+
+        let root = status("root");
+        assert_eq!(root.last_changed, Some(date(2022, 1, 8)));
+        assert_eq!(root.password_expires, None);
+        assert!(!root.password_expired && !root.account_expired && !root.warn);
+        assert_eq!(root.to_string(), "root  PS  01/08/22");
+        assert_eq!(status("listen").to_string(), "listen  LK");
+
+        // Expired on 2022-02-07, inactive 10 days later
+        let expired = status("expired");
+        assert_eq!(expired.password_expires, Some(date(2022, 2, 7)));
+        assert!(expired.password_expired && expired.inactive && !expired.warn);
+        let entry = shadow_file.get_entry("expired").unwrap();
+        let early = entry.status(date(2022, 2, 15));
+        assert!(early.password_expired && !early.inactive);
+        assert!(entry.status(date(2022, 2, 1)).warn);
+        assert!(!entry.status(date(2022, 1, 31)).warn);
+
+        let forced = status("forced");
+        assert!(forced.must_change_password && forced.password_expired);
+        assert_eq!(forced.last_changed, None);
+        assert_eq!(forced.to_string(), "forced  PS  00/00/00");
+
+        let gone = status("gone");
+        assert_eq!(gone.account_expires, Some(date(2022, 2, 27)));
+        assert!(gone.account_expired);
+        assert!(
+            !shadow_file
+                .get_entry("gone")
+                .unwrap()
+                .status(date(2022, 2, 26))
+                .account_expired
+        );
+
+        let json = serde_json::to_value(&expired).unwrap();
+        assert_eq!(json["state"], "PS");
+        assert_eq!(json["password_expires"], "2022-02-07");
+        assert_eq!(json["inactive"], true);
+    }
+
+    #[test]
+    fn date_accessor_example() {
+        let mut shadow_file = parse_shadow_file(AGING_SHADOW).unwrap();
+        let entry = shadow_file.get_entry_mut("root").unwrap();
+        entry.set_expires_on(Some(date(2030, 1, 1)));
+        entry.set_last_changed_on(Some(date(2022, 3, 1)));
+        assert_eq!(entry.expire_date(), Some(21915));
+        assert_eq!(entry.last_changed(), Some(19052));
+        assert_eq!(entry.expires_on(), Some(date(2030, 1, 1)));
+
+        // Changing the password records the day of the change
+        entry.expire_password();
+        assert!(entry.must_change_password());
+        entry.set_password_hash("$5$salt$other");
+        assert!(!entry.must_change_password());
+        assert_eq!(entry.last_changed_on(), Some(crate::today()));
+
+        entry.set_expires_on(None);
+        assert_eq!(entry.expires_on(), None);
+        assert!(shadow_file.serialize().starts_with("root:$5$salt$other:"));
+    }
+}
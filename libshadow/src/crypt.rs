@@ -0,0 +1,391 @@
+use crate::ShadowError;
+use anyhow::{anyhow, Context, Result};
+use pwhash::{bcrypt, sha256_crypt, sha512_crypt, HashSetup};
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::str::FromStr;
+
+pub static POLICY_CONF: &str = "/etc/security/policy.conf";
+pub static CRYPT_CONF: &str = "/etc/security/crypt.conf";
+
+/// The crypt(3C) algorithms of illumos which can be used for new
+/// password hashes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CryptAlgorithm {
+    /// BSD MD5 crypt, `$1$`. Only for compatibility with old policies.
+    Md5Crypt,
+    /// OpenBSD bcrypt, `$2a$`. Rounds are the logarithmic cost.
+    Bcrypt,
+    /// The illumos MD5 crypt, `$md5$`. Hashed by crypt(3C) of the running
+    /// system, so only available on illumos.
+    SunMd5,
+    Sha256Crypt,
+    Sha512Crypt,
+}
+
+impl CryptAlgorithm {
+    pub const ALL: [CryptAlgorithm; 5] = [
+        CryptAlgorithm::Md5Crypt,
+        CryptAlgorithm::Bcrypt,
+        CryptAlgorithm::SunMd5,
+        CryptAlgorithm::Sha256Crypt,
+        CryptAlgorithm::Sha512Crypt,
+    ];
+
+    /// The name of the algorithm in crypt.conf and policy.conf
+    pub fn id(&self) -> &'static str {
+        match self {
+            CryptAlgorithm::Md5Crypt => "1",
+            CryptAlgorithm::Bcrypt => "2a",
+            CryptAlgorithm::SunMd5 => "md5",
+            CryptAlgorithm::Sha256Crypt => "5",
+            CryptAlgorithm::Sha512Crypt => "6",
+        }
+    }
+
+    /// The crypt.conf module implementing the algorithm
+    fn module(&self) -> &'static str {
+        match self {
+            CryptAlgorithm::Md5Crypt => "crypt_bsdmd5.so.1",
+            CryptAlgorithm::Bcrypt => "crypt_bsdbf.so.1",
+            CryptAlgorithm::SunMd5 => "crypt_sunmd5.so.1",
+            CryptAlgorithm::Sha256Crypt => "crypt_sha256.so.1",
+            CryptAlgorithm::Sha512Crypt => "crypt_sha512.so.1",
+        }
+    }
+
+    /// The algorithm of a crypt(3C) hash by its prefix. None if `hash`
+    /// is not a hash of one of the algorithms.
+    /// ```
+    /// use libshadow::CryptAlgorithm;
+    ///
+    /// assert_eq!(CryptAlgorithm::from_hash("$2b$10$abc"), Some(CryptAlgorithm::Bcrypt));
+    /// assert_eq!(CryptAlgorithm::from_hash("$md5,rounds=1000$abc$$def"), Some(CryptAlgorithm::SunMd5));
+    /// assert_eq!(CryptAlgorithm::from_hash("secret"), None);
+    /// ```
+    pub fn from_hash(hash: &str) -> Option<CryptAlgorithm> {
+        let id = hash.strip_prefix('$')?.split(['$', ',']).next()?;
+        if !hash[1 + id.len()..].starts_with(['$', ',']) {
+            return None;
+        }
+        match id {
+            "1" => Some(CryptAlgorithm::Md5Crypt),
+            "2a" | "2b" | "2y" => Some(CryptAlgorithm::Bcrypt),
+            "md5" => Some(CryptAlgorithm::SunMd5),
+            "5" => Some(CryptAlgorithm::Sha256Crypt),
+            "6" => Some(CryptAlgorithm::Sha512Crypt),
+            _ => None,
+        }
+    }
+}
+
+impl Display for CryptAlgorithm {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CryptAlgorithm::Md5Crypt => write!(f, "md5-crypt"),
+            CryptAlgorithm::Bcrypt => write!(f, "bcrypt"),
+            CryptAlgorithm::SunMd5 => write!(f, "sun-md5"),
+            CryptAlgorithm::Sha256Crypt => write!(f, "sha256-crypt"),
+            CryptAlgorithm::Sha512Crypt => write!(f, "sha512-crypt"),
+        }
+    }
+}
+
+/// Accepts the names printed by Display as well as the crypt.conf names
+impl FromStr for CryptAlgorithm {
+    type Err = ShadowError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "2b" | "2y" => return Ok(CryptAlgorithm::Bcrypt),
+            "sunmd5" => return Ok(CryptAlgorithm::SunMd5),
+            _ => {}
+        }
+        CryptAlgorithm::ALL
+            .into_iter()
+            .find(|a| a.id() == s || a.to_string() == s)
+            .ok_or_else(|| ShadowError::UnknownAlgorithm(s.into()))
+    }
+}
+
+/// How new password hashes are generated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CryptPolicy {
+    pub algorithm: CryptAlgorithm,
+    /// Rounds of the algorithm, its default if not set
+    pub rounds: Option<u32>,
+}
+
+/// SHA-512 crypt with the default rounds
+impl Default for CryptPolicy {
+    fn default() -> Self {
+        CryptPolicy::new(CryptAlgorithm::Sha512Crypt)
+    }
+}
+
+impl CryptPolicy {
+    pub fn new(algorithm: CryptAlgorithm) -> Self {
+        CryptPolicy {
+            algorithm,
+            rounds: None,
+        }
+    }
+
+    pub fn with_rounds(mut self, rounds: u32) -> Self {
+        self.rounds = Some(rounds);
+        self
+    }
+
+    /// The policy of the system mounted at `root`. `CRYPT_DEFAULT` of
+    /// policy.conf selects the algorithm, its crypt.conf line the rounds.
+    /// Without policy.conf or with the traditional `__unix__` crypt, which
+    /// only uses 8 characters of a password, the default is used.
+    pub fn from_root<P: AsRef<Path>>(root: P) -> Result<Self> {
+        let read = |file: &str| {
+            let path = root.as_ref().join(file.trim_start_matches('/'));
+            match fs::read_to_string(&path) {
+                Ok(content) => Ok(Some(content)),
+                Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e).with_context(|| format!("could not read {}", path.display())),
+            }
+        };
+        match read(POLICY_CONF)? {
+            Some(policy_conf) => CryptPolicy::parse(&policy_conf, read(CRYPT_CONF)?.as_deref()),
+            None => Ok(CryptPolicy::default()),
+        }
+    }
+
+    /// The policy given by the contents of policy.conf and crypt.conf
+    /// ```
+    /// use libshadow::{CryptAlgorithm, CryptPolicy};
+    ///
+    /// let policy = CryptPolicy::parse(
+    ///     "CRYPT_ALGORITHMS_ALLOW=1,2a,md5,5,6\nCRYPT_DEFAULT=5\n",
+    ///     Some("5 crypt_sha256.so.1 rounds=10000\n6 crypt_sha512.so.1\n"),
+    /// )
+    /// .unwrap();
+    /// assert_eq!(policy, CryptPolicy::new(CryptAlgorithm::Sha256Crypt).with_rounds(10000));
+    /// ```
+    pub fn parse(policy_conf: &str, crypt_conf: Option<&str>) -> Result<Self> {
+        // The last assignment wins
+        let default = policy_conf
+            .lines()
+            .rev()
+            .map(str::trim)
+            .filter(|l| !l.starts_with('#'))
+            .filter_map(|l| l.split_once('='))
+            .find(|(key, _)| key.trim() == "CRYPT_DEFAULT")
+            .map(|(_, value)| value.trim());
+        let id = match default {
+            Some(id) if id != "__unix__" => id,
+            _ => return Ok(CryptPolicy::default()),
+        };
+
+        let line = crypt_conf.and_then(|c| {
+            c.lines()
+                .map(|l| l.split_whitespace().collect::<Vec<_>>())
+                .find(|fields| fields.len() >= 2 && fields[0] == id)
+        });
+        let mut policy = match &line {
+            // The module decides on the algorithm, the name is arbitrary
+            Some(fields) => CryptAlgorithm::ALL
+                .into_iter()
+                .find(|a| fields[1].ends_with(a.module()))
+                .map(CryptPolicy::new)
+                .ok_or_else(|| anyhow!(ShadowError::UnknownAlgorithm(fields[1].into())))?,
+            None => CryptPolicy::new(id.parse()?),
+        };
+        for param in line.iter().flat_map(|fields| &fields[2..]) {
+            if let Some(rounds) = param.strip_prefix("rounds=") {
+                let rounds = rounds
+                    .parse()
+                    .with_context(|| format!("invalid rounds for {} in crypt.conf", id))?;
+                policy.rounds = Some(rounds);
+            }
+        }
+        Ok(policy)
+    }
+
+    /// Hash `password` with a random salt
+    pub fn hash(&self, password: &str) -> Result<String> {
+        let setup = HashSetup {
+            salt: None,
+            rounds: self.rounds,
+        };
+        Ok(match self.algorithm {
+            #[allow(deprecated)]
+            CryptAlgorithm::Md5Crypt => pwhash::md5_crypt::hash(password)?,
+            CryptAlgorithm::Bcrypt => bcrypt::hash_with(
+                bcrypt::BcryptSetup {
+                    salt: None,
+                    cost: self.rounds,
+                    variant: Some(bcrypt::BcryptVariant::V2a),
+                },
+                password,
+            )?,
+            CryptAlgorithm::SunMd5 => {
+                let salt = match self.rounds {
+                    Some(rounds) => format!("$md5,rounds={}${}$", rounds, random_salt(8)),
+                    None => format!("$md5${}$", random_salt(8)),
+                };
+                system_crypt(password, &salt)?
+            }
+            #[allow(deprecated)]
+            CryptAlgorithm::Sha256Crypt => sha256_crypt::hash_with(setup, password)?,
+            CryptAlgorithm::Sha512Crypt => sha512_crypt::hash_with(setup, password)?,
+        })
+    }
+}
+
+/// Whether `value` is a crypt(3C) hash of one of the known algorithms and
+/// not a clear text password
+pub fn is_password_hash(value: &str) -> bool {
+    CryptAlgorithm::from_hash(value).is_some()
+}
+
+/// Check `password` against `hash` of any of the known algorithms
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    match CryptAlgorithm::from_hash(hash) {
+        Some(CryptAlgorithm::SunMd5) => system_crypt(password, hash).is_ok_and(|h| h == hash),
+        _ => pwhash::unix::verify(password, hash),
+    }
+}
+
+fn random_salt(len: usize) -> String {
+    use rand::Rng;
+    const SALT_CHARS: &[u8] = b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+    let mut rng = rand::thread_rng();
+    (0..len)
+        .map(|_| SALT_CHARS[rng.gen_range(0..SALT_CHARS.len())] as char)
+        .collect()
+}
+
+/// crypt(3C) of the running system, which knows all algorithms of its
+/// crypt.conf
+#[cfg(target_os = "illumos")]
+fn system_crypt(password: &str, salt: &str) -> Result<String> {
+    use std::ffi::{CStr, CString};
+    use std::os::raw::c_char;
+
+    extern "C" {
+        fn crypt(key: *const c_char, salt: *const c_char) -> *mut c_char;
+    }
+
+    let key = CString::new(password)?;
+    let salt = CString::new(salt)?;
+    let hash = unsafe { crypt(key.as_ptr(), salt.as_ptr()) };
+    if hash.is_null() {
+        return Err(anyhow!(
+            "crypt(3C) failed: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    Ok(unsafe { CStr::from_ptr(hash) }.to_str()?.to_string())
+}
+
+#[cfg(not(target_os = "illumos"))]
+fn system_crypt(_password: &str, _salt: &str) -> Result<String> {
+    Err(anyhow!(ShadowError::UnsupportedAlgorithm(
+        CryptAlgorithm::SunMd5.to_string()
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        is_password_hash, parse_shadow_file, verify_password, CryptAlgorithm, CryptPolicy,
+        ShadowEntry,
+    };
+
+    static CRYPT_CONF: &str = r#"#
+# The algorithm name __unix__ is reserved.
+
+1       crypt_bsdmd5.so.1
+2a      crypt_bsdbf.so.1
+md5     crypt_sunmd5.so.1
+5       crypt_sha256.so.1
+6       crypt_sha512.so.1 rounds=20000
+"#;
+
+    #[test]
+    fn policy_example() {
+        let parse = |policy: &str| CryptPolicy::parse(policy, Some(CRYPT_CONF)).unwrap();
+        assert_eq!(parse(""), CryptPolicy::default());
+        assert_eq!(parse("CRYPT_DEFAULT=__unix__\n"), CryptPolicy::default());
+        assert_eq!(
+            parse("#CRYPT_DEFAULT=6\nCRYPT_DEFAULT=2a\n"),
+            CryptPolicy::new(CryptAlgorithm::Bcrypt)
+        );
+        assert_eq!(
+            parse("CRYPT_DEFAULT=6"),
+            CryptPolicy::new(CryptAlgorithm::Sha512Crypt).with_rounds(20000)
+        );
+        assert_eq!(
+            CryptPolicy::parse("CRYPT_DEFAULT=md5", None).unwrap(),
+            CryptPolicy::new(CryptAlgorithm::SunMd5)
+        );
+        assert!(CryptPolicy::parse("CRYPT_DEFAULT=7", None).is_err());
+        assert!(CryptPolicy::from_root("/nonexistent").unwrap() == CryptPolicy::default());
+
+        for algorithm in CryptAlgorithm::ALL {
+            assert_eq!(
+                algorithm.to_string().parse::<CryptAlgorithm>().unwrap(),
+                algorithm
+            );
+            assert_eq!(algorithm.id().parse::<CryptAlgorithm>().unwrap(), algorithm);
+        }
+    }
+
+    #[test]
+    fn hash_example() {
+        for policy in [
+            CryptPolicy::new(CryptAlgorithm::Sha256Crypt),
+            CryptPolicy::new(CryptAlgorithm::Sha512Crypt).with_rounds(10000),
+            CryptPolicy::new(CryptAlgorithm::Bcrypt).with_rounds(4),
+            CryptPolicy::new(CryptAlgorithm::Md5Crypt),
+        ] {
+            let hash = policy.hash("blubber").unwrap();
+            assert_eq!(CryptAlgorithm::from_hash(&hash), Some(policy.algorithm));
+            assert!(verify_password("blubber", &hash));
+            assert!(!verify_password("blabber", &hash));
+
+            // Every hash has to survive the shadow file
+            let mut entry = ShadowEntry::new("jdoe").unwrap();
+            entry.update_password_hash_with("blubber", &policy).unwrap();
+            let mut shadow_file = parse_shadow_file("root:NP:6445::::::").unwrap();
+            shadow_file.insert(entry).unwrap();
+            let shadow_file = parse_shadow_file(&shadow_file.serialize()).unwrap();
+            shadow_file
+                .get_entry("jdoe")
+                .unwrap()
+                .check_password("blubber")
+                .unwrap();
+        }
+        assert!(CryptPolicy::new(CryptAlgorithm::Sha512Crypt)
+            .with_rounds(10000)
+            .hash("x")
+            .unwrap()
+            .starts_with("$6$rounds=10000$"));
+    }
+
+    #[test]
+    fn detect_example() {
+        for hash in [
+            "$1$5pZSV9va$azfrPr6af3Fc7dLblQXVa0",
+            "$2a$04$abcdefghijklmnopqrstuu5jNc9XEzQZBvxOgeSVLnQBmBuF8TKMe",
+            "$2b$04$abcdefghijklmnopqrstuu5jNc9XEzQZBvxOgeSVLnQBmBuF8TKMe",
+            "$2y$04$abcdefghijklmnopqrstuu5jNc9XEzQZBvxOgeSVLnQBmBuF8TKMe",
+            "$md5$zrdhpMlZ$$wBvMOEqbSjU.hu5T2VEP01",
+            "$md5,rounds=5000$GUBv0xjJ$$mSwgIswdjlTY0YxV7HBVm0",
+            "$5$rounds=10000$saltstring$3xv.VbSHBb41AL9AvLeujZkZRBAwqFMz2.opqey6IcA",
+            "$6$saltstring$svn8UoSVapNtMuq1ukKS4tPQd8iKwSMHWjl/O817G3uBnIFNjnQJuesI68u4OTLiBFdcbYEdFCoEOfaS35inz1",
+        ] {
+            assert!(is_password_hash(hash), "{}", hash);
+        }
+        for password in ["secret", "$", "$6", "$7$abc", "$md5abc$", "6$abc"] {
+            assert!(!is_password_hash(password), "{}", password);
+        }
+    }
+}
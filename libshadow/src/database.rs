@@ -0,0 +1,473 @@
+use crate::write::replace_file;
+use crate::{
+    parse_group_file, parse_passwd_file, parse_shadow_file, GroupEntry, GroupFile, PasswdEntry,
+    PasswdFile, PasswordLock, ShadowEntry, ShadowError, ShadowFile, GROUP_FILE, PASSWD_FILE,
+    SHADOW_FILE,
+};
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// User and group IDs below this are reserved for the system, like the
+/// default of useradd and groupadd
+pub const FIRST_ID: u32 = 100;
+/// nobody, noaccess and nobody4 live at the top of the range
+const LAST_ID: u32 = 60000;
+
+/// A user to create with [`UserDatabase::user_add`]
+#[derive(Debug, Clone, Default)]
+pub struct NewUser {
+    pub username: String,
+    /// User ID, the next free one if not given
+    pub uid: Option<u32>,
+    /// Name of the primary group, `other` if not given
+    pub group: Option<String>,
+    /// Supplementary groups
+    pub groups: Vec<String>,
+    pub comment: String,
+    /// Home directory, `/home/<username>` if not given
+    pub home: Option<String>,
+    /// Login shell, `/bin/sh` if not given
+    pub shell: Option<String>,
+    /// Password hash. Without one the account stays locked.
+    pub password_hash: Option<String>,
+}
+
+/// Changes to an existing user for [`UserDatabase::user_mod`]. Only the
+/// fields which are set are changed.
+#[derive(Debug, Clone, Default)]
+pub struct UserChanges {
+    pub username: Option<String>,
+    pub uid: Option<u32>,
+    pub group: Option<String>,
+    /// Replaces all supplementary groups
+    pub groups: Option<Vec<String>>,
+    pub comment: Option<String>,
+    pub home: Option<String>,
+    pub shell: Option<String>,
+}
+
+/// /etc/passwd, /etc/shadow and /etc/group of a system, changed together
+/// so every user has an entry in passwd and shadow and only existing
+/// users are members of groups. The root path can point to a mounted
+/// image which is not the running system. The [`PasswordLock`] of the
+/// root is held as long as the database exists, so nobody changes the
+/// files between reading and saving them.
+#[derive(Debug)]
+pub struct UserDatabase {
+    root: PathBuf,
+    _lock: PasswordLock,
+    pub passwd: PasswdFile,
+    pub shadow: ShadowFile,
+    pub group: GroupFile,
+}
+
+impl UserDatabase {
+    /// Lock and read the databases of the system mounted at `root`
+    pub fn open<P: AsRef<Path>>(root: P) -> Result<Self> {
+        let root = root.as_ref().to_path_buf();
+        let lock = PasswordLock::acquire(root.join("etc"))?;
+        let read = |file: &str| {
+            let path = root.join(file.trim_start_matches('/'));
+            fs::read_to_string(&path).with_context(|| format!("could not read {}", path.display()))
+        };
+        Ok(UserDatabase {
+            passwd: parse_passwd_file(&read(PASSWD_FILE)?)?,
+            shadow: parse_shadow_file(&read(SHADOW_FILE)?)?,
+            group: parse_group_file(&read(GROUP_FILE)?)?,
+            root,
+            _lock: lock,
+        })
+    }
+
+    /// Write all three files back to the root path. They are replaced
+    /// one by one, the lock taken by [`UserDatabase::open`] keeps others
+    /// out.
+    pub fn save(&self) -> Result<()> {
+        for (file, content, mode) in [
+            (PASSWD_FILE, self.passwd.serialize(), 0o644),
+            (SHADOW_FILE, self.shadow.serialize(), 0o400),
+            (GROUP_FILE, self.group.serialize(), 0o644),
+        ] {
+            replace_file(
+                &self.root.join(file.trim_start_matches('/')),
+                &content,
+                mode,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// The user ID of `username`
+    pub fn uid(&self, username: &str) -> Option<u32> {
+        self.passwd.get_entry(username).map(|e| e.uid)
+    }
+
+    /// The group ID of the group `name`
+    pub fn gid(&self, name: &str) -> Option<u32> {
+        self.group.get_entry(name).map(|e| e.gid)
+    }
+
+    fn gid_or_err(&self, name: &str) -> Result<u32> {
+        self.gid(name)
+            .ok_or_else(|| anyhow!(ShadowError::UnknownGroup(name.into())))
+    }
+
+    /// The lowest user ID from [`FIRST_ID`] on which is not used yet
+    pub fn next_uid(&self) -> Result<u32> {
+        (FIRST_ID..=LAST_ID)
+            .find(|id| self.passwd.get_entry_by_uid(*id).is_none())
+            .ok_or_else(|| anyhow!(ShadowError::NoFreeId))
+    }
+
+    /// The lowest group ID from [`FIRST_ID`] on which is not used yet
+    pub fn next_gid(&self) -> Result<u32> {
+        (FIRST_ID..=LAST_ID)
+            .find(|id| self.group.get_entry_by_gid(*id).is_none())
+            .ok_or_else(|| anyhow!(ShadowError::NoFreeId))
+    }
+
+    /// Check that every group exists before a user is added to them
+    fn check_groups(&self, groups: &[String]) -> Result<()> {
+        for name in groups {
+            self.gid_or_err(name)?;
+        }
+        Ok(())
+    }
+
+    /// Make `username` a member of exactly `groups`
+    fn set_groups(&mut self, username: &str, groups: &[String]) {
+        for entry in &mut self.group.entries {
+            let member = groups.contains(&entry.name);
+            entry.members.retain(|m| m != username);
+            if member {
+                entry.members.push(username.into());
+            }
+        }
+    }
+
+    /// Create a user like useradd(8). The user gets entries in passwd
+    /// and shadow and is added to its supplementary groups. Returns the
+    /// user ID.
+    pub fn user_add(&mut self, user: NewUser) -> Result<u32> {
+        let mut shadow_entry = ShadowEntry::new(&user.username)?;
+        if self.passwd.get_entry(&user.username).is_some()
+            || self.shadow.get_entry(&user.username).is_some()
+        {
+            return Err(anyhow!(ShadowError::UserExists(user.username)));
+        }
+        let uid = match user.uid {
+            Some(uid) if self.passwd.get_entry_by_uid(uid).is_some() => {
+                return Err(anyhow!(ShadowError::UidInUse(uid)))
+            }
+            Some(uid) => uid,
+            None => self.next_uid()?,
+        };
+        let gid = self.gid_or_err(user.group.as_deref().unwrap_or("other"))?;
+        self.check_groups(&user.groups)?;
+
+        if let Some(hash) = &user.password_hash {
+            shadow_entry.set_password_hash(hash);
+        }
+        self.passwd.insert(PasswdEntry {
+            username: user.username.clone(),
+            password: "x".into(),
+            uid,
+            gid,
+            gecos: user.comment,
+            home: user
+                .home
+                .unwrap_or_else(|| format!("/home/{}", user.username)),
+            shell: user.shell.unwrap_or_else(|| "/bin/sh".into()),
+        })?;
+        self.shadow.insert(shadow_entry)?;
+        self.set_groups(&user.username, &user.groups);
+        Ok(uid)
+    }
+
+    /// Remove a user like userdel(8) from passwd, shadow and the member
+    /// lists of all groups. The home directory is left alone.
+    pub fn user_del(&mut self, username: &str) -> Result<()> {
+        if self.passwd.remove(username).is_none() {
+            return Err(anyhow!(ShadowError::UnknownUser(username.into())));
+        }
+        self.shadow.remove(username);
+        self.set_groups(username, &[]);
+        Ok(())
+    }
+
+    /// Change a user like usermod(8). A new username is applied to
+    /// shadow and the group member lists as well.
+    pub fn user_mod(&mut self, username: &str, changes: UserChanges) -> Result<()> {
+        if self.passwd.get_entry(username).is_none() {
+            return Err(anyhow!(ShadowError::UnknownUser(username.into())));
+        }
+        if let Some(new_name) = &changes.username {
+            if new_name != username && self.passwd.get_entry(new_name).is_some() {
+                return Err(anyhow!(ShadowError::UserExists(new_name.clone())));
+            }
+            ShadowEntry::new(new_name)?;
+        }
+        if let Some(uid) = changes.uid {
+            if self
+                .passwd
+                .get_entry_by_uid(uid)
+                .is_some_and(|e| e.username != username)
+            {
+                return Err(anyhow!(ShadowError::UidInUse(uid)));
+            }
+        }
+        let gid = match &changes.group {
+            Some(group) => Some(self.gid_or_err(group)?),
+            None => None,
+        };
+        if let Some(groups) = &changes.groups {
+            self.check_groups(groups)?;
+        }
+
+        let entry = self.passwd.get_entry_mut(username).unwrap();
+        if let Some(uid) = changes.uid {
+            entry.uid = uid;
+        }
+        if let Some(gid) = gid {
+            entry.gid = gid;
+        }
+        if let Some(comment) = changes.comment {
+            entry.gecos = comment;
+        }
+        if let Some(home) = changes.home {
+            entry.home = home;
+        }
+        if let Some(shell) = changes.shell {
+            entry.shell = shell;
+        }
+        if let Some(groups) = &changes.groups {
+            self.set_groups(username, groups);
+        }
+        if let Some(new_name) = changes.username {
+            self.passwd.get_entry_mut(username).unwrap().username = new_name.clone();
+            if let Some(shadow_entry) = self.shadow.get_entry_mut(username) {
+                shadow_entry.username = new_name.clone();
+            }
+            for group in &mut self.group.entries {
+                for member in group.members.iter_mut().filter(|m| *m == username) {
+                    *member = new_name.clone();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Create a group like groupadd(8). Returns the group ID.
+    pub fn group_add(&mut self, name: &str, gid: Option<u32>) -> Result<u32> {
+        if name.is_empty() || name.contains([':', ',', '\n']) {
+            return Err(anyhow!(ShadowError::InvalidGroupName(name.into())));
+        }
+        let gid = match gid {
+            Some(gid) if self.group.get_entry_by_gid(gid).is_some() => {
+                return Err(anyhow!(ShadowError::GidInUse(gid)))
+            }
+            Some(gid) => gid,
+            None => self.next_gid()?,
+        };
+        self.group.insert(GroupEntry {
+            name: name.into(),
+            password: String::new(),
+            gid,
+            members: vec![],
+        })?;
+        Ok(gid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{NewUser, ShadowError, UserChanges, UserDatabase, PWD_LOCK_FILE};
+    use std::fs;
+    use std::path::PathBuf;
+
+    static EXAMPLE_PASSWD: &str = r#"root:x:0:0:Super-User:/root:/usr/bin/bash
+daemon:x:1:1::/:
+bin:x:2:2::/usr/bin:
+sys:x:3:3::/:
+adm:x:4:4:Admin:/var/adm:
+staff1:x:100:10:First Staff:/home/staff1:/usr/bin/bash
+nobody:x:60001:60001:NFS Anonymous Access User:/:"#;
+
+    static EXAMPLE_SHADOW: &str = r#"root:$5$0123456789abcdef$JkBU8xJ0rG1QV2LmK7cPHXXOlGw9Fb.3vJc5h0zM9a2:6445::::::
+daemon:NP:6445::::::
+bin:NP:6445::::::
+sys:NP:6445::::::
+adm:NP:6445::::::
+staff1:*LK*:18675::::::
+nobody:*LK*:6445::::::"#;
+
+    static EXAMPLE_GROUP: &str = r#"root::0:
+other::1:root
+bin::2:root,daemon
+sys::3:root,bin,adm
+adm::4:root,daemon
+staff::10:staff1
+nobody::60001:"#;
+
+    /// A root holding the example files, unique for every test
+    fn example_root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("libshadow-{}-{}", std::process::id(), name));
+        fs::create_dir_all(root.join("etc")).unwrap();
+        fs::write(root.join("etc/passwd"), format!("{}\n", EXAMPLE_PASSWD)).unwrap();
+        fs::write(root.join("etc/shadow"), format!("{}\n", EXAMPLE_SHADOW)).unwrap();
+        fs::write(root.join("etc/group"), format!("{}\n", EXAMPLE_GROUP)).unwrap();
+        root
+    }
+
+    fn example(name: &str) -> UserDatabase {
+        UserDatabase::open(example_root(name)).unwrap()
+    }
+
+    #[test]
+    fn parse_passwd_group_example() {
+        let db = example("parse");
+        assert_eq!(db.passwd.serialize(), EXAMPLE_PASSWD);
+        assert_eq!(db.group.serialize(), EXAMPLE_GROUP);
+
+        let root = db.passwd.get_entry("root").unwrap();
+        assert_eq!(root.gecos, "Super-User");
+        assert_eq!(root.shell, "/usr/bin/bash");
+        assert_eq!(db.passwd.get_entry("daemon").unwrap().shell, "");
+        assert_eq!(db.uid("staff1"), Some(100));
+        assert_eq!(db.gid("staff"), Some(10));
+        assert_eq!(db.group.get_entry("root").unwrap().members.len(), 0);
+        let groups: Vec<&str> = db
+            .group
+            .groups_of("daemon")
+            .iter()
+            .map(|g| g.name.as_str())
+            .collect();
+        assert_eq!(groups, vec!["bin", "adm"]);
+        fs::remove_dir_all(&db.root).unwrap();
+    }
+
+    #[test]
+    fn user_add_del_example() {
+        let mut db = example("user_add_del");
+        let uid = db
+            .user_add(NewUser {
+                username: "jdoe".into(),
+                groups: vec!["staff".into(), "adm".into()],
+                comment: "J. Doe".into(),
+                ..Default::default()
+            })
+            .unwrap();
+        // 100 is taken by staff1
+        assert_eq!(uid, 101);
+        let jdoe = db.passwd.get_entry("jdoe").unwrap();
+        assert_eq!(jdoe.gid, 1);
+        assert_eq!(jdoe.home, "/home/jdoe");
+        assert_eq!(jdoe.shell, "/bin/sh");
+        assert!(db.shadow.get_entry("jdoe").unwrap().is_locked());
+        assert_eq!(db.group.groups_of("jdoe").len(), 2);
+        assert!(db.group.serialize().contains("staff::10:staff1,jdoe"));
+
+        let err = db
+            .user_add(NewUser {
+                username: "jdoe".into(),
+                ..Default::default()
+            })
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ShadowError>(),
+            Some(ShadowError::UserExists(_))
+        ));
+        let err = db
+            .user_add(NewUser {
+                username: "other".into(),
+                uid: Some(0),
+                ..Default::default()
+            })
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ShadowError>(),
+            Some(ShadowError::UidInUse(0))
+        ));
+        let err = db
+            .user_add(NewUser {
+                username: "other".into(),
+                groups: vec!["wheel".into()],
+                ..Default::default()
+            })
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ShadowError>(),
+            Some(ShadowError::UnknownGroup(_))
+        ));
+        assert!(db.passwd.get_entry("other").is_none());
+
+        db.user_del("jdoe").unwrap();
+        assert_eq!(db.passwd.serialize(), EXAMPLE_PASSWD);
+        assert_eq!(db.shadow.serialize(), EXAMPLE_SHADOW);
+        assert_eq!(db.group.serialize(), EXAMPLE_GROUP);
+        assert!(db.user_del("jdoe").is_err());
+        fs::remove_dir_all(&db.root).unwrap();
+    }
+
+    #[test]
+    fn user_mod_group_add_example() {
+        let mut db = example("user_mod_group_add");
+        assert_eq!(db.group_add("dev", None).unwrap(), 100);
+        assert_eq!(db.group_add("ops", Some(500)).unwrap(), 500);
+        assert!(db.group_add("ops2", Some(500)).is_err());
+        assert!(db.group_add("dev", None).is_err());
+
+        db.user_mod(
+            "staff1",
+            UserChanges {
+                username: Some("staff2".into()),
+                group: Some("dev".into()),
+                groups: Some(vec!["staff".into(), "ops".into()]),
+                shell: Some("/bin/ksh".into()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(db.passwd.get_entry("staff1").is_none());
+        let staff2 = db.passwd.get_entry("staff2").unwrap();
+        assert_eq!(staff2.gid, 100);
+        assert_eq!(staff2.shell, "/bin/ksh");
+        assert!(db.shadow.get_entry("staff2").is_some());
+        assert!(db.group.groups_of("staff1").is_empty());
+        assert_eq!(db.group.groups_of("staff2").len(), 2);
+
+        assert!(db
+            .user_mod(
+                "staff2",
+                UserChanges {
+                    uid: Some(0),
+                    ..Default::default()
+                }
+            )
+            .is_err());
+        assert!(db.user_mod("staff1", UserChanges::default()).is_err());
+        fs::remove_dir_all(&db.root).unwrap();
+    }
+
+    #[test]
+    fn open_save_example() {
+        let root = example_root("open_save");
+        let mut db = UserDatabase::open(&root).unwrap();
+        // the lock is taken on open and held until the database is dropped
+        assert!(root.join("etc").join(PWD_LOCK_FILE).exists());
+        db.user_add(NewUser {
+            username: "jdoe".into(),
+            ..Default::default()
+        })
+        .unwrap();
+        db.save().unwrap();
+        db.save().unwrap();
+        drop(db);
+
+        let db = UserDatabase::open(&root).unwrap();
+        assert_eq!(db.uid("jdoe"), Some(101));
+        assert!(db.shadow.get_entry("jdoe").is_some());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,213 @@
+use crate::ShadowError;
+use anyhow::{anyhow, Result};
+use pest::Parser;
+
+pub static GROUP_FILE: &str = "/etc/group";
+
+/// A group in /etc/group. `members` are the users which have the group
+/// as supplementary group.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupEntry {
+    pub name: String,
+    pub password: String,
+    pub gid: u32,
+    pub members: Vec<String>,
+}
+
+impl GroupEntry {
+    fn serialize(&self) -> String {
+        format!(
+            "{}:{}:{}:{}",
+            self.name,
+            self.password,
+            self.gid,
+            self.members.join(",")
+        )
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct GroupFile {
+    pub(crate) entries: Vec<GroupEntry>,
+}
+
+impl GroupFile {
+    /// Get the group named `name`
+    pub fn get_entry(&self, name: &str) -> Option<&GroupEntry> {
+        self.entries.iter().find(|e| e.name == name)
+    }
+
+    pub fn get_entry_mut(&mut self, name: &str) -> Option<&mut GroupEntry> {
+        self.entries.iter_mut().find(|e| e.name == name)
+    }
+
+    /// Get the first group with the group ID `gid`
+    pub fn get_entry_by_gid(&self, gid: u32) -> Option<&GroupEntry> {
+        self.entries.iter().find(|e| e.gid == gid)
+    }
+
+    /// All groups in the order of the file
+    pub fn entries(&self) -> &[GroupEntry] {
+        &self.entries
+    }
+
+    /// Add a new group at the end. Fails if a group with the name exists.
+    pub fn insert(&mut self, entry: GroupEntry) -> Result<()> {
+        if self.get_entry(&entry.name).is_some() {
+            return Err(anyhow!(ShadowError::GroupExists(entry.name)));
+        }
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// Update the group if one with the same name already exists or
+    /// insert a new one at the end
+    pub fn insert_or_update(&mut self, entry: GroupEntry) {
+        match self.get_entry_mut(&entry.name) {
+            Some(e) => *e = entry,
+            None => self.entries.push(entry),
+        }
+    }
+
+    /// Remove the group named `name` and return it
+    pub fn remove(&mut self, name: &str) -> Option<GroupEntry> {
+        let i = self.entries.iter().position(|e| e.name == name)?;
+        Some(self.entries.remove(i))
+    }
+
+    /// The groups `username` is a supplementary member of
+    pub fn groups_of(&self, username: &str) -> Vec<&GroupEntry> {
+        self.entries
+            .iter()
+            .filter(|e| e.members.iter().any(|m| m == username))
+            .collect()
+    }
+
+    /// This function writes the groups in the format expected by
+    /// /etc/group
+    pub fn serialize(&self) -> String {
+        self.entries
+            .iter()
+            .map(GroupEntry::serialize)
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+#[derive(Parser)]
+#[grammar = "group.pest"]
+struct GroupParser;
+
+/// Parse a group file
+/// ```no_run
+/// use libshadow::{parse_group_file, GROUP_FILE};
+/// use std::fs;
+/// let contents = fs::read_to_string(GROUP_FILE).unwrap();
+///
+/// let group = parse_group_file(&contents).unwrap();
+/// let sys = group.get_entry("sys").unwrap();
+/// println!("{}", sys.members.join(" "));
+/// ```
+pub fn parse_group_file(file: &str) -> Result<GroupFile> {
+    let group_file = GroupParser::parse(Rule::group_file, file)?;
+
+    let mut group_file_struct = GroupFile::default();
+
+    for pair in group_file {
+        for entry_pair in pair.into_inner() {
+            if entry_pair.as_rule() != Rule::group_entry {
+                continue;
+            }
+            let mut entry = GroupEntry {
+                name: String::new(),
+                password: String::new(),
+                gid: 0,
+                members: vec![],
+            };
+            for field in entry_pair.into_inner() {
+                match field.as_rule() {
+                    Rule::groupname => entry.name = field.as_str().into(),
+                    Rule::password => entry.password = field.as_str().into(),
+                    Rule::gid => entry.gid = field.as_str().parse::<u32>()?,
+                    Rule::member => entry.members.push(field.as_str().into()),
+                    _ => {}
+                }
+            }
+            group_file_struct.entries.push(entry);
+        }
+    }
+
+    Ok(group_file_struct)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{parse_group_file, GroupEntry};
+
+    static EXAMPLE_GROUP: &str = r#"root::0:
+other::1:root
+bin::2:root,daemon
+sys::3:root,bin,adm
+adm::4:root,daemon
+uucp::5:root
+staff::10:
+sysadmin::14:
+netadm::65:
+nobody::60001:
+noaccess::60002:
+"#;
+
+    #[test]
+    fn round_trip_example() {
+        let group = parse_group_file(EXAMPLE_GROUP).unwrap();
+        assert_eq!(group.entries().len(), 11);
+        // The newline at the end of the file is added back when writing
+        assert_eq!(format!("{}\n", group.serialize()), EXAMPLE_GROUP);
+
+        let without_newline = EXAMPLE_GROUP.trim_end_matches('\n');
+        let group = parse_group_file(without_newline).unwrap();
+        assert_eq!(group.serialize(), without_newline);
+    }
+
+    #[test]
+    fn parse_members_example() {
+        let group = parse_group_file(EXAMPLE_GROUP).unwrap();
+        assert_eq!(
+            group.get_entry("sys"),
+            Some(&GroupEntry {
+                name: "sys".into(),
+                password: "".into(),
+                gid: 3,
+                members: vec!["root".into(), "bin".into(), "adm".into()],
+            })
+        );
+        assert!(group.get_entry("root").unwrap().members.is_empty());
+        // An empty member list at the end of the file is not a member
+        assert!(group.get_entry("noaccess").unwrap().members.is_empty());
+        assert_eq!(group.get_entry_by_gid(65).unwrap().name, "netadm");
+        assert_eq!(group.groups_of("root").len(), 5);
+    }
+
+    #[test]
+    fn modify_round_trip_example() {
+        let mut group = parse_group_file(EXAMPLE_GROUP).unwrap();
+        group
+            .get_entry_mut("staff")
+            .unwrap()
+            .members
+            .push("jdoe".into());
+        group.get_entry_mut("other").unwrap().members.clear();
+        let serialized = group.serialize();
+        assert!(serialized.contains("\nstaff::10:jdoe\n"));
+        assert!(serialized.contains("\nother::1:\n"));
+
+        let reparsed = parse_group_file(&serialized).unwrap();
+        assert_eq!(reparsed.entries(), group.entries());
+    }
+
+    #[test]
+    fn parse_empty_file() {
+        assert!(parse_group_file("").unwrap().entries().is_empty());
+        assert_eq!(parse_group_file("\n").unwrap().serialize(), "");
+    }
+}
@@ -5,11 +5,81 @@ extern crate pest_derive;
 use anyhow::{anyhow, Result};
 use pest::iterators::Pairs;
 use pest::Parser;
+use std::fmt::{Display, Formatter};
+use std::time::{SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+mod aging;
+mod crypt;
+mod database;
+mod group;
+mod passwd;
+mod policy;
+mod write;
+
+pub use aging::{date_from_days, days_from_date, today, AccountStatus, PasswordState};
+pub use crypt::{
+    is_password_hash, verify_password, CryptAlgorithm, CryptPolicy, CRYPT_CONF, POLICY_CONF,
+};
+pub use database::{NewUser, UserChanges, UserDatabase, FIRST_ID};
+pub use group::{parse_group_file, GroupEntry, GroupFile, GROUP_FILE};
+pub use passwd::{parse_passwd_file, PasswdEntry, PasswdFile, PASSWD_FILE};
+pub use policy::{PasswordPolicy, PolicyViolation, DEFAULT_PASSWD_FILE, PASSHISTORY_FILE};
+pub use write::{update_shadow_file, PasswordLock, PWD_LOCK_FILE};
 
 #[allow(dead_code)]
 pub static SHADOW_FILE: &str = "/etc/shadow";
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Error)]
+pub enum ShadowError {
+    #[error("no entry named {0} in shadow file")]
+    UnknownUser(String),
+    #[error("an entry named {0} already exists in shadow file")]
+    UserExists(String),
+    #[error("{0:?} is not a valid username")]
+    InvalidUsername(String),
+    #[error("{0:?} is not a valid group name")]
+    InvalidGroupName(String),
+    #[error("no group named {0}")]
+    UnknownGroup(String),
+    #[error("a group named {0} already exists")]
+    GroupExists(String),
+    #[error("user ID {0} is already in use")]
+    UidInUse(u32),
+    #[error("group ID {0} is already in use")]
+    GidInUse(u32),
+    #[error("no free ID left")]
+    NoFreeId,
+    #[error("{0} is locked by another process")]
+    Locked(String),
+    #[error("unknown crypt algorithm {0}")]
+    UnknownAlgorithm(String),
+    #[error("crypt algorithm {0} is not supported on this platform")]
+    UnsupportedAlgorithm(String),
+    #[error("password does not meet the policy: {0}")]
+    WeakPassword(String),
+    #[error("{0} has no password, unlocking it would allow to log in without one")]
+    NoPassword(String),
+}
+
+/// Days since 1970-01-01, the unit of the date fields in /etc/shadow
+fn days_since_epoch() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| (d.as_secs() / 86400) as i64)
+        .unwrap_or_default()
+}
+
+/// Aging fields are stored as -1 if they are empty in the file
+fn optional_field(value: i64) -> Option<i64> {
+    if value != -1 {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ShadowEntry {
     username: String,
     password_hash: String,
@@ -33,7 +103,7 @@ impl Default for ShadowEntry {
             password_locked: false,
             no_login: false,
             no_password: false,
-            password_last_changed: 0,
+            password_last_changed: -1,
             min: -1,
             max: -1,
             warn: -1,
@@ -45,16 +115,169 @@ impl Default for ShadowEntry {
 }
 
 impl ShadowEntry {
+    /// A new entry for `username` which is locked until a password is set,
+    /// like the entries useradd creates
+    pub fn new(username: &str) -> Result<Self> {
+        if username.is_empty() || username.contains([':', '\n']) {
+            return Err(anyhow!(ShadowError::InvalidUsername(username.into())));
+        }
+        Ok(ShadowEntry {
+            username: username.into(),
+            password_locked: true,
+            password_last_changed: days_since_epoch(),
+            ..Default::default()
+        })
+    }
+
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    /// The password hash. Kept while the entry is locked, empty for
+    /// NP and NL entries.
+    pub fn password_hash(&self) -> &str {
+        &self.password_hash
+    }
+
     /// Update the entries password hash in a safe way
     /// (meaning use a good cryptographic algorithm)
     pub fn update_password_hash(&mut self, clear_new_password: &str) -> Result<()> {
-        use pwhash::sha512_crypt::hash;
-        self.password_hash = hash(clear_new_password)?;
+        self.update_password_hash_with(clear_new_password, &CryptPolicy::default())
+    }
+
+    /// Update the entries password hash with the algorithm of `policy`
+    pub fn update_password_hash_with(
+        &mut self,
+        clear_new_password: &str,
+        policy: &CryptPolicy,
+    ) -> Result<()> {
+        self.set_password_hash(&policy.hash(clear_new_password)?);
         Ok(())
     }
 
+    /// Replace the password field with `new_hash` like passwd(1) does.
+    /// This unlocks the entry and records today as the last change.
     pub fn set_password_hash(&mut self, new_hash: &str) {
-        self.password_hash = new_hash.clone().into()
+        self.password_hash = new_hash.into();
+        self.password_locked = false;
+        self.no_login = false;
+        self.no_password = false;
+        self.password_last_changed = days_since_epoch();
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.password_locked
+    }
+
+    /// Lock the entry by prefixing the password field with `*LK*`.
+    /// The hash is kept so [`ShadowEntry::unlock`] restores it.
+    pub fn lock(&mut self) {
+        self.password_locked = true;
+    }
+
+    /// Remove the `*LK*` prefix and restore the previous password field.
+    /// Like `passwd -u` this fails for entries without a hash, NP or NL
+    /// as an empty password field allows to log in without a password.
+    pub fn unlock(&mut self) -> Result<()> {
+        if self.password_hash.is_empty() && !self.no_login && !self.no_password {
+            return Err(anyhow!(ShadowError::NoPassword(self.username.clone())));
+        }
+        self.password_locked = false;
+        Ok(())
+    }
+
+    /// Whether the entry is marked NP, no password is needed to log in
+    pub fn is_no_password(&self) -> bool {
+        self.no_password
+    }
+
+    /// Mark the entry NP. Only non-interactive accounts should be NP.
+    pub fn set_no_password(&mut self) {
+        self.password_hash.clear();
+        self.no_password = true;
+        self.no_login = false;
+    }
+
+    /// Whether the entry is marked NL, a non-login account which can
+    /// still run cron jobs
+    pub fn is_no_login(&self) -> bool {
+        self.no_login
+    }
+
+    /// Mark the entry NL
+    pub fn set_no_login(&mut self) {
+        self.password_hash.clear();
+        self.no_login = true;
+        self.no_password = false;
+    }
+
+    /// Force a password change at the next login like `passwd -f`
+    pub fn expire_password(&mut self) {
+        self.password_last_changed = 0;
+    }
+
+    /// Day of the last password change in days since 1970-01-01.
+    /// 0 means the password must be changed at the next login.
+    pub fn last_changed(&self) -> Option<i64> {
+        optional_field(self.password_last_changed)
+    }
+
+    pub fn set_last_changed(&mut self, days: Option<i64>) {
+        self.password_last_changed = days.unwrap_or(-1);
+    }
+
+    /// Minimum number of days between password changes
+    pub fn min_days(&self) -> Option<i64> {
+        optional_field(self.min)
+    }
+
+    pub fn set_min_days(&mut self, days: Option<i64>) {
+        self.min = days.unwrap_or(-1);
+    }
+
+    /// Number of days a password is valid
+    pub fn max_days(&self) -> Option<i64> {
+        optional_field(self.max)
+    }
+
+    pub fn set_max_days(&mut self, days: Option<i64>) {
+        self.max = days.unwrap_or(-1);
+    }
+
+    /// Number of days before the password expires the user is warned
+    pub fn warn_days(&self) -> Option<i64> {
+        optional_field(self.warn)
+    }
+
+    pub fn set_warn_days(&mut self, days: Option<i64>) {
+        self.warn = days.unwrap_or(-1);
+    }
+
+    /// Number of days the account may be inactive before it is locked
+    pub fn inactive_days(&self) -> Option<i64> {
+        optional_field(self.inactive)
+    }
+
+    pub fn set_inactive_days(&mut self, days: Option<i64>) {
+        self.inactive = days.unwrap_or(-1);
+    }
+
+    /// Day the account expires in days since 1970-01-01
+    pub fn expire_date(&self) -> Option<i64> {
+        optional_field(self.expire)
+    }
+
+    pub fn set_expire_date(&mut self, days: Option<i64>) {
+        self.expire = days.unwrap_or(-1);
+    }
+
+    /// The reserved flag field, 0 if empty
+    pub fn flag(&self) -> i64 {
+        self.flag
+    }
+
+    pub fn set_flag(&mut self, flag: i64) {
+        self.flag = flag;
     }
 
     /// Use this function to check if the hash of the entry
@@ -62,28 +285,45 @@ impl ShadowEntry {
     /// pass the cleartext password to check the entries hash against
     /// as argument
     pub fn check_password(&self, password: &str) -> Result<()> {
-        use pwhash::unix::verify;
-        if verify(password, &self.password_hash) {
+        if verify_password(password, &self.password_hash) {
             return Ok(());
         }
 
         Err(anyhow!("Passwords do not match"))
     }
 
+    fn serialize(&self) -> String {
+        format!(
+            "{}:{}:{}:{}:{}:{}:{}:{}:{}",
+            self.username,
+            self.print_password_entry(),
+            self.print_lastchg(),
+            self.print_min(),
+            self.print_max(),
+            self.print_warn(),
+            self.print_inactive(),
+            self.print_expire(),
+            self.print_flag()
+        )
+    }
+
     fn print_password_entry(&self) -> String {
-        if self.password_locked {
-            String::from("*LK*")
-        } else if self.no_login {
+        let password = if self.no_login {
             String::from("NL")
         } else if self.no_password {
             String::from("NP")
         } else {
             self.password_hash.clone()
+        };
+        if self.password_locked {
+            format!("*LK*{}", password)
+        } else {
+            password
         }
     }
 
     fn print_lastchg(&self) -> String {
-        if self.password_last_changed != 0 {
+        if self.password_last_changed != -1 {
             format!("{}", self.password_last_changed)
         } else {
             String::new()
@@ -139,64 +379,167 @@ impl ShadowEntry {
     }
 }
 
-#[derive(Debug, Default)]
+/// A line of the shadow file which is not an entry this library can
+/// change, reported when the file is parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShadowWarning {
+    /// 1-based line number
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for ShadowWarning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum ShadowLine {
+    /// An entry and the text it was parsed from. The text is written
+    /// back as long as the entry is unchanged.
+    Entry {
+        entry: ShadowEntry,
+        original: Option<String>,
+    },
+    /// Comments, blank lines, NIS compat entries and lines which could not
+    /// be parsed. They are written back as they are.
+    Verbatim(String),
+}
+
+#[derive(Debug, Default, Clone)]
 pub struct ShadowFile {
-    entries: Vec<ShadowEntry>,
+    lines: Vec<ShadowLine>,
+    warnings: Vec<ShadowWarning>,
 }
 
 impl ShadowFile {
+    fn iter(&self) -> impl Iterator<Item = &ShadowEntry> {
+        self.lines.iter().filter_map(|l| match l {
+            ShadowLine::Entry { entry, .. } => Some(entry),
+            ShadowLine::Verbatim(_) => None,
+        })
+    }
+
+    fn iter_mut(&mut self) -> impl Iterator<Item = &mut ShadowEntry> {
+        self.lines.iter_mut().filter_map(|l| match l {
+            ShadowLine::Entry { entry, .. } => Some(entry),
+            ShadowLine::Verbatim(_) => None,
+        })
+    }
+
+    fn push(&mut self, entry: ShadowEntry) {
+        self.lines.push(ShadowLine::Entry {
+            entry,
+            original: None,
+        })
+    }
+
     /// Get the shadow entry with `username` as username
     pub fn get_entry(&self, username: &str) -> Option<ShadowEntry> {
-        for e in &self.entries {
-            if e.username.as_str() == username {
-                return Some(e.clone());
-            }
-        }
+        self.iter().find(|e| e.username == username).cloned()
+    }
 
-        None
+    /// The shadow entry with `username` as username for changing it
+    pub fn get_entry_mut(&mut self, username: &str) -> Option<&mut ShadowEntry> {
+        self.iter_mut().find(|e| e.username == username)
+    }
+
+    /// All entries in the order of the file
+    pub fn entries(&self) -> Vec<&ShadowEntry> {
+        self.iter().collect()
+    }
+
+    /// The lines which were kept as they are when the file was parsed
+    pub fn warnings(&self) -> &[ShadowWarning] {
+        &self.warnings
     }
 
     /// Update the shadow entry if one with the same
     /// username already exists or insert a new one at the end
     pub fn insert_or_update(&mut self, entry: ShadowEntry) {
-        for (i, e) in self.entries.iter().enumerate() {
-            if e.username == entry.username {
-                self.entries[i] = entry.clone();
-                return;
-            }
+        match self.get_entry_mut(&entry.username) {
+            Some(e) => *e = entry,
+            None => self.push(entry),
         }
     }
 
-    /// This function writes the Shadow Entry in the format expected by
-    /// /etc/shadow
-    pub fn serialize(&self) -> String {
-        let mut file = String::new();
-
-        for (i, e) in self.entries.iter().enumerate() {
-            if i > 0 {
-                file += "\n"
-            }
+    /// Add a new entry at the end. Fails if the user already has one.
+    pub fn insert(&mut self, entry: ShadowEntry) -> Result<()> {
+        if self.get_entry(&entry.username).is_some() {
+            return Err(anyhow!(ShadowError::UserExists(entry.username)));
+        }
+        self.push(entry);
+        Ok(())
+    }
 
-            file += &format!(
-                "{}:{}:{}:{}:{}:{}:{}:{}:{}",
-                e.username,
-                e.print_password_entry(),
-                e.print_lastchg(),
-                e.print_min(),
-                e.print_max(),
-                e.print_warn(),
-                e.print_inactive(),
-                e.print_expire(),
-                e.print_flag()
-            )
+    /// Remove the entry of `username` and return it
+    pub fn remove(&mut self, username: &str) -> Option<ShadowEntry> {
+        let i = self.lines.iter().position(
+            |l| matches!(l, ShadowLine::Entry { entry, .. } if entry.username == username),
+        )?;
+        match self.lines.remove(i) {
+            ShadowLine::Entry { entry, .. } => Some(entry),
+            ShadowLine::Verbatim(_) => None,
         }
+    }
+
+    fn entry_or_err(&mut self, username: &str) -> Result<&mut ShadowEntry> {
+        self.get_entry_mut(username)
+            .ok_or_else(|| anyhow!(ShadowError::UnknownUser(username.into())))
+    }
+
+    /// Lock the entry of `username`, see [`ShadowEntry::lock`]
+    pub fn lock(&mut self, username: &str) -> Result<()> {
+        self.entry_or_err(username)?.lock();
+        Ok(())
+    }
+
+    /// Unlock the entry of `username`, see [`ShadowEntry::unlock`]
+    pub fn unlock(&mut self, username: &str) -> Result<()> {
+        self.entry_or_err(username)?.unlock()
+    }
+
+    /// Mark the entry of `username` NP
+    pub fn set_no_password(&mut self, username: &str) -> Result<()> {
+        self.entry_or_err(username)?.set_no_password();
+        Ok(())
+    }
+
+    /// Mark the entry of `username` NL
+    pub fn set_no_login(&mut self, username: &str) -> Result<()> {
+        self.entry_or_err(username)?.set_no_login();
+        Ok(())
+    }
+
+    /// Force `username` to change the password at the next login
+    pub fn expire_password(&mut self, username: &str) -> Result<()> {
+        self.entry_or_err(username)?.expire_password();
+        Ok(())
+    }
 
-        file
+    /// This function writes the Shadow Entry in the format expected by
+    /// /etc/shadow. Lines which were not changed are written as they were
+    /// read.
+    pub fn serialize(&self) -> String {
+        self.lines
+            .iter()
+            .map(|l| match l {
+                ShadowLine::Entry {
+                    entry,
+                    original: Some(text),
+                } if parse_shadow_entry(text).is_ok_and(|e| e == *entry) => text.clone(),
+                ShadowLine::Entry { entry, .. } => entry.serialize(),
+                ShadowLine::Verbatim(text) => text.clone(),
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
     }
 }
 
 /// This function provides a safe default to generate a password hash for
-/// /etc/shadow files. Use this to prehash a password in the configuration
+/// /etc/shadow files. Use this to prehash a password in the configuration.
+/// [`CryptPolicy::from_root`] gives the algorithm a system is configured for.
 /// ```no_run
 /// use libshadow::gen_password_hash;
 ///
@@ -204,68 +547,96 @@ impl ShadowFile {
 /// // Do something with Hash
 /// ```
 pub fn gen_password_hash(clear_password: &str) -> Result<String> {
-    use pwhash::sha512_crypt::hash;
-    Ok(hash(clear_password)?)
+    CryptPolicy::default().hash(clear_password)
 }
 
 #[derive(Parser)]
 #[grammar = "shadow.pest"]
 struct ShadowParser;
 
+fn parse_shadow_entry(line: &str) -> Result<ShadowEntry> {
+    let shadow_line: Pairs<Rule> = ShadowParser::parse(Rule::shadow_line, line)?;
+
+    let mut shadow_entry = ShadowEntry::default();
+    for entry_pair in shadow_line.flatten() {
+        let number = || entry_pair.as_str().parse::<i64>();
+        match entry_pair.as_rule() {
+            Rule::username => shadow_entry.username = entry_pair.as_str().into(),
+            Rule::password => shadow_entry.password_hash = entry_pair.as_str().into(),
+            Rule::no_login => shadow_entry.no_login = true,
+            Rule::no_password => shadow_entry.no_password = true,
+            Rule::locked_password => shadow_entry.password_locked = true,
+            Rule::lastchg => shadow_entry.password_last_changed = number()?,
+            Rule::min => shadow_entry.min = number()?,
+            Rule::max => shadow_entry.max = number()?,
+            Rule::warn => shadow_entry.warn = number()?,
+            Rule::inactive => shadow_entry.inactive = number()?,
+            Rule::expire => shadow_entry.expire = number()?,
+            Rule::flag => shadow_entry.flag = number()?,
+            _ => {}
+        }
+    }
+    Ok(shadow_entry)
+}
+
 /// Parse a Shadow file and get the entries in easily
-/// changeable form
+/// changeable form. Lines which are not entries are kept as they are and
+/// reported by [`ShadowFile::warnings`] so a damaged file can still be
+/// changed.
 /// ```no_run
 /// use libshadow::{parse_shadow_file, SHADOW_FILE};
 /// use std::fs;
 /// let contents = fs::read_to_string(SHADOW_FILE).unwrap();
 ///
 /// let shadow = parse_shadow_file(&contents).unwrap();
+/// for warning in shadow.warnings() {
+///     eprintln!("{}: {}", SHADOW_FILE, warning);
+/// }
 /// // Do something with the file
 /// let new_file = shadow.serialize();
 /// // Do something with the new file
 /// ```
 pub fn parse_shadow_file(file: &str) -> Result<ShadowFile> {
-    let shadow_file: Pairs<Rule> = ShadowParser::parse(Rule::shadow_file, file)?;
-
     let mut shadow_file_struct = ShadowFile::default();
+    if file.is_empty() {
+        return Ok(shadow_file_struct);
+    }
 
-    for pair in shadow_file {
-        for file_pair in pair.into_inner() {
-            let mut shadow_entry = ShadowEntry::default();
-            for entry_pair in file_pair.into_inner() {
-                match entry_pair.as_rule() {
-                    Rule::username => shadow_entry.username = entry_pair.as_str().into(),
-                    Rule::password => shadow_entry.password_hash = entry_pair.as_str().into(),
-                    Rule::no_login => shadow_entry.no_login = true,
-                    Rule::no_password => shadow_entry.no_password = true,
-                    Rule::locked_password => shadow_entry.password_locked = true,
-                    Rule::lastchg => {
-                        shadow_entry.password_last_changed = entry_pair.as_str().parse::<i64>()?;
-                    }
-                    Rule::min => {
-                        shadow_entry.min = entry_pair.as_str().parse::<i64>()?;
-                    }
-                    Rule::max => {
-                        shadow_entry.max = entry_pair.as_str().parse::<i64>()?;
-                    }
-                    Rule::warn => {
-                        shadow_entry.warn = entry_pair.as_str().parse::<i64>()?;
-                    }
-                    Rule::inactive => {
-                        shadow_entry.inactive = entry_pair.as_str().parse::<i64>()?;
-                    }
-                    Rule::expire => {
-                        shadow_entry.expire = entry_pair.as_str().parse::<i64>()?;
-                    }
-                    Rule::flag => {
-                        shadow_entry.flag = entry_pair.as_str().parse::<i64>()?;
-                    }
-                    _ => {}
-                }
+    // The newline at the end of the file is added back by whoever writes it
+    let content = file.strip_suffix('\n').unwrap_or(file);
+    for (i, line) in content.split('\n').enumerate() {
+        let verbatim = ShadowLine::Verbatim(line.into());
+        // Blank lines, comments and +/- entries of the NIS compat mode
+        if line.trim().is_empty() || line.starts_with(['#', '+', '-']) {
+            shadow_file_struct.lines.push(verbatim);
+            continue;
+        }
+        let (shadow_line, message) = match parse_shadow_entry(line) {
+            Ok(entry) if shadow_file_struct.get_entry(&entry.username).is_some() => {
+                let message = format!("duplicate entry for {}", entry.username);
+                (verbatim, Some(message))
             }
-            if shadow_entry.username != "" {
-                shadow_file_struct.entries.push(shadow_entry)
+            Ok(entry) => (
+                ShadowLine::Entry {
+                    original: Some(line.into()),
+                    entry,
+                },
+                None,
+            ),
+            Err(e) => {
+                let message = match e.downcast_ref::<pest::error::Error<Rule>>() {
+                    Some(e) => format!("invalid entry: {}", e.variant.message()),
+                    None => format!("invalid entry: {}", e),
+                };
+                (verbatim, Some(message))
             }
+        };
+        shadow_file_struct.lines.push(shadow_line);
+        if let Some(message) = message {
+            shadow_file_struct.warnings.push(ShadowWarning {
+                line: i + 1,
+                message,
+            });
         }
     }
 
@@ -274,7 +645,7 @@ pub fn parse_shadow_file(file: &str) -> Result<ShadowFile> {
 
 #[cfg(test)]
 mod tests {
-    use crate::parse_shadow_file;
+    use crate::{parse_shadow_file, ShadowEntry, ShadowError};
 
     static EXAMPLE_SHADOW: &str = r#"root:$6$L2Yjwxe3zlIDk4yf$1RwTeVJL2erBYnyIVerOlN5/aoyELMyquctogNESxd/gZQ11mzh4NM5QS6.S.CIslv4LzRYZ1sqVDEqBKTKvv1:6445::::::
 daemon:NP:6445::::::
@@ -324,4 +695,206 @@ jenkins:NL:18676::::::"#;
         let serialized = shadow_file.serialize();
         assert_ne!(EXAMPLE_SHADOW, serialized)
     }
+
+    /// The line of `username` in the serialized file
+    fn line<'a>(serialized: &'a str, username: &str) -> &'a str {
+        serialized
+            .lines()
+            .find(|l| l.starts_with(&format!("{}:", username)))
+            .unwrap()
+    }
+
+    #[test]
+    fn insert_remove_example() {
+        let mut shadow_file = parse_shadow_file(EXAMPLE_SHADOW).unwrap();
+        let count = shadow_file.entries().len();
+
+        shadow_file
+            .insert(ShadowEntry::new("alice").unwrap())
+            .unwrap();
+        let err = shadow_file
+            .insert(ShadowEntry::new("alice").unwrap())
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ShadowError>(),
+            Some(ShadowError::UserExists(_))
+        ));
+        let mut bob = ShadowEntry::new("bob").unwrap();
+        bob.set_no_login();
+        shadow_file.insert_or_update(bob);
+        assert_eq!(shadow_file.entries().len(), count + 2);
+        let serialized = shadow_file.serialize();
+        let alice = line(&serialized, "alice");
+        assert!(alice.starts_with("alice:*LK*:"));
+        assert!(alice.ends_with("::::::"));
+        assert!(serialized
+            .lines()
+            .last()
+            .unwrap()
+            .starts_with("bob:*LK*NL:"));
+        assert!(ShadowEntry::new("a:b").is_err());
+
+        let removed = shadow_file.remove("alice").unwrap();
+        assert_eq!(removed.username(), "alice");
+        assert!(shadow_file.remove("alice").is_none());
+        assert!(shadow_file.remove("bob").is_some());
+        assert_eq!(shadow_file.serialize(), EXAMPLE_SHADOW);
+    }
+
+    #[test]
+    fn lock_unlock_example() {
+        let mut shadow_file = parse_shadow_file(EXAMPLE_SHADOW).unwrap();
+        let root = line(EXAMPLE_SHADOW, "root").to_string();
+        shadow_file.lock("root").unwrap();
+        shadow_file.lock("root").unwrap();
+        let locked = shadow_file.serialize();
+        assert_eq!(line(&locked, "root"), root.replacen(":", ":*LK*", 1));
+
+        // The hash survives a round trip through the file while locked
+        let mut shadow_file = parse_shadow_file(&locked).unwrap();
+        let entry = shadow_file.get_entry("root").unwrap();
+        assert!(entry.is_locked());
+        assert!(entry.password_hash().starts_with("$6$"));
+        shadow_file.unlock("root").unwrap();
+        assert_eq!(shadow_file.serialize(), EXAMPLE_SHADOW);
+
+        shadow_file.lock("daemon").unwrap();
+        assert_eq!(
+            line(&shadow_file.serialize(), "daemon"),
+            "daemon:*LK*NP:6445::::::"
+        );
+        // Without a hash the entry would allow to log in without a password
+        let err = shadow_file.unlock("dladm").unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ShadowError>(),
+            Some(ShadowError::NoPassword(_))
+        ));
+        assert!(shadow_file.get_entry("dladm").unwrap().is_locked());
+        assert_eq!(
+            line(&shadow_file.serialize(), "dladm"),
+            "dladm:*LK*:18675::::::"
+        );
+
+        let err = shadow_file.lock("nosuchuser").unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ShadowError>(),
+            Some(ShadowError::UnknownUser(_))
+        ));
+    }
+
+    #[test]
+    fn account_state_example() {
+        let mut shadow_file = parse_shadow_file(EXAMPLE_SHADOW).unwrap();
+        shadow_file.set_no_password("jenkins").unwrap();
+        shadow_file.set_no_login("daemon").unwrap();
+        shadow_file.expire_password("bin").unwrap();
+        let serialized = shadow_file.serialize();
+        assert_eq!(line(&serialized, "jenkins"), "jenkins:NP:18676::::::");
+        assert_eq!(line(&serialized, "daemon"), "daemon:NL:6445::::::");
+        assert_eq!(line(&serialized, "bin"), "bin:NP:0::::::");
+
+        // Setting a password replaces NP, NL and the lock
+        let mut entry = shadow_file.get_entry("xvm").unwrap();
+        entry.set_password_hash("$5$rounds=5000$salt$hash");
+        assert!(!entry.is_locked());
+        assert!(entry.last_changed().unwrap() > 6445);
+        shadow_file.insert_or_update(entry);
+        assert!(line(&shadow_file.serialize(), "xvm").starts_with("xvm:$5$rounds=5000$salt$hash:"));
+    }
+
+    #[test]
+    fn aging_example() {
+        let mut shadow_file = parse_shadow_file(EXAMPLE_SHADOW).unwrap();
+        let daemon = shadow_file.get_entry_mut("daemon").unwrap();
+        assert_eq!(daemon.last_changed(), Some(6445));
+        assert_eq!(daemon.min_days(), None);
+        assert_eq!(daemon.expire_date(), None);
+        daemon.set_min_days(Some(1));
+        daemon.set_max_days(Some(90));
+        daemon.set_warn_days(Some(7));
+        daemon.set_inactive_days(Some(30));
+        daemon.set_expire_date(Some(20000));
+        assert_eq!(daemon.max_days(), Some(90));
+        assert_eq!(
+            line(&shadow_file.serialize(), "daemon"),
+            "daemon:NP:6445:1:90:7:30:20000:"
+        );
+
+        let listen = shadow_file.get_entry_mut("listen").unwrap();
+        assert_eq!(listen.last_changed(), None);
+        listen.set_last_changed(Some(0));
+        listen.set_max_days(None);
+        assert_eq!(
+            line(&shadow_file.serialize(), "listen"),
+            "listen:*LK*:0::::::"
+        );
+    }
+
+    static PERMISSIVE_SHADOW: &str = r#"# local accounts
+root:$6$saltstring$hash:6445::::::
+svc_user:NP:18675::::::
+first.last-name:*LK*$5$rounds=5000$salt$hash:18675:0:90:7:::
+
+nopass::18675::::::
+short:*LK*
+this line is broken
+daemon:NP:6445::::::
+root:NP:6445::::::
+legacy:x:0010:-1:99999:7:::
++@netgroup::::::::
+"#;
+
+    #[test]
+    fn permissive_example() {
+        let mut shadow_file = parse_shadow_file(PERMISSIVE_SHADOW).unwrap();
+        // Lines which are not changed are written as they were read
+        assert_eq!(shadow_file.serialize() + "\n", PERMISSIVE_SHADOW);
+        let warnings: Vec<usize> = shadow_file.warnings().iter().map(|w| w.line).collect();
+        assert_eq!(warnings, vec![8, 10]);
+        assert_eq!(
+            shadow_file.warnings()[1].to_string(),
+            "line 10: duplicate entry for root"
+        );
+
+        let usernames: Vec<&str> = shadow_file.entries().iter().map(|e| e.username()).collect();
+        assert_eq!(
+            usernames,
+            vec![
+                "root",
+                "svc_user",
+                "first.last-name",
+                "nopass",
+                "short",
+                "daemon",
+                "legacy"
+            ]
+        );
+        let entry = shadow_file.get_entry("first.last-name").unwrap();
+        assert!(entry.is_locked());
+        assert_eq!(entry.password_hash(), "$5$rounds=5000$salt$hash");
+        assert_eq!(entry.max_days(), Some(90));
+        assert_eq!(shadow_file.get_entry("nopass").unwrap().password_hash(), "");
+        assert!(shadow_file.get_entry("short").unwrap().is_locked());
+        assert_eq!(
+            shadow_file.get_entry("legacy").unwrap().last_changed(),
+            Some(10)
+        );
+
+        // Only the changed entry is written in the normalized form
+        shadow_file.lock("svc_user").unwrap();
+        assert!(shadow_file.unlock("short").is_err());
+        shadow_file.remove("daemon").unwrap();
+        shadow_file
+            .insert(ShadowEntry::new("new_user").unwrap())
+            .unwrap();
+        let serialized = shadow_file.serialize();
+        assert_eq!(line(&serialized, "svc_user"), "svc_user:*LK*NP:18675::::::");
+        assert_eq!(line(&serialized, "short"), "short:*LK*");
+        assert!(line(&serialized, "legacy").starts_with("legacy:x:0010:-1:"));
+        assert!(serialized.contains("\nthis line is broken\nroot:NP:6445::::::\nlegacy"));
+        assert!(serialized.contains("+@netgroup::::::::\nnew_user:*LK*:"));
+        assert!(line(&serialized, "new_user").starts_with("new_user:*LK*:"));
+
+        assert!(parse_shadow_file("").unwrap().entries().is_empty());
+    }
 }
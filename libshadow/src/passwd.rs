@@ -0,0 +1,196 @@
+use crate::ShadowError;
+use anyhow::{anyhow, Result};
+use pest::Parser;
+
+pub static PASSWD_FILE: &str = "/etc/passwd";
+
+/// An account in /etc/passwd. The password is kept in /etc/shadow, the
+/// password field here is usually `x`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PasswdEntry {
+    pub username: String,
+    pub password: String,
+    pub uid: u32,
+    pub gid: u32,
+    /// Comment field, usually the full name of the user
+    pub gecos: String,
+    pub home: String,
+    pub shell: String,
+}
+
+impl PasswdEntry {
+    fn serialize(&self) -> String {
+        format!(
+            "{}:{}:{}:{}:{}:{}:{}",
+            self.username, self.password, self.uid, self.gid, self.gecos, self.home, self.shell
+        )
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct PasswdFile {
+    entries: Vec<PasswdEntry>,
+}
+
+impl PasswdFile {
+    /// Get the entry with `username` as username
+    pub fn get_entry(&self, username: &str) -> Option<&PasswdEntry> {
+        self.entries.iter().find(|e| e.username == username)
+    }
+
+    pub fn get_entry_mut(&mut self, username: &str) -> Option<&mut PasswdEntry> {
+        self.entries.iter_mut().find(|e| e.username == username)
+    }
+
+    /// Get the first entry with the user ID `uid`
+    pub fn get_entry_by_uid(&self, uid: u32) -> Option<&PasswdEntry> {
+        self.entries.iter().find(|e| e.uid == uid)
+    }
+
+    /// All entries in the order of the file
+    pub fn entries(&self) -> &[PasswdEntry] {
+        &self.entries
+    }
+
+    /// Add a new entry at the end. Fails if the user already has one.
+    pub fn insert(&mut self, entry: PasswdEntry) -> Result<()> {
+        if self.get_entry(&entry.username).is_some() {
+            return Err(anyhow!(ShadowError::UserExists(entry.username)));
+        }
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// Update the entry if one with the same username already exists or
+    /// insert a new one at the end
+    pub fn insert_or_update(&mut self, entry: PasswdEntry) {
+        match self.get_entry_mut(&entry.username) {
+            Some(e) => *e = entry,
+            None => self.entries.push(entry),
+        }
+    }
+
+    /// Remove the entry of `username` and return it
+    pub fn remove(&mut self, username: &str) -> Option<PasswdEntry> {
+        let i = self.entries.iter().position(|e| e.username == username)?;
+        Some(self.entries.remove(i))
+    }
+
+    /// This function writes the entries in the format expected by
+    /// /etc/passwd
+    pub fn serialize(&self) -> String {
+        self.entries
+            .iter()
+            .map(PasswdEntry::serialize)
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+#[derive(Parser)]
+#[grammar = "passwd.pest"]
+struct PasswdParser;
+
+/// Parse a passwd file
+/// ```no_run
+/// use libshadow::{parse_passwd_file, PASSWD_FILE};
+/// use std::fs;
+/// let contents = fs::read_to_string(PASSWD_FILE).unwrap();
+///
+/// let passwd = parse_passwd_file(&contents).unwrap();
+/// let root = passwd.get_entry("root").unwrap();
+/// assert_eq!(root.uid, 0);
+/// ```
+pub fn parse_passwd_file(file: &str) -> Result<PasswdFile> {
+    let passwd_file = PasswdParser::parse(Rule::passwd_file, file)?;
+
+    let mut passwd_file_struct = PasswdFile::default();
+
+    for pair in passwd_file {
+        for entry_pair in pair.into_inner() {
+            if entry_pair.as_rule() != Rule::passwd_entry {
+                continue;
+            }
+            let mut fields = entry_pair.into_inner().map(|p| p.as_str());
+            let mut next = || fields.next().unwrap_or_default().to_string();
+            passwd_file_struct.entries.push(PasswdEntry {
+                username: next(),
+                password: next(),
+                uid: next().parse::<u32>()?,
+                gid: next().parse::<u32>()?,
+                gecos: next(),
+                home: next(),
+                shell: next(),
+            });
+        }
+    }
+
+    Ok(passwd_file_struct)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{parse_passwd_file, PasswdEntry};
+
+    static EXAMPLE_PASSWD: &str = r#"root:x:0:0:Super-User:/root:/usr/bin/bash
+daemon:x:1:1::/:
+bin:x:2:2::/usr/bin:
+sys:x:3:3::/:
+adm:x:4:4:Admin:/var/adm:
+lp:x:71:8:Line Printer Admin:/:
+dladm:x:15:65:Datalink Admin:/:
+staff1:x:100:10:First Staff, Room 1.01:/home/staff1:/usr/bin/bash
+nobody:x:60001:60001:NFS Anonymous Access User:/:
+noaccess:x:60002:60002:No Access User:/:
+"#;
+
+    #[test]
+    fn round_trip_example() {
+        let passwd = parse_passwd_file(EXAMPLE_PASSWD).unwrap();
+        assert_eq!(passwd.entries().len(), 10);
+        // The newline at the end of the file is added back when writing
+        assert_eq!(format!("{}\n", passwd.serialize()), EXAMPLE_PASSWD);
+
+        let without_newline = EXAMPLE_PASSWD.trim_end_matches('\n');
+        let passwd = parse_passwd_file(without_newline).unwrap();
+        assert_eq!(passwd.serialize(), without_newline);
+    }
+
+    #[test]
+    fn parse_fields_example() {
+        let passwd = parse_passwd_file(EXAMPLE_PASSWD).unwrap();
+        assert_eq!(
+            passwd.get_entry("staff1"),
+            Some(&PasswdEntry {
+                username: "staff1".into(),
+                password: "x".into(),
+                uid: 100,
+                gid: 10,
+                gecos: "First Staff, Room 1.01".into(),
+                home: "/home/staff1".into(),
+                shell: "/usr/bin/bash".into(),
+            })
+        );
+        let daemon = passwd.get_entry("daemon").unwrap();
+        assert_eq!(daemon.gecos, "");
+        assert_eq!(daemon.shell, "");
+        assert_eq!(passwd.get_entry_by_uid(60002).unwrap().username, "noaccess");
+    }
+
+    #[test]
+    fn modify_round_trip_example() {
+        let mut passwd = parse_passwd_file(EXAMPLE_PASSWD).unwrap();
+        let staff1 = passwd.remove("staff1").unwrap();
+        assert!(passwd.insert(passwd.entries()[0].clone()).is_err());
+        passwd.insert(staff1.clone()).unwrap();
+        let reparsed = parse_passwd_file(&passwd.serialize()).unwrap();
+        assert_eq!(reparsed.entries(), passwd.entries());
+        assert_eq!(reparsed.entries().last(), Some(&staff1));
+    }
+
+    #[test]
+    fn parse_empty_file() {
+        assert!(parse_passwd_file("").unwrap().entries().is_empty());
+        assert_eq!(parse_passwd_file("\n").unwrap().serialize(), "");
+    }
+}
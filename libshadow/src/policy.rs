@@ -0,0 +1,360 @@
+use crate::{verify_password, ShadowError};
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+pub static DEFAULT_PASSWD_FILE: &str = "/etc/default/passwd";
+pub static PASSHISTORY_FILE: &str = "/etc/security/passhistory";
+
+/// passwd(1) keeps at most 26 old passwords
+const MAX_HISTORY: usize = 26;
+
+/// A rule of the password policy a password breaks
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum PolicyViolation {
+    #[error("it is shorter than {0} characters")]
+    TooShort(usize),
+    #[error("it has less than {0} digits")]
+    TooFewDigits(usize),
+    #[error("it has less than {0} uppercase letters")]
+    TooFewUppercase(usize),
+    #[error("it has less than {0} special characters")]
+    TooFewSpecial(usize),
+    #[error("it is based on the login name")]
+    MatchesName,
+    #[error("it is one of the last {0} passwords")]
+    InHistory(usize),
+    #[error("it is a word of the dictionary {0}")]
+    InDictionary(String),
+}
+
+/// The rules of /etc/default/passwd for new passwords, see passwd(1).
+/// Unset values have the defaults of passwd(1).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PasswordPolicy {
+    /// PASSLENGTH, the minimum number of characters
+    pub min_length: usize,
+    /// MINDIGIT
+    pub min_digit: usize,
+    /// MINUPPER
+    pub min_upper: usize,
+    /// MINSPECIAL, characters which are neither letters nor digits
+    pub min_special: usize,
+    /// NAMECHECK, forbid the login name, reversed or circularly shifted
+    pub name_check: bool,
+    /// HISTORY, the number of old passwords which can not be reused
+    pub history: usize,
+    /// DICTIONLIST, word lists the password must not be in
+    pub dictionaries: Vec<PathBuf>,
+    /// Root of the system the history and dictionaries are read from
+    root: PathBuf,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        PasswordPolicy {
+            min_length: 6,
+            min_digit: 0,
+            min_upper: 0,
+            min_special: 0,
+            name_check: true,
+            history: 0,
+            dictionaries: vec![],
+            root: PathBuf::from("/"),
+        }
+    }
+}
+
+impl PasswordPolicy {
+    /// The policy of the system mounted at `root`, the defaults if it has
+    /// no /etc/default/passwd
+    pub fn from_root<P: AsRef<Path>>(root: P) -> Result<Self> {
+        let path = root
+            .as_ref()
+            .join(DEFAULT_PASSWD_FILE.trim_start_matches('/'));
+        let mut policy = match fs::read_to_string(&path) {
+            Ok(content) => PasswordPolicy::parse(&content)
+                .with_context(|| format!("invalid {}", path.display()))?,
+            Err(e) if e.kind() == ErrorKind::NotFound => PasswordPolicy::default(),
+            Err(e) => return Err(e).with_context(|| format!("could not read {}", path.display())),
+        };
+        policy.root = root.as_ref().to_path_buf();
+        Ok(policy)
+    }
+
+    /// The policy given by the contents of /etc/default/passwd. The
+    /// history and dictionaries are read from `/`.
+    /// ```
+    /// use libshadow::PasswordPolicy;
+    ///
+    /// let policy = PasswordPolicy::parse("PASSLENGTH=8\nMINDIGIT=1\n#NAMECHECK=NO\n").unwrap();
+    /// assert_eq!(policy.min_length, 8);
+    /// assert_eq!(policy.min_digit, 1);
+    /// assert!(policy.name_check);
+    /// ```
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut policy = PasswordPolicy::default();
+        let assignments = content
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.starts_with('#'))
+            .filter_map(|l| l.split_once('='))
+            .map(|(key, value)| (key.trim(), value.trim()));
+        for (key, value) in assignments {
+            let number = || -> Result<usize> {
+                value
+                    .parse()
+                    .with_context(|| format!("{} is not a number: {:?}", key, value))
+            };
+            match key {
+                "PASSLENGTH" => policy.min_length = number()?,
+                "MINDIGIT" => policy.min_digit = number()?,
+                "MINUPPER" => policy.min_upper = number()?,
+                "MINSPECIAL" => policy.min_special = number()?,
+                "NAMECHECK" => policy.name_check = !value.eq_ignore_ascii_case("NO"),
+                "HISTORY" => policy.history = number()?.min(MAX_HISTORY),
+                "DICTIONLIST" | "DICTIONARYLIST" => {
+                    policy.dictionaries = value
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|p| !p.is_empty())
+                        .map(PathBuf::from)
+                        .collect()
+                }
+                // Aging and the rules not checked here
+                _ => {}
+            }
+        }
+        Ok(policy)
+    }
+
+    /// All rules `password` of `username` breaks. Without a username the
+    /// name and history are not checked.
+    /// ```
+    /// use libshadow::{PasswordPolicy, PolicyViolation};
+    ///
+    /// let policy = PasswordPolicy::parse("PASSLENGTH=8\nMINDIGIT=1\n").unwrap();
+    /// assert_eq!(
+    ///     policy.check(Some("jdoe"), "eodj").unwrap(),
+    ///     vec![
+    ///         PolicyViolation::TooShort(8),
+    ///         PolicyViolation::TooFewDigits(1),
+    ///         PolicyViolation::MatchesName,
+    ///     ]
+    /// );
+    /// assert!(policy.check(Some("jdoe"), "correct horse 1").unwrap().is_empty());
+    /// ```
+    pub fn check(&self, username: Option<&str>, password: &str) -> Result<Vec<PolicyViolation>> {
+        let count = |f: fn(&char) -> bool| password.chars().filter(f).count();
+        let mut violations = vec![];
+        if password.chars().count() < self.min_length {
+            violations.push(PolicyViolation::TooShort(self.min_length));
+        }
+        if count(char::is_ascii_digit) < self.min_digit {
+            violations.push(PolicyViolation::TooFewDigits(self.min_digit));
+        }
+        if count(|c| c.is_uppercase()) < self.min_upper {
+            violations.push(PolicyViolation::TooFewUppercase(self.min_upper));
+        }
+        if count(|c| !c.is_alphanumeric()) < self.min_special {
+            violations.push(PolicyViolation::TooFewSpecial(self.min_special));
+        }
+        if let Some(username) = username {
+            if self.name_check && is_circular_shift(username, password) {
+                violations.push(PolicyViolation::MatchesName);
+            }
+            if self.history > 0 && self.in_history(username, password)? {
+                violations.push(PolicyViolation::InHistory(self.history));
+            }
+        }
+        for dictionary in &self.dictionaries {
+            if self.in_dictionary(dictionary, password)? {
+                violations.push(PolicyViolation::InDictionary(
+                    dictionary.display().to_string(),
+                ));
+            }
+        }
+        Ok(violations)
+    }
+
+    /// Fail with [`ShadowError::WeakPassword`] listing all broken rules
+    /// if `password` does not meet the policy
+    pub fn enforce(&self, username: Option<&str>, password: &str) -> Result<()> {
+        let violations = self.check(username, password)?;
+        if violations.is_empty() {
+            return Ok(());
+        }
+        Err(anyhow!(ShadowError::WeakPassword(
+            violations
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )))
+    }
+
+    /// Read a file of the system, None if it does not exist
+    fn read(&self, file: &Path) -> Result<Option<String>> {
+        let path = self.root.join(file.strip_prefix("/").unwrap_or(file));
+        match fs::read_to_string(&path) {
+            Ok(content) => Ok(Some(content)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("could not read {}", path.display())),
+        }
+    }
+
+    /// Whether `password` matches one of the last hashes of `username` in
+    /// the passhistory file, lines of `name:hash:hash:...:`
+    fn in_history(&self, username: &str, password: &str) -> Result<bool> {
+        let content = match self.read(Path::new(PASSHISTORY_FILE))? {
+            Some(content) => content,
+            None => return Ok(false),
+        };
+        let hashes = content
+            .lines()
+            .filter_map(|l| l.split_once(':'))
+            .find(|(name, _)| *name == username)
+            .map(|(_, hashes)| hashes.split(':').filter(|h| !h.is_empty()).collect())
+            .unwrap_or_else(Vec::new);
+        Ok(hashes
+            .into_iter()
+            .take(self.history)
+            .any(|hash| verify_password(password, hash)))
+    }
+
+    /// Whether `password` is a word of the word list, ignoring case. A
+    /// missing dictionary is an error, it would silently weaken the policy.
+    fn in_dictionary(&self, dictionary: &Path, password: &str) -> Result<bool> {
+        let content = self
+            .read(dictionary)?
+            .ok_or_else(|| anyhow!("dictionary {} does not exist", dictionary.display()))?;
+        let password = password.to_lowercase();
+        Ok(content
+            .lines()
+            .any(|word| word.trim().to_lowercase() == password))
+    }
+}
+
+/// Whether `password` is `name` or its reverse, circularly shifted and
+/// ignoring case like passwd(1) checks it
+fn is_circular_shift(name: &str, password: &str) -> bool {
+    let name = name.to_lowercase();
+    let password = password.to_lowercase();
+    if name.chars().count() != password.chars().count() {
+        return false;
+    }
+    let reversed: String = name.chars().rev().collect();
+    [name, reversed]
+        .iter()
+        .any(|n| format!("{}{}", n, n).contains(&password))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{gen_password_hash, PasswordPolicy, PolicyViolation, DEFAULT_PASSWD_FILE};
+    use std::fs;
+
+    static DEFAULT_PASSWD: &str = r#"#
+# Copyright 2009 Sun Microsystems, Inc.  All rights reserved.
+# Use is subject to license terms.
+#
+MAXWEEKS=
+MINWEEKS=
+PASSLENGTH=8
+#NAMECHECK=NO
+HISTORY=2
+MINUPPER=1
+MINSPECIAL=1
+MINDIGIT=2
+DICTIONLIST=/usr/share/lib/dict/words
+"#;
+
+    #[test]
+    fn policy_example() {
+        let policy = PasswordPolicy::parse(DEFAULT_PASSWD).unwrap();
+        assert_eq!(policy.min_length, 8);
+        assert_eq!(policy.min_upper, 1);
+        assert_eq!(policy.min_special, 1);
+        assert_eq!(policy.min_digit, 2);
+        assert_eq!(policy.history, 2);
+        assert!(policy.name_check);
+        assert_eq!(policy.dictionaries.len(), 1);
+
+        assert_eq!(
+            PasswordPolicy::parse("").unwrap(),
+            PasswordPolicy::default()
+        );
+        assert!(!PasswordPolicy::parse("NAMECHECK=NO").unwrap().name_check);
+        assert_eq!(PasswordPolicy::parse("HISTORY=99").unwrap().history, 26);
+        assert!(PasswordPolicy::parse("PASSLENGTH=eight").is_err());
+        assert_eq!(
+            PasswordPolicy::from_root("/nonexistent")
+                .unwrap()
+                .min_length,
+            6
+        );
+    }
+
+    #[test]
+    fn check_example() {
+        let root = std::env::temp_dir().join(format!("libshadow-policy-{}", std::process::id()));
+        let write = |file: &str, content: &str| {
+            let path = root.join(file.trim_start_matches('/'));
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        };
+        write(DEFAULT_PASSWD_FILE, DEFAULT_PASSWD);
+        write(
+            "/usr/share/lib/dict/words",
+            "aardvark\nPassword12!\nzebra\n",
+        );
+        let old = gen_password_hash("Old-Secret42").unwrap();
+        write(
+            "/etc/security/passhistory",
+            &format!("root:{}:\njdoe:{}:\n", gen_password_hash("x").unwrap(), old),
+        );
+        let policy = PasswordPolicy::from_root(&root).unwrap();
+        let check = |user, password| policy.check(user, password).unwrap();
+
+        assert!(check(Some("jdoe"), "Str0ng-Secret42").is_empty());
+        assert_eq!(
+            check(Some("jdoe"), "secret"),
+            vec![
+                PolicyViolation::TooShort(8),
+                PolicyViolation::TooFewDigits(2),
+                PolicyViolation::TooFewUppercase(1),
+                PolicyViolation::TooFewSpecial(1),
+            ]
+        );
+        assert_eq!(
+            check(Some("jdoe"), "Old-Secret42"),
+            vec![PolicyViolation::InHistory(2)]
+        );
+        // Only the history of the user counts
+        assert!(check(Some("root"), "Old-Secret42").is_empty());
+        assert!(check(None, "Old-Secret42").is_empty());
+        assert_eq!(
+            check(None, "password12!"),
+            vec![
+                PolicyViolation::TooFewUppercase(1),
+                PolicyViolation::InDictionary("/usr/share/lib/dict/words".into()),
+            ]
+        );
+        assert!(check(Some("Adm1n-x%"), "%x-N1MDA").contains(&PolicyViolation::MatchesName));
+        assert!(check(Some("Adm1n-x%"), "n-x%Adm1").contains(&PolicyViolation::MatchesName));
+
+        let err = policy.enforce(Some("jdoe"), "Secret-1").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "password does not meet the policy: it has less than 2 digits"
+        );
+        assert!(policy.enforce(Some("jdoe"), "Secret-12").is_ok());
+
+        // A missing dictionary does not let every password pass
+        fs::remove_file(root.join("usr/share/lib/dict/words")).unwrap();
+        assert!(policy.check(None, "Secret-12").is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
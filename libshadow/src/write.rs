@@ -0,0 +1,205 @@
+use crate::{parse_shadow_file, ShadowError, ShadowFile};
+use anyhow::{anyhow, Context, Result};
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// The lock file of lckpwdf(3C), relative to the directory of the
+/// password files
+pub static PWD_LOCK_FILE: &str = ".pwd.lock";
+
+/// How long lckpwdf(3C) waits for the lock
+const LOCK_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// The lock passwd(1) and the other tools changing the password files
+/// take, see lckpwdf(3C). It is released when dropped.
+#[derive(Debug)]
+pub struct PasswordLock {
+    _file: File,
+}
+
+impl PasswordLock {
+    /// Take the lock of the password files in `dir`, usually
+    /// `<root>/etc`. Waits up to 15 seconds like lckpwdf(3C).
+    pub fn acquire<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let path = dir.as_ref().join(PWD_LOCK_FILE);
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .mode(0o600)
+            .open(&path)
+            .with_context(|| format!("could not open {}", path.display()))?;
+
+        let mut lock: libc::flock = unsafe { std::mem::zeroed() };
+        lock.l_type = libc::F_WRLCK as _;
+        lock.l_whence = libc::SEEK_SET as _;
+        let start = Instant::now();
+        while unsafe { libc::fcntl(file.as_raw_fd(), libc::F_SETLK, &lock) } == -1 {
+            let err = std::io::Error::last_os_error();
+            match err.raw_os_error() {
+                Some(libc::EAGAIN) | Some(libc::EACCES) if start.elapsed() < LOCK_TIMEOUT => {
+                    sleep(Duration::from_millis(100))
+                }
+                Some(libc::EAGAIN) | Some(libc::EACCES) => {
+                    return Err(anyhow!(ShadowError::Locked(path.display().to_string())))
+                }
+                Some(libc::EINTR) => {}
+                _ => return Err(err).with_context(|| format!("could not lock {}", path.display())),
+            }
+        }
+        Ok(PasswordLock { _file: file })
+    }
+}
+
+/// Replace the file at `path` with `content` without a window in which
+/// it is missing or half written. The new file gets `mode` and the owner
+/// of the old one, the old one is kept as `o<name>` like passwd(1) does
+/// with /etc/oshadow. The caller has to hold the [`PasswordLock`].
+pub(crate) fn replace_file(path: &Path, content: &str, mode: u32) -> Result<()> {
+    let dir = parent_dir(path);
+    let name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("{} is not a file", path.display()))?
+        .to_string_lossy();
+    let tmp_path = dir.join(format!(".{}.tmp", name));
+    let backup_path = dir.join(format!("o{}", name));
+    let owner = match fs::metadata(path) {
+        Ok(metadata) => Some((metadata.uid(), metadata.gid())),
+        Err(e) if e.kind() == ErrorKind::NotFound => None,
+        Err(e) => return Err(e).with_context(|| format!("could not stat {}", path.display())),
+    };
+
+    // A leftover of a crash, nobody else writes it while we hold the lock
+    let _ = fs::remove_file(&tmp_path);
+    let write_tmp = || -> std::io::Result<()> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(mode)
+            .open(&tmp_path)?;
+        if let Some((uid, gid)) = owner {
+            std::os::unix::fs::fchown(&file, Some(uid), Some(gid))?;
+        }
+        file.write_all(content.as_bytes())?;
+        if !content.is_empty() && !content.ends_with('\n') {
+            file.write_all(b"\n")?;
+        }
+        file.sync_all()
+    };
+    if let Err(e) = write_tmp() {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e).with_context(|| format!("could not write {}", tmp_path.display()));
+    }
+
+    if owner.is_some() {
+        let _ = fs::remove_file(&backup_path);
+        fs::hard_link(path, &backup_path)
+            .or_else(|_| fs::copy(path, &backup_path).map(|_| ()))
+            .with_context(|| format!("could not back up {}", path.display()))?;
+    }
+    fs::rename(&tmp_path, path).with_context(|| {
+        format!(
+            "could not rename {} to {}",
+            tmp_path.display(),
+            path.display()
+        )
+    })?;
+    File::open(&dir)
+        .and_then(|d| d.sync_all())
+        .with_context(|| format!("could not sync {}", dir.display()))?;
+    Ok(())
+}
+
+/// The directory `path` lives in, where its lock file is
+fn parent_dir(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+impl ShadowFile {
+    /// Write the file to `path`, usually `<root>/etc/shadow`, while
+    /// holding the [`PasswordLock`] of its directory. The file is replaced
+    /// atomically with mode 0400, the previous one is kept as `oshadow`.
+    /// ```no_run
+    /// use libshadow::{parse_shadow_file, SHADOW_FILE};
+    /// use std::fs;
+    ///
+    /// let mut shadow = parse_shadow_file(&fs::read_to_string(SHADOW_FILE).unwrap()).unwrap();
+    /// shadow.lock("jdoe").unwrap();
+    /// shadow.write(SHADOW_FILE).unwrap();
+    /// ```
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let _lock = PasswordLock::acquire(parent_dir(path))?;
+        replace_file(path, &self.serialize(), 0o400)
+    }
+}
+
+/// Read, change and write the shadow file at `path` while holding the
+/// [`PasswordLock`], so neither this change nor one of passwd(1) running
+/// at the same time gets lost. Returns the changed file.
+/// ```no_run
+/// use libshadow::{update_shadow_file, SHADOW_FILE};
+///
+/// let shadow = update_shadow_file(SHADOW_FILE, |shadow| shadow.unlock("jdoe")).unwrap();
+/// for warning in shadow.warnings() {
+///     eprintln!("{}: {}", SHADOW_FILE, warning);
+/// }
+/// ```
+pub fn update_shadow_file<P, F>(path: P, change: F) -> Result<ShadowFile>
+where
+    P: AsRef<Path>,
+    F: FnOnce(&mut ShadowFile) -> Result<()>,
+{
+    let path = path.as_ref();
+    let _lock = PasswordLock::acquire(parent_dir(path))?;
+    let content =
+        fs::read_to_string(path).with_context(|| format!("could not read {}", path.display()))?;
+    let mut shadow = parse_shadow_file(&content)?;
+    change(&mut shadow)?;
+    replace_file(path, &shadow.serialize(), 0o400)?;
+    Ok(shadow)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{parse_shadow_file, update_shadow_file, PWD_LOCK_FILE};
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn update_example() {
+        let dir = std::env::temp_dir().join(format!("libshadow-write-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("shadow");
+        let old = "root:NP:6445::::::\ndaemon:NP:6445::::::\n";
+        fs::write(&path, old).unwrap();
+
+        let shadow = update_shadow_file(&path, |shadow| shadow.lock("daemon")).unwrap();
+        assert!(shadow.warnings().is_empty());
+        let new = fs::read_to_string(&path).unwrap();
+        assert_eq!(new, "root:NP:6445::::::\ndaemon:*LK*NP:6445::::::\n");
+        assert_eq!(fs::read_to_string(dir.join("oshadow")).unwrap(), old);
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o400);
+        assert!(dir.join(PWD_LOCK_FILE).exists());
+        assert!(!dir.join(".shadow.tmp").exists());
+
+        // A failed change leaves the file alone
+        assert!(update_shadow_file(&path, |shadow| shadow.lock("nobody")).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), new);
+
+        let shadow = parse_shadow_file(&new).unwrap();
+        shadow.write(&path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), new);
+        assert_eq!(fs::read_to_string(dir.join("oshadow")).unwrap(), new);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
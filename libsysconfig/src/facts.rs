@@ -0,0 +1,84 @@
+use illumos::run_capture_stdout;
+use log::debug;
+use std::collections::HashMap;
+use std::fs;
+
+/// The names of all facts [`system_facts`] can provide
+pub const FACT_NAMES: &[&str] = &[
+    "hostname",
+    "arch",
+    "platform",
+    "manufacturer",
+    "product",
+    "mac",
+];
+
+/// Collect the facts of the running system which `if` conditions in
+/// sysconfig files can check:
+///
+/// * `hostname` the nodename of the system
+/// * `arch` the processor architecture as reported by `uname -p`
+/// * `platform` the hardware platform as reported by `uname -i`
+/// * `manufacturer` and `product` from the SMBIOS system information
+/// * `mac` the MAC addresses of all physical links
+///
+/// Facts which can not be determined are left out.
+pub fn system_facts() -> HashMap<String, Vec<String>> {
+    let mut facts = HashMap::new();
+
+    let hostname = fs::read_to_string("/etc/nodename")
+        .ok()
+        .or_else(|| run_capture_stdout(&["/usr/bin/uname", "-n"], None).ok());
+    if let Some(hostname) = hostname {
+        facts.insert("hostname".into(), vec![hostname.trim().into()]);
+    }
+
+    for (fact, flag) in [("arch", "-p"), ("platform", "-i")] {
+        if let Ok(value) = run_capture_stdout(&["/usr/bin/uname", flag], None) {
+            facts.insert(fact.into(), vec![value.trim().into()]);
+        }
+    }
+
+    match run_capture_stdout(&["/usr/sbin/smbios", "-t", "SMB_TYPE_SYSTEM"], None) {
+        Ok(output) => {
+            for line in output.lines() {
+                if let Some((key, value)) = line.trim().split_once(':') {
+                    match key {
+                        "Manufacturer" => {
+                            facts.insert("manufacturer".into(), vec![value.trim().into()]);
+                        }
+                        "Product" => {
+                            facts.insert("product".into(), vec![value.trim().into()]);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        Err(e) => debug!(target: "libsysconfig", "could not read smbios: {}", e),
+    }
+
+    if let Ok(output) = run_capture_stdout(
+        &["/usr/sbin/dladm", "show-phys", "-m", "-p", "-o", "address"],
+        None,
+    ) {
+        let macs = output
+            .lines()
+            .filter(|l| !l.is_empty())
+            .map(normalize_mac)
+            .collect::<Vec<String>>();
+        facts.insert("mac".into(), macs);
+    }
+
+    facts
+}
+
+/// dladm prints MAC addresses without leading zeros (2:8:20:0:0:1) and
+/// escapes the colons in parsable output. Convert them to the usual form.
+fn normalize_mac(mac: &str) -> String {
+    mac.replace("\\:", ":")
+        .split(':')
+        .map(|octet| format!("{:0>2}", octet.to_lowercase()))
+        .collect::<Vec<String>>()
+        .join(":")
+}
@@ -1,12 +1,10 @@
-use crate::InstructionError;
-use crate::{CommandOutput, Instruction, NetworkConfig, RootPasswordType};
+use crate::{CommandOutput, Instruction, NetworkConfig};
 use anyhow::{anyhow, Result};
 use illumos::{run, run_capture_stdout, svccfg};
-use libshadow::{parse_shadow_file, SHADOW_FILE};
+use libshadow::{update_shadow_file, ShadowError, SHADOW_FILE};
 use log::{debug, info, warn};
 use regex::Regex;
 use std::collections::HashMap;
-use std::fs;
 use std::fs::File;
 use std::io::{Read, Write};
 use std::path::Path;
@@ -67,10 +65,9 @@ pub fn apply_instruction(root_path: &str, instruction: Instruction) -> Result<Co
             gateway,
             ..
         } => add_route(root_path, route_match, gateway),
-        Instruction::SetRootPassword(tp) => match tp {
-            RootPasswordType::Clear(_) => Err(anyhow!(InstructionError::UnencryptedPassword)),
-            RootPasswordType::Hash(hash) => set_root_password_hash(root_path, &hash),
-        },
+        Instruction::SetRootPassword(tp) => {
+            set_root_password_hash(root_path, &tp.hash_for_root(root_path)?)
+        }
         Instruction::SetHostname(hostname) => set_hostname(root_path, &hostname),
         Instruction::SetKeymap(keymap) => setup_keyboard(root_path, &keymap),
         Instruction::SetTimezone(tz) => setup_timezone(root_path, &tz),
@@ -238,19 +235,25 @@ fn set_hostname(root_path: &str, hostname: &str) -> Result<CommandOutput> {
 }
 
 fn set_root_password_hash(root_path: &str, hash: &str) -> Result<CommandOutput> {
-    let p = Path::new(root_path);
-    let shadow_path = p.join(SHADOW_FILE);
-    let contents = fs::read_to_string(&shadow_path)?;
+    let shadow_path = Path::new(root_path).join(SHADOW_FILE.trim_start_matches('/'));
     info!(target: "libsysconfig", "Setting root password to hash given");
-    let mut shadow = parse_shadow_file(&contents)?;
-    if let Some(mut root_user) = shadow.get_entry("root") {
-        root_user.set_password_hash(&hash);
-        shadow.insert_or_update(root_user);
-
-        let new_file = shadow.serialize();
-        fs::write(&shadow_path, &new_file)?;
-    } else {
-        warn!(target: "libsysconfig", "No root user present in shadow file skipping setting the password")
+    let result = update_shadow_file(&shadow_path, |shadow| match shadow.get_entry_mut("root") {
+        Some(root_user) => {
+            root_user.set_password_hash(hash);
+            Ok(())
+        }
+        None => Err(anyhow!(ShadowError::UnknownUser("root".into()))),
+    });
+    match result {
+        Ok(shadow) => {
+            for warning in shadow.warnings() {
+                warn!(target: "libsysconfig", "{}: {}", shadow_path.display(), warning);
+            }
+        }
+        Err(e) if matches!(e.downcast_ref(), Some(ShadowError::UnknownUser(_))) => {
+            warn!(target: "libsysconfig", "No root user present in shadow file skipping setting the password")
+        }
+        Err(e) => return Err(e),
     }
 
     Ok(CommandOutput {
@@ -343,7 +346,7 @@ fn setup_dns(
     root_path: &str,
     nameservers: Vec<String>,
     domain: Option<String>,
-    search: Option<String>,
+    search: Vec<String>,
 ) -> Result<CommandOutput> {
     let p = Path::new(root_path);
     let mut resolv_conf = String::new();
@@ -360,7 +363,8 @@ fn setup_dns(
         resolv_conf += format!("\ndomain {}", dom).as_str();
     }
 
-    if let Some(se) = search {
+    if !search.is_empty() {
+        let se = search.join(" ");
         info!(target: "libsysconfig", "Setting DNS search to {}", &se);
         resolv_conf += format!("\nsearch {}", se).as_str();
     }
@@ -1,81 +1,34 @@
-use libcfgparser::KeywordDefinition;
+use crate::Instruction;
+use libcfgparser::{FromKeyword, KeywordDefinition, OptionDefinition, ValueType};
+use libshadow::CryptAlgorithm;
 
-pub fn get_supported_keywords() -> Vec<(String, KeywordDefinition)> {
+/// The addresses of a network interface. Without an address DHCP is used.
+pub(crate) fn network_address_options() -> Vec<OptionDefinition> {
+    vec![
+        OptionDefinition::new("static", ValueType::IpAddress)
+            .with_description("Static address with prefix length like 192.168.1.10/24"),
+        OptionDefinition::new("static6", ValueType::IpAddress)
+            .with_description("Static IPv6 address with prefix length"),
+    ]
+}
+
+/// How a clear text root password is hashed
+pub(crate) fn root_password_options() -> Vec<OptionDefinition> {
     vec![
-        ("keyboard".into(), KeywordDefinition { options: vec![] }),
-        ("timezone".into(), KeywordDefinition { options: vec![] }),
-        ("terminal".into(), KeywordDefinition { options: vec![] }),
-        ("timeserver".into(), KeywordDefinition { options: vec![] }),
-        (
-            "system_locale".into(),
-            KeywordDefinition { options: vec![] },
-        ),
-        (
-            "network_interface".into(),
-            KeywordDefinition {
-                options: vec![
-                    "name".into(),
-                    "static".into(),
-                    "static6".into(),
-                    "primary".into(),
-                ],
-            },
-        ),
-        (
-            "dataset".into(),
-            KeywordDefinition {
-                options: vec![
-                    "aclinherit".into(),
-                    "aclmode".into(),
-                    "atime".into(),
-                    "canmount".into(),
-                    "checksum".into(),
-                    "compression".into(),
-                    "copies".into(),
-                    "devices".into(),
-                    "encryption".into(),
-                    "keyformat".into(),
-                    "keylocation".into(),
-                    "exec".into(),
-                    "filesystem_limit".into(),
-                    "special_small_blocks".into(),
-                    "mountpoint".into(),
-                    "nbmand".into(),
-                    "pbkdf2iters".into(),
-                    "primarycache".into(),
-                    "quota".into(),
-                    "snapshot_limit".into(),
-                    "readonly".into(),
-                    "recordsize".into(),
-                    "redundant_metadata".into(),
-                    "refquota".into(),
-                    "refreservation".into(),
-                    "reservation".into(),
-                    "secondarycache".into(),
-                    "setuid".into(),
-                    "sharesmb".into(),
-                    "sharenfs".into(),
-                    "logbias".into(),
-                    "snapdir".into(),
-                    "sync".into(),
-                    "vscan".into(),
-                    "xattr".into(),
-                    "casesensitivity".into(),
-                    "normalization".into(),
-                    "utf8only".into(),
-                ],
-            },
-        ),
-        (
-            "setup_dns".into(),
-            KeywordDefinition {
-                options: vec!["search".into(), "domain".into()],
-            },
-        ),
-        ("route".into(), KeywordDefinition { options: vec![] }),
-        (
-            "root_password".into(),
-            KeywordDefinition { options: vec![] },
-        ),
+        OptionDefinition::new(
+            "algorithm",
+            ValueType::Enum(CryptAlgorithm::ALL.iter().map(|a| a.to_string()).collect()),
+        )
+        .with_description("crypt(3C) algorithm instead of the CRYPT_DEFAULT of the image"),
+        OptionDefinition::new("rounds", ValueType::Integer)
+            .with_description("Rounds of the algorithm, the cost for bcrypt"),
+        OptionDefinition::new("check-policy", ValueType::Boolean)
+            .with_default("false")
+            .with_description("Reject passwords breaking /etc/default/passwd of the image"),
     ]
 }
+
+/// The definitions of all keywords, generated from [`Instruction`]
+pub fn get_supported_keywords() -> Vec<(String, KeywordDefinition)> {
+    Instruction::keyword_definitions()
+}
@@ -1,3 +1,4 @@
+mod facts;
 mod illumos_driver;
 mod keywords;
 mod mock_driver;
@@ -6,10 +7,14 @@ extern crate tera;
 
 use anyhow::{anyhow, Result};
 pub use command::{svccfg, svccfg_stdin};
+pub use facts::{system_facts, FACT_NAMES};
 pub use keywords::get_supported_keywords;
-use lazy_static::lazy_static;
-use libcfgparser::Keyword;
-use regex::Regex;
+use libcfgparser::derive_support::required_argument;
+use libcfgparser::{Diagnostic, FromKeyword, Keyword};
+use libshadow::{CryptAlgorithm, CryptPolicy, PasswordPolicy};
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 pub use svcprop::svcprop;
@@ -17,13 +22,72 @@ use thiserror::Error;
 
 pub type InstructionsSet = Vec<Instruction>;
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
 pub enum RootPasswordType {
     Clear(String),
     Hash(String),
+    /// Clear text of the native format which is hashed with the crypt
+    /// policy of the image, see [`hash_root_passwords`]. Can not be
+    /// serialized so it never ends up in an instruction set.
+    #[serde(skip)]
+    Unhashed {
+        password: String,
+        algorithm: Option<CryptAlgorithm>,
+        rounds: Option<u32>,
+        check_policy: bool,
+    },
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+impl RootPasswordType {
+    /// The hash to write into the shadow file of the image at
+    /// `root_path`. Clear text is hashed with `--algorithm` or the
+    /// CRYPT_DEFAULT of the image, `--rounds` applies to either. With
+    /// `--check-policy` it has to meet /etc/default/passwd of the image.
+    fn hash_for_root(self, root_path: &str) -> Result<String> {
+        match self {
+            RootPasswordType::Clear(_) => Err(anyhow!(InstructionError::UnencryptedPassword)),
+            RootPasswordType::Hash(hash) => Ok(hash),
+            RootPasswordType::Unhashed {
+                password,
+                algorithm,
+                rounds,
+                check_policy,
+            } => {
+                if check_policy {
+                    PasswordPolicy::from_root(root_path)?.enforce(Some("root"), &password)?;
+                }
+                let mut policy = match algorithm {
+                    Some(algorithm) => CryptPolicy::new(algorithm),
+                    None => CryptPolicy::from_root(root_path)?,
+                };
+                if let Some(rounds) = rounds {
+                    policy.rounds = Some(rounds);
+                }
+                policy.hash(&password)
+            }
+        }
+    }
+}
+
+/// Hash the clear text root passwords of `instructions` with the crypt
+/// policy of the image at `root_path`, so the instructions can be
+/// serialized and shipped to the image.
+pub fn hash_root_passwords(
+    root_path: &str,
+    instructions: InstructionsSet,
+) -> Result<InstructionsSet> {
+    instructions
+        .into_iter()
+        .map(|instruction| match instruction {
+            Instruction::SetRootPassword(tp @ RootPasswordType::Unhashed { .. }) => Ok(
+                Instruction::SetRootPassword(RootPasswordType::Hash(tp.hash_for_root(root_path)?)),
+            ),
+            instruction => Ok(instruction),
+        })
+        .collect()
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
 pub enum NetworkConfig {
     DHCP,
     DHCPStateful,
@@ -36,47 +100,162 @@ pub enum NetworkConfig {
 //TODO VNIC Setup
 //TODO IPMP Setup
 //TODO Etherstub Setup (mostly because VXLAN)
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Keyword, JsonSchema)]
 pub enum Instruction {
+    /// Create a ZFS dataset. Every option is set as ZFS property of the
+    /// dataset, `--property=name=value` sets properties without an option.
+    #[keyword(
+        name = "dataset",
+        options = "libcfgparser::zfs::dataset_options",
+        example = "dataset --compression=lz4 --mountpoint=/export rpool/export"
+    )]
     CreateDataset {
+        #[keyword(arg)]
         name: String,
+        #[keyword(with = "dataset_properties")]
         properties: Option<HashMap<String, String>>,
     },
+    /// Set the default locale of the system. Locales without a codeset
+    /// are UTF-8.
+    #[keyword(name = "system_locale", example = "system_locale en_US.UTF-8")]
     SetLocale {
+        #[keyword(arg)]
         name: String,
+        #[keyword(with = "locale_is_unicode")]
         unicode: bool,
     },
+    /// Configure the DNS resolver with one or more name servers
+    #[keyword(
+        name = "setup_dns",
+        arguments = "1..",
+        example = "setup_dns --domain=example.com 192.168.1.1 192.168.1.2"
+    )]
     SetupDNS {
+        /// Local domain name of the system
+        #[keyword(option)]
         domain: Option<String>,
-        search: Option<String>,
+        /// Domain to search for short host names
+        #[serde(default, deserialize_with = "string_or_list")]
+        #[schemars(schema_with = "string_or_list_schema")]
+        #[keyword(option)]
+        search: Vec<String>,
+        #[keyword(arg)]
         nameservers: Vec<String>,
     },
+    /// Add a persistent route to the network `match` via `gateway`.
+    /// Given only two arguments the name is used as network, `default`
+    /// adds the default route.
+    #[keyword(
+        name = "route",
+        arguments = "2..3",
+        argument_names = "name match gateway",
+        example = "route default 192.168.1.1"
+    )]
     AddRoute {
+        #[keyword(arg)]
         name: String,
+        #[keyword(with = "route_match")]
         route_match: String,
+        #[keyword(with = "route_gateway")]
         gateway: String,
     },
-    SetRootPassword(RootPasswordType),
+    /// Set the password of root. Passwords which are not a crypt(3C)
+    /// hash yet are hashed with `--algorithm` or the CRYPT_DEFAULT of the
+    /// image, clear text never ends up in an instruction set. With
+    /// `--check-policy` the password has to meet the rules of
+    /// /etc/default/passwd of the image.
+    #[keyword(
+        name = "root_password",
+        arguments = "1",
+        argument_names = "password",
+        options = "keywords::root_password_options"
+    )]
+    SetRootPassword(#[keyword(with = "root_password")] RootPasswordType),
+    #[keyword(skip)]
     SetHostname(String),
+    /// Set the keyboard layout of the console
+    #[keyword(name = "keyboard", example = "keyboard US-English")]
     SetKeymap(String),
+    /// Set the time zone of the system
+    #[keyword(name = "timezone", example = "timezone Europe/Zurich")]
     SetTimezone(String),
+    /// Configure a terminal type for the console. The type is given either
+    /// as argument or with `--type`.
+    #[keyword(name = "terminal", example = "terminal --label=serial vt100")]
     SetupTerminal {
+        /// Serial port under /dev/term to configure a login service for
+        #[keyword(option)]
         name: Option<String>,
+        /// ttymon label of the terminal, defaults to console
+        #[keyword(option)]
         label: Option<String>,
+        /// STREAMS modules to push, defaults to ldterm,ttcompat
+        #[keyword(option = "module")]
         modules: Option<String>,
+        /// Login prompt of the terminal
+        #[keyword(option)]
         prompt: Option<String>,
+        /// Terminal type like vt100 or xterm
+        #[keyword(option = "type", arg)]
         terminal_type: String,
     },
+    /// Synchronize the clock with the NTP server
+    #[keyword(name = "timeserver", example = "timeserver 0.pool.ntp.org")]
     SetTimeServer(String),
+    /// Configure the addresses of a network interface. Without static
+    /// address the interface uses DHCP.
+    #[keyword(
+        name = "network_interface",
+        options = "keywords::network_address_options",
+        example = "network_interface e1000g0 { static 192.168.1.10/24; primary }"
+    )]
     ConfigureNetworkAdapter {
+        #[keyword(arg)]
         device: String,
+        /// Address object name appended to the device, defaults to /v4 or /v6
+        #[keyword(option)]
         name: Option<String>,
+        #[keyword(with = "ipv4_config")]
         ipv4: Option<NetworkConfig>,
+        #[keyword(with = "ipv6_config")]
         ipv6: Option<NetworkConfig>,
+        /// Use the interface for the primary hostname of the system
+        #[keyword(option)]
         primary: bool,
     },
 }
 
+#[derive(Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// Accept a single string, a list of strings or null. Older configurations
+/// had only a single DNS search domain.
+fn string_or_list<'de, D>(deserializer: D) -> std::result::Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(match Option::<OneOrMany>::deserialize(deserializer)? {
+        None => vec![],
+        Some(OneOrMany::One(s)) => vec![s],
+        Some(OneOrMany::Many(v)) => v,
+    })
+}
+
+/// The schema of what [`string_or_list`] accepts
+fn string_or_list_schema(gen: &mut SchemaGenerator) -> Schema {
+    <Option<OneOrMany>>::json_schema(gen)
+}
+
+/// The JSON schema of [`InstructionsSet`] which JSON, YAML and TOML
+/// configurations are checked against
+pub fn instructions_schema() -> serde_json::Value {
+    libcfgparser::json_schema::<InstructionsSet>()
+}
+
 #[allow(dead_code)]
 #[derive(Debug)]
 pub struct CommandOutput {
@@ -87,211 +266,113 @@ pub struct CommandOutput {
 
 #[derive(Error, Debug)]
 enum InstructionError {
-    #[error("keyword {0} is not known")]
-    UnknownInstruction(String),
-    #[error("option {0} is not known for instruction {1}")]
-    UnknownOptionInInstruction(String, String),
+    #[error("property {0} is not in the form key=value")]
+    InvalidProperty(String),
     #[error("applying instruction failed: command: {command} returned {output}")]
     CommandFailed { command: String, output: String },
     #[error("The root password has not been encrypted and hashed, aborting")]
     UnencryptedPassword,
 }
 
-pub fn parse_keywords(keywords: Vec<Keyword>) -> Result<InstructionsSet> {
-    let mut set = InstructionsSet::new();
-    for c in keywords {
-        match c.name.as_str() {
-            "keyboard" => {
-                set.push(Instruction::SetKeymap(c.arguments[0].clone()));
+/// The options of a dataset keyword as ZFS properties. `--property="key=value"`
+/// may be given multiple times for properties without an option of their own.
+fn dataset_properties(c: &Keyword) -> Result<Option<HashMap<String, String>>> {
+    let mut properties = HashMap::<String, String>::new();
+    for opt in &c.options {
+        if opt.name == "property" {
+            let raw = opt.value.to_string();
+            if let Some((key, value)) = raw.split_once('=') {
+                properties.insert(key.into(), value.into());
+            } else {
+                return Err(anyhow!(Diagnostic::new(
+                    &opt.span,
+                    InstructionError::InvalidProperty(raw)
+                )));
             }
-            "timezone" => {
-                set.push(Instruction::SetTimezone(c.arguments[0].clone()));
-            }
-            "terminal" => {
-                let (name_option, label_option, module_option, prompt_option, terminal_type) =
-                    if let Some(opts) = c.options {
-                        let mut name_option: Option<String> = None;
-                        let mut label_option: Option<String> = None;
-                        let mut module_option: Option<String> = None;
-                        let mut prompt_option: Option<String> = None;
-                        let mut terminal_type = String::new();
-
-                        for (key, value) in opts {
-                            match key.as_str() {
-                                "name" => {
-                                    name_option = Some(value);
-                                }
-                                "label" => {
-                                    label_option = Some(value);
-                                }
-                                "module" => {
-                                    module_option = Some(value);
-                                }
-                                "prompt" => {
-                                    prompt_option = Some(value);
-                                }
-                                "type" => {
-                                    terminal_type = value;
-                                }
-                                _ => {
-                                    return Err(anyhow!(
-                                        InstructionError::UnknownOptionInInstruction(c.name, key)
-                                    ))
-                                }
-                            }
-                        }
+        } else {
+            properties.insert(opt.name.clone(), opt.value.to_string());
+        }
+    }
+    Ok(if properties.is_empty() {
+        None
+    } else {
+        Some(properties)
+    })
+}
 
-                        if terminal_type.is_empty() {
-                            terminal_type = c.arguments[0].clone()
-                        }
+/// Locales without a codeset like en_US are unicode
+fn locale_is_unicode(c: &Keyword) -> Result<bool> {
+    let locale_name = required_argument(c, 0)?;
+    Ok(locale_name.to_uppercase().contains(".UTF-8") || !locale_name.contains('.'))
+}
 
-                        (
-                            name_option,
-                            label_option,
-                            module_option,
-                            prompt_option,
-                            terminal_type,
-                        )
-                    } else {
-                        (None, None, None, None, c.arguments[0].clone())
-                    };
-                set.push(Instruction::SetupTerminal {
-                    name: name_option,
-                    label: label_option,
-                    modules: module_option,
-                    prompt: prompt_option,
-                    terminal_type,
-                });
-            }
-            "timeserver" => {
-                set.push(Instruction::SetTimeServer(c.arguments[0].clone()));
-            }
-            "network_interface" => {
-                let parsed_options = if let Some(opts) = c.options {
-                    let name_option = if opts.contains_key("name") {
-                        Some(opts["name"].clone())
-                    } else {
-                        None
-                    };
-                    let (ipv4_option, ipv6_option) = if opts.contains_key("static") {
-                        let static_addr = opts["static"].clone();
-                        if static_addr.contains(":") {
-                            (None, Some(NetworkConfig::Static(static_addr)))
-                        } else if opts.contains_key("static6") {
-                            let static6_addr = opts["static6"].clone();
-                            (
-                                Some(NetworkConfig::Static(static_addr)),
-                                Some(NetworkConfig::Static(static6_addr)),
-                            )
-                        } else {
-                            (Some(NetworkConfig::Static(static_addr)), None)
-                        }
-                    } else if opts.contains_key("static6") {
-                        let static6_addr = opts["static6"].clone();
-                        (None, Some(NetworkConfig::Static(static6_addr)))
-                    } else {
-                        (Some(NetworkConfig::DHCP), Some(NetworkConfig::DHCPStateful))
-                    };
+/// The network is the argument before the gateway, which is the last one
+fn route_match(c: &Keyword) -> Result<String> {
+    required_argument(c, 1)?;
+    required_argument(c, c.arguments.len() - 2)
+}
 
-                    let primary_option = if opts.contains_key("primary") {
-                        true
-                    } else {
-                        false
-                    };
+fn route_gateway(c: &Keyword) -> Result<String> {
+    required_argument(c, 1)?;
+    required_argument(c, c.arguments.len() - 1)
+}
 
-                    (name_option, ipv4_option, ipv6_option, primary_option)
-                } else {
-                    (None, None, None, false)
-                };
-                set.push(Instruction::ConfigureNetworkAdapter {
-                    device: c.arguments[0].clone(),
-                    name: parsed_options.0,
-                    ipv4: parsed_options.1,
-                    ipv6: parsed_options.2,
-                    primary: parsed_options.3,
-                })
-            }
-            "system_locale" => {
-                let locale_name = c.arguments[0].clone();
-                let unicode = if locale_name.to_uppercase().contains(".UTF-8") {
-                    true
-                } else if locale_name.contains(".") {
-                    false
-                } else {
-                    true
-                };
-                set.push(Instruction::SetLocale {
-                    name: locale_name,
-                    unicode,
-                });
-            }
-            "dataset" => {
-                set.push(Instruction::CreateDataset {
-                    name: c.arguments[0].clone(),
-                    properties: c.options.clone(),
-                });
-            }
-            "setup_dns" => {
-                let (option_domain, option_search) = if let Some(opts) = c.options {
-                    (
-                        if opts.contains_key("domain") {
-                            Some(opts["domain"].clone())
-                        } else {
-                            None
-                        },
-                        if opts.contains_key("search") {
-                            Some(opts["search"].clone())
-                        } else {
-                            None
-                        },
-                    )
-                } else {
-                    (None, None)
-                };
-                set.push(Instruction::SetupDNS {
-                    domain: option_domain,
-                    search: option_search,
-                    nameservers: c.arguments.clone(),
-                });
-            }
-            "route" => {
-                if c.arguments.len() > 2 {
-                    set.push(Instruction::AddRoute {
-                        name: c.arguments[0].clone(),
-                        route_match: c.arguments[1].clone(),
-                        gateway: c.arguments[2].clone(),
-                    });
-                } else {
-                    set.push(Instruction::AddRoute {
-                        name: c.arguments[0].clone(),
-                        route_match: c.arguments[0].clone(),
-                        gateway: c.arguments[1].clone(),
-                    })
-                }
-            }
-            "root_password" => {
-                lazy_static! {
-                    static ref RE: Regex = Regex::new(r"^\$\d\$").unwrap();
-                }
-                set.push(Instruction::SetRootPassword(
-                    if RE.is_match(&c.arguments[0]) {
-                        RootPasswordType::Hash(c.arguments[0].clone())
-                    } else {
-                        RootPasswordType::Hash(libshadow::gen_password_hash(
-                            &c.arguments[0].clone(),
-                        )?)
-                    },
-                ))
-            }
-            _ => {
-                return Err(anyhow!(InstructionError::UnknownInstruction(
-                    c.name.clone()
-                )))
-            }
+fn root_password(c: &Keyword) -> Result<RootPasswordType> {
+    let password = required_argument(c, 0)?;
+    let check_policy = c.flag("check-policy").unwrap_or(false);
+    if libshadow::is_password_hash(&password) {
+        if check_policy {
+            return Err(anyhow!(
+                "--check-policy needs a clear text password, a hash can not be checked"
+            ));
         }
+        return Ok(RootPasswordType::Hash(password));
+    }
+    let algorithm = match c.option("algorithm") {
+        Some(o) => Some(o.value.to_string().parse::<CryptAlgorithm>()?),
+        None => None,
+    };
+    let rounds = match c.option("rounds").and_then(|o| o.value.as_integer()) {
+        Some(rounds) => Some(u32::try_from(rounds)?),
+        None => None,
+    };
+    Ok(RootPasswordType::Unhashed {
+        password,
+        algorithm,
+        rounds,
+        check_policy,
+    })
+}
+
+/// An IPv6 address given as --static counts as --static6. Without any
+/// address the interface is configured with DHCP.
+fn static_addresses(c: &Keyword) -> (Option<String>, Option<String>) {
+    let static_addr = c.option("static").map(|o| o.value.to_string());
+    let static6_addr = c.option("static6").map(|o| o.value.to_string());
+    match (static_addr, static6_addr) {
+        (Some(addr), _) if addr.contains(':') => (None, Some(addr)),
+        addresses => addresses,
     }
+}
+
+fn ipv4_config(c: &Keyword) -> Result<Option<NetworkConfig>> {
+    Ok(match static_addresses(c) {
+        (Some(addr), _) => Some(NetworkConfig::Static(addr)),
+        (None, Some(_)) => None,
+        (None, None) => Some(NetworkConfig::DHCP),
+    })
+}
 
-    Ok(set)
+fn ipv6_config(c: &Keyword) -> Result<Option<NetworkConfig>> {
+    Ok(match static_addresses(c) {
+        (_, Some(addr)) => Some(NetworkConfig::Static(addr)),
+        (Some(_), None) => None,
+        (None, None) => Some(NetworkConfig::DHCPStateful),
+    })
+}
+
+pub fn parse_keywords(keywords: Vec<Keyword>) -> Result<InstructionsSet> {
+    keywords.iter().map(Instruction::from_keyword).collect()
 }
 
 pub enum Driver {
@@ -1,6 +1,9 @@
 use anyhow::{anyhow, Result};
 use clap::Parser;
-use libshadow::{gen_password_hash, parse_shadow_file, SHADOW_FILE};
+use libshadow::{
+    is_password_hash, parse_shadow_file, today, update_shadow_file, AccountStatus, CryptAlgorithm,
+    CryptPolicy, PasswordPolicy, ShadowFile, SHADOW_FILE,
+};
 use std::fs;
 
 /// shadow file modification utility
@@ -23,18 +26,96 @@ struct Args {
     #[clap(short, long)]
     file: Option<String>,
 
+    /// Print the password and aging status like passwd -s, of the -s
+    /// entry or of all entries
+    #[clap(short = 'S', long)]
+    status: bool,
+
+    /// Write the modified shadow file back instead of printing it
+    #[clap(short, long)]
+    write: bool,
+
+    /// Hash algorithm instead of the CRYPT_DEFAULT of policy.conf
+    /// (sha256-crypt, sha512-crypt, bcrypt, sun-md5)
+    #[clap(short, long)]
+    algorithm: Option<CryptAlgorithm>,
+
+    /// Rounds of the hash algorithm, the cost for bcrypt
+    #[clap(short, long)]
+    rounds: Option<u32>,
+
+    /// Reject clear text passwords which do not meet the rules of
+    /// /etc/default/passwd
+    #[clap(short, long)]
+    check_policy: bool,
+
+    /// Root of the system whose policy.conf, crypt.conf and
+    /// /etc/default/passwd are used
+    #[clap(long, default_value = "/")]
+    root: String,
+
     input: Option<String>,
 }
 
+/// The policy clear text passwords are hashed with. policy.conf and
+/// crypt.conf of the root are only read when no algorithm is given.
+fn crypt_policy(
+    root: &str,
+    algorithm: Option<CryptAlgorithm>,
+    rounds: Option<u32>,
+) -> Result<CryptPolicy> {
+    let mut policy = match algorithm {
+        Some(algorithm) => CryptPolicy::new(algorithm),
+        None => CryptPolicy::from_root(root)?,
+    };
+    if let Some(rounds) = rounds {
+        policy.rounds = Some(rounds);
+    }
+    Ok(policy)
+}
+
 fn main() -> Result<()> {
     let args = Args::parse();
 
+    let password_policy = if args.check_policy {
+        Some(PasswordPolicy::from_root(&args.root)?)
+    } else {
+        None
+    };
+
     if let Some(hash) = args.hash {
-        let hash = gen_password_hash(&hash)?;
+        if let Some(password_policy) = &password_policy {
+            password_policy.enforce(None, &hash)?;
+        }
+        let hash = crypt_policy(&args.root, args.algorithm, args.rounds)?.hash(&hash)?;
         println!("{}", hash);
         return Ok(());
     }
 
+    if args.status {
+        let path = args.file.unwrap_or_else(|| SHADOW_FILE.to_string());
+        let shadow = parse_shadow_file(&fs::read_to_string(&path)?)?;
+        for warning in shadow.warnings() {
+            eprintln!("warning: {}", warning);
+        }
+        let today = today();
+        let status: Vec<AccountStatus> = match &args.shadow {
+            Some(name) => vec![shadow
+                .get_entry(name)
+                .ok_or_else(|| anyhow!("No entry named {} in shadow file", name))?
+                .status(today)],
+            None => shadow.entries().iter().map(|e| e.status(today)).collect(),
+        };
+        if args.json {
+            println!("{}", serde_json::to_string_pretty(&status)?);
+        } else {
+            for s in status {
+                println!("{}", s);
+            }
+        }
+        return Ok(());
+    }
+
     if let Some(shadow_name) = args.shadow {
         if args.input.is_none() {
             return Err(anyhow!(
@@ -42,32 +123,45 @@ fn main() -> Result<()> {
             ));
         }
 
-        let contents = if let Some(file) = args.file {
-            fs::read_to_string(file)?
-        } else {
-            fs::read_to_string(SHADOW_FILE)?
-        };
-        let mut shadow = parse_shadow_file(&contents)?;
-        let account_name: String = shadow_name;
+        let path = args.file.unwrap_or_else(|| SHADOW_FILE.to_string());
         let pw_or_hash: String = args.input.unwrap();
-
-        if let Some(mut entry) = shadow.get_entry(&account_name) {
-            // Assume it's a Hash if input starts with $
-            if pw_or_hash.starts_with("$") {
+        if password_policy.is_some() && is_password_hash(&pw_or_hash) {
+            return Err(anyhow!(
+                "--check-policy needs a clear text password, a hash can not be checked"
+            ));
+        }
+        let set_password = |shadow: &mut ShadowFile| -> Result<()> {
+            let entry = shadow
+                .get_entry_mut(&shadow_name)
+                .ok_or_else(|| anyhow!("No entry named {} in shadow file", shadow_name))?;
+            if is_password_hash(&pw_or_hash) {
                 entry.set_password_hash(&pw_or_hash)
             } else {
-                entry.update_password_hash(&pw_or_hash)?;
+                if let Some(password_policy) = &password_policy {
+                    password_policy.enforce(Some(&shadow_name), &pw_or_hash)?;
+                }
+                let policy = crypt_policy(&args.root, args.algorithm, args.rounds)?;
+                entry.update_password_hash_with(&pw_or_hash, &policy)?;
             }
-            shadow.insert_or_update(entry);
-            let new_file = shadow.serialize();
+            Ok(())
+        };
 
-            println!("{}", new_file)
+        if args.write {
+            let shadow = update_shadow_file(&path, set_password)?;
+            for warning in shadow.warnings() {
+                eprintln!("warning: {}", warning);
+            }
         } else {
-            return Err(anyhow!("No entry named {} in shadow file", account_name));
+            let mut shadow = parse_shadow_file(&fs::read_to_string(&path)?)?;
+            for warning in shadow.warnings() {
+                eprintln!("warning: {}", warning);
+            }
+            set_password(&mut shadow)?;
+            println!("{}", shadow.serialize())
         }
 
         return Ok(());
     }
 
-    Err(anyhow!("Please either specify -H or -s for modes"))
+    Err(anyhow!("Please either specify -H, -S or -s for modes"))
 }
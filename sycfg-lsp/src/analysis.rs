@@ -0,0 +1,741 @@
+use libcfgparser::docs::{keyword_markdown, option_markdown, option_signature, signature};
+use libcfgparser::{
+    resolve_include, Document, FactProvider, KeywordDefinition, OptionDefinition, ParserError,
+    ParserErrors, Span, SysConfigParser, ValueType,
+};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionTextEdit, Diagnostic, DiagnosticSeverity, Hover,
+    HoverContents, Location, MarkupContent, MarkupKind, Position, Range, TextEdit, Url,
+};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Statements the parser handles itself
+const DIRECTIVES: &[&str] = &["set", "include", "if", "else", "endif"];
+
+/// Facts are only known on the machine being configured. While editing
+/// every known fact exists but has no value, so `if` conditions are checked
+/// for unknown facts without matching anything.
+#[derive(Debug)]
+struct EditorFacts;
+
+impl FactProvider for EditorFacts {
+    fn fact(&self, name: &str) -> Option<Vec<String>> {
+        libsysconfig::FACT_NAMES.contains(&name).then(Vec::new)
+    }
+}
+
+/// The statement the cursor is in
+struct Context {
+    /// The keywords of the blocks around the statement, outermost first
+    blocks: Vec<String>,
+    /// The tokens of the statement before the cursor
+    tokens: Vec<String>,
+    /// Byte offset of the last token if the cursor is at its end
+    partial: Option<usize>,
+}
+
+/// The lines of `text` with their line break. Like in the grammar a line
+/// ends with `\n`, `\r\n` or a lone `\r`.
+fn lines(text: &str) -> impl Iterator<Item = &str> {
+    let mut rest = text;
+    std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        let end = match rest.find(['\n', '\r']) {
+            Some(i) if rest[i..].starts_with("\r\n") => i + 2,
+            Some(i) => i + 1,
+            None => rest.len(),
+        };
+        let (line, tail) = rest.split_at(end);
+        rest = tail;
+        Some(line)
+    })
+}
+
+/// Split the text before `offset` into statements the way the grammar
+/// does, close enough for completion and hover
+fn context(text: &str, offset: usize) -> Context {
+    let mut blocks: Vec<String> = vec![];
+    let mut tokens: Vec<String> = vec![];
+    let mut current = String::new();
+    let mut current_start = 0;
+    let mut in_string = false;
+    let mut heredoc: Option<String> = None;
+    let mut chars = text[..offset].char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        if let Some(tag) = &heredoc {
+            // skip the body up to the terminator line
+            let line = lines(&text[i..offset]).next().unwrap_or_default();
+            let line_end = line.ends_with(['\n', '\r']).then(|| i + line.len() - 1);
+            if line.trim_end_matches(['\n', '\r']).trim_start_matches('\t') == tag {
+                heredoc = None;
+            }
+            if let Some(end) = line_end {
+                while chars.peek().is_some_and(|(j, _)| *j <= end) {
+                    chars.next();
+                }
+            } else {
+                break;
+            }
+            continue;
+        }
+        if in_string {
+            current.push(c);
+            if c == '\\' {
+                if let Some((_, next)) = chars.next() {
+                    current.push(next);
+                }
+            } else if c == '"' {
+                in_string = false;
+            }
+            continue;
+        }
+
+        let finish = |tokens: &mut Vec<String>, current: &mut String| {
+            if !current.is_empty() {
+                tokens.push(std::mem::take(current));
+            }
+        };
+        match c {
+            '"' => {
+                if current.is_empty() {
+                    current_start = i;
+                }
+                in_string = true;
+                current.push(c);
+            }
+            '#' if current.is_empty() => {
+                while chars.peek().is_some_and(|(_, c)| !matches!(c, '\n' | '\r')) {
+                    chars.next();
+                }
+            }
+            '\\' if chars.peek().is_some_and(|(_, c)| matches!(c, '\n' | '\r')) => {
+                if chars.next().is_some_and(|(_, c)| c == '\r') {
+                    chars.next_if(|(_, c)| *c == '\n');
+                }
+                finish(&mut tokens, &mut current);
+            }
+            '\r' if chars.peek().is_some_and(|(_, c)| *c == '\n') => {}
+            '\n' | '\r' => {
+                finish(&mut tokens, &mut current);
+                heredoc = tokens
+                    .last()
+                    .and_then(|t| t.split_once("<<"))
+                    .map(|(_, tag)| tag.trim_start_matches('-').to_string())
+                    .filter(|tag| !tag.is_empty());
+                tokens.clear();
+            }
+            ';' => {
+                finish(&mut tokens, &mut current);
+                tokens.clear();
+            }
+            '{' => {
+                finish(&mut tokens, &mut current);
+                blocks.push(tokens.first().cloned().unwrap_or_default());
+                tokens.clear();
+            }
+            '}' => {
+                finish(&mut tokens, &mut current);
+                blocks.pop();
+                tokens.clear();
+            }
+            c if c.is_whitespace() => finish(&mut tokens, &mut current),
+            c => {
+                if current.is_empty() {
+                    current_start = i;
+                }
+                current.push(c);
+            }
+        }
+    }
+
+    let partial = if current.is_empty() {
+        None
+    } else {
+        tokens.push(current);
+        Some(current_start)
+    };
+    Context {
+        blocks,
+        tokens,
+        partial,
+    }
+}
+
+/// Byte offset of an LSP position, which counts UTF-16 code units
+pub fn offset(text: &str, position: Position) -> usize {
+    let mut offset = 0;
+    for (i, line) in lines(text).enumerate() {
+        if i == position.line as usize {
+            let mut units = 0;
+            for (b, c) in line.char_indices() {
+                if units >= position.character as usize || matches!(c, '\n' | '\r') {
+                    return offset + b;
+                }
+                units += c.len_utf16();
+            }
+            return offset + line.len();
+        }
+        offset += line.len();
+    }
+    text.len()
+}
+
+/// LSP position of a byte offset
+pub fn position(text: &str, offset: usize) -> Position {
+    let mut offset = offset.min(text.len());
+    while !text.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    // between the two characters of a \r\n is still the end of the line
+    if text[..offset].ends_with('\r') && text[offset..].starts_with('\n') {
+        offset -= 1;
+    }
+    let before = &text[..offset];
+    let mut line = 0;
+    let mut line_start = 0;
+    for l in lines(before).filter(|l| l.ends_with(['\n', '\r'])) {
+        line += 1;
+        line_start += l.len();
+    }
+    Position::new(line, before[line_start..].encode_utf16().count() as u32)
+}
+
+fn range(text: &str, span: &Span) -> Range {
+    let start = span.start.min(text.len());
+    // spans of statements include the line break
+    let mut end = start + text[start..span.end.min(text.len())].trim_end().len();
+    if end <= start {
+        // mark at least one character
+        end = text[start..]
+            .chars()
+            .next()
+            .filter(|c| !matches!(c, '\n' | '\r'))
+            .map(|c| start + c.len_utf8())
+            .unwrap_or(start);
+    }
+    Range::new(position(text, start), position(text, end))
+}
+
+fn markdown(value: String) -> Hover {
+    Hover {
+        contents: HoverContents::Markup(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value,
+        }),
+        range: None,
+    }
+}
+
+/// Answers the requests of the language server for one set of keywords
+pub struct Analyzer {
+    parser: SysConfigParser,
+    definitions: HashMap<String, KeywordDefinition>,
+}
+
+impl Analyzer {
+    pub fn new(definitions: Vec<(String, KeywordDefinition)>) -> Self {
+        let mut parser = SysConfigParser::default();
+        for (name, def) in &definitions {
+            parser.add_keyword(name.clone(), def.clone());
+        }
+        parser.set_fact_provider(Box::new(EditorFacts));
+        Analyzer {
+            parser,
+            definitions: definitions.into_iter().collect(),
+        }
+    }
+
+    /// The definition of the block the blocks in `blocks` describe
+    fn block_definition(&self, blocks: &[String]) -> Option<&KeywordDefinition> {
+        let (first, rest) = blocks.split_first()?;
+        let mut def = self.definitions.get(first)?;
+        for name in rest {
+            def = def.children.get(name)?;
+        }
+        Some(def)
+    }
+
+    /// The definition of the keyword a statement in `blocks` starts with
+    fn keyword_definition(&self, blocks: &[String], name: &str) -> Option<&KeywordDefinition> {
+        if blocks.is_empty() {
+            self.definitions.get(name)
+        } else {
+            self.block_definition(blocks)?.children.get(name)
+        }
+    }
+
+    /// Errors of the configuration as found by the parser. Keywords in
+    /// branches of `if` which are not taken are checked on their own.
+    pub fn diagnostics(&self, text: &str, path: Option<&Path>) -> Vec<Diagnostic> {
+        let result = match path {
+            Some(path) => self.parser.parse_config_with_path(text, path),
+            None => self.parser.parse_config(text),
+        };
+
+        let mut errors: Vec<ParserError> = vec![];
+        let mut diagnostics: Vec<Diagnostic> = vec![];
+        if let Err(err) = result {
+            match err.downcast::<ParserErrors>() {
+                Ok(errs) => errors.extend(errs.errors),
+                Err(err) => match err.downcast::<ParserError>() {
+                    Ok(e) => errors.push(e),
+                    Err(err) => diagnostics.push(Diagnostic {
+                        range: Range::default(),
+                        severity: Some(DiagnosticSeverity::ERROR),
+                        source: Some("sycfg".into()),
+                        message: err.to_string(),
+                        ..Default::default()
+                    }),
+                },
+            }
+        }
+
+        if let Ok(doc) = Document::parse(text) {
+            for keyword in doc.keywords() {
+                if DIRECTIVES.contains(&keyword.name.as_str()) || keyword.to_string().contains("${")
+                {
+                    continue;
+                }
+                let mut keyword = keyword.clone();
+                if let Err(err) = self.parser.check_keyword(&mut keyword) {
+                    if let Ok(e) = err.downcast::<ParserError>() {
+                        let known = errors.iter().any(|known| {
+                            known.span().start == e.span().start
+                                && known.to_string() == e.to_string()
+                        });
+                        if !known {
+                            errors.push(e);
+                        }
+                    }
+                }
+            }
+        }
+
+        for err in errors {
+            let span = err.span();
+            let (range, message) = match (&span.file, path) {
+                (Some(file), Some(path)) if file != path => {
+                    (Range::default(), format!("{}: {}", span, err))
+                }
+                _ => (range(text, span), err.to_string()),
+            };
+            // Variables can also be defined on the command line
+            let severity = match err {
+                ParserError::UndefinedVariable { .. } => DiagnosticSeverity::WARNING,
+                _ => DiagnosticSeverity::ERROR,
+            };
+            diagnostics.push(Diagnostic {
+                range,
+                severity: Some(severity),
+                source: Some("sycfg".into()),
+                message,
+                ..Default::default()
+            });
+        }
+        diagnostics
+    }
+
+    pub fn completion(&self, text: &str, position: Position) -> Vec<CompletionItem> {
+        let offset = offset(text, position);
+        let ctx = context(text, offset);
+        let replace = |start: usize, label: String, kind: CompletionItemKind, detail: String| {
+            CompletionItem {
+                label: label.clone(),
+                kind: Some(kind),
+                detail: Some(detail),
+                text_edit: Some(CompletionTextEdit::Edit(TextEdit::new(
+                    Range::new(self::position(text, start), position),
+                    label,
+                ))),
+                ..Default::default()
+            }
+        };
+        let start = ctx.partial.unwrap_or(offset);
+
+        // The first word of a statement is a keyword
+        if ctx.tokens.len() <= 1 && (ctx.tokens.is_empty() || ctx.partial.is_some()) {
+            let mut items = vec![];
+            if ctx.blocks.is_empty() {
+                for (name, def) in &self.definitions {
+                    items.push(replace(
+                        start,
+                        name.clone(),
+                        CompletionItemKind::KEYWORD,
+                        signature(name, def),
+                    ));
+                }
+                for directive in DIRECTIVES {
+                    items.push(replace(
+                        start,
+                        directive.to_string(),
+                        CompletionItemKind::KEYWORD,
+                        "directive".into(),
+                    ));
+                }
+            } else if let Some(def) = self.block_definition(&ctx.blocks) {
+                for (name, child) in &def.children {
+                    items.push(replace(
+                        start,
+                        name.clone(),
+                        CompletionItemKind::KEYWORD,
+                        signature(name, child),
+                    ));
+                }
+                for opt in &def.options {
+                    items.push(replace(
+                        start,
+                        opt.name.clone(),
+                        CompletionItemKind::PROPERTY,
+                        option_signature(opt),
+                    ));
+                }
+            }
+            return items;
+        }
+
+        let (partial, name) = match (ctx.partial, ctx.tokens.last(), ctx.tokens.first()) {
+            (Some(_), Some(partial), Some(name)) if partial.starts_with('-') => (partial, name),
+            _ => return vec![],
+        };
+        let def = match self.keyword_definition(&ctx.blocks, name) {
+            Some(def) => def,
+            None => return vec![],
+        };
+
+        // Values of an option with a fixed set of values
+        if let Some((option, _)) = partial.trim_start_matches('-').split_once('=') {
+            return match def.option(option).map(|o| &o.value_type) {
+                Some(ValueType::Enum(values)) => values
+                    .iter()
+                    .map(|v| {
+                        replace(
+                            start,
+                            format!("--{}={}", option, v),
+                            CompletionItemKind::ENUM_MEMBER,
+                            v.clone(),
+                        )
+                    })
+                    .collect(),
+                Some(ValueType::Boolean) => ["true", "false"]
+                    .iter()
+                    .map(|v| {
+                        replace(
+                            start,
+                            format!("--{}={}", option, v),
+                            CompletionItemKind::VALUE,
+                            v.to_string(),
+                        )
+                    })
+                    .collect(),
+                _ => vec![],
+            };
+        }
+
+        let given = |opt: &OptionDefinition| {
+            ctx.tokens[..ctx.tokens.len() - 1].iter().any(|t| {
+                let t = t.trim_start_matches("--");
+                let t = t.split_once('=').map(|(n, _)| n).unwrap_or(t);
+                t == opt.name || t.strip_prefix("no-") == Some(&opt.name)
+            })
+        };
+        let mut items = vec![];
+        for opt in def.options.iter().filter(|o| o.repeatable || !given(o)) {
+            let labels = match opt.value_type {
+                ValueType::Boolean => vec![format!("--{}", opt.name), format!("--no-{}", opt.name)],
+                _ => vec![format!("--{}=", opt.name)],
+            };
+            for label in labels {
+                items.push(replace(
+                    start,
+                    label,
+                    CompletionItemKind::PROPERTY,
+                    opt.description
+                        .clone()
+                        .unwrap_or_else(|| option_signature(opt)),
+                ));
+            }
+        }
+        items
+    }
+
+    pub fn hover(&self, text: &str, position: Position) -> Option<Hover> {
+        let offset = offset(text, position);
+        let is_word = |c: char| c.is_alphanumeric() || "-_.".contains(c);
+        let start = text[..offset]
+            .char_indices()
+            .rev()
+            .take_while(|(_, c)| is_word(*c))
+            .last()
+            .map(|(i, _)| i)
+            .unwrap_or(offset);
+        let end = text[offset..]
+            .char_indices()
+            .find(|(_, c)| !is_word(*c))
+            .map(|(i, _)| offset + i)
+            .unwrap_or(text.len());
+        let word = &text[start..end];
+        if word.is_empty() {
+            return None;
+        }
+
+        let ctx = context(text, start);
+        if let Some(option) = word.strip_prefix("--") {
+            let option = option.strip_prefix("no-").unwrap_or(option);
+            let def = self.keyword_definition(&ctx.blocks, ctx.tokens.first()?)?;
+            return def.option(option).map(|o| markdown(option_markdown(o)));
+        }
+        if !ctx.tokens.is_empty() {
+            return None;
+        }
+        if let Some(def) = self.keyword_definition(&ctx.blocks, word) {
+            return Some(markdown(keyword_markdown(word, def)));
+        }
+        let option = word.strip_prefix("no-").unwrap_or(word);
+        self.block_definition(&ctx.blocks)?
+            .option(option)
+            .map(|o| markdown(option_markdown(o)))
+    }
+
+    /// The files an include on the line at `position` refers to
+    pub fn definition(&self, text: &str, position: Position, path: &Path) -> Vec<Location> {
+        let line = lines(text).nth(position.line as usize).unwrap_or_default();
+        let pattern = match line.trim().strip_prefix("include") {
+            Some(rest) if rest.starts_with(char::is_whitespace) => rest
+                .split('#')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .trim_matches('"'),
+            _ => return vec![],
+        };
+        resolve_include(pattern, path)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|file| file.exists())
+            .filter_map(|file| Url::from_file_path(file).ok())
+            .map(|uri| Location::new(uri, Range::default()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn analyzer() -> Analyzer {
+        Analyzer::new(vec![
+            (
+                String::from("hostname"),
+                KeywordDefinition {
+                    min_arguments: 1,
+                    max_arguments: Some(1),
+                    ..Default::default()
+                },
+            ),
+            (
+                String::from("network_interface"),
+                KeywordDefinition {
+                    min_arguments: 1,
+                    max_arguments: Some(1),
+                    options: vec![
+                        OptionDefinition::new("static", ValueType::IpAddress),
+                        OptionDefinition::new("primary", ValueType::Boolean),
+                        OptionDefinition::new(
+                            "type",
+                            ValueType::Enum(vec!["ether".into(), "vlan".into()]),
+                        ),
+                    ],
+                    children: HashMap::from([(
+                        String::from("route"),
+                        KeywordDefinition {
+                            min_arguments: 1,
+                            max_arguments: Some(1),
+                            ..Default::default()
+                        },
+                    )]),
+                    ..Default::default()
+                },
+            ),
+        ])
+    }
+
+    /// The labels of completions, sorted
+    fn labels(items: Vec<CompletionItem>) -> Vec<String> {
+        let mut labels: Vec<String> = items.into_iter().map(|i| i.label).collect();
+        labels.sort();
+        labels
+    }
+
+    #[test]
+    fn offset_position_test() {
+        let text = "hostname \"bü😀\"\n# ä\r\nkeyboard\rlocale C\n";
+        // UTF-16 counts the emoji as two units
+        assert_eq!(
+            position(text, text.find('"').unwrap() + 8),
+            Position::new(0, 14)
+        );
+        assert_eq!(
+            offset(text, Position::new(0, 14)),
+            text.find('"').unwrap() + 8
+        );
+        assert_eq!(
+            position(text, text.find("keyboard").unwrap()),
+            Position::new(2, 0)
+        );
+        assert_eq!(
+            position(text, text.find("locale").unwrap()),
+            Position::new(3, 0)
+        );
+        assert_eq!(position(text, text.len()), Position::new(4, 0));
+        // between \r and \n is still the end of the line
+        assert_eq!(
+            position(text, text.find('\n').unwrap() + 6),
+            Position::new(1, 3)
+        );
+
+        for (i, _) in text.char_indices() {
+            if text[..i].ends_with('\r') && text[i..].starts_with('\n') {
+                continue;
+            }
+            assert_eq!(offset(text, position(text, i)), i, "offset {}", i);
+        }
+
+        // characters beyond the end of a line are at its end
+        assert_eq!(
+            offset(text, Position::new(1, 100)),
+            text.find('\r').unwrap()
+        );
+        assert_eq!(
+            offset(text, Position::new(2, 100)),
+            text.rfind('\r').unwrap()
+        );
+        assert_eq!(offset(text, Position::new(9, 0)), text.len());
+    }
+
+    #[test]
+    fn range_test() {
+        let text = "hostname ä\rkeyboard\r\n";
+        let span = |start: usize, end: usize| Span {
+            file: None,
+            line: 0,
+            column: 0,
+            start,
+            end,
+        };
+        assert_eq!(
+            range(text, &span(12, text.len())),
+            Range::new(Position::new(1, 0), Position::new(1, 8))
+        );
+        assert_eq!(
+            range(text, &span(9, 9)),
+            Range::new(Position::new(0, 9), Position::new(0, 10))
+        );
+        // an empty span at the end of a line marks no line break
+        assert_eq!(
+            range(text, &span(20, 20)),
+            Range::new(Position::new(1, 8), Position::new(1, 8))
+        );
+    }
+
+    #[test]
+    fn completion_test() {
+        let analyzer = analyzer();
+        for newline in ["\n", "\r\n", "\r"] {
+            let text = [
+                "# sample profile",
+                "hostname \"büro\"",
+                "network_interface net0 --primary --t",
+                "network_interface net1 --type=",
+                "network_interface net2 {",
+                "    ",
+                "}",
+                "ho",
+            ]
+            .join(newline);
+            let at = |line: u32| {
+                let character = text.split(newline).nth(line as usize).unwrap().len() as u32;
+                Position::new(line, character)
+            };
+
+            assert_eq!(
+                labels(analyzer.completion(&text, at(2))),
+                vec!["--static=", "--type="]
+            );
+            assert_eq!(
+                labels(analyzer.completion(&text, at(3))),
+                vec!["--type=ether", "--type=vlan"]
+            );
+            assert_eq!(
+                labels(analyzer.completion(&text, at(5))),
+                vec!["primary", "route", "static", "type"]
+            );
+            assert_eq!(
+                labels(analyzer.completion(&text, at(7))),
+                vec![
+                    "else",
+                    "endif",
+                    "hostname",
+                    "if",
+                    "include",
+                    "network_interface",
+                    "set"
+                ]
+            );
+
+            // the edit replaces the word before the cursor
+            let item = analyzer
+                .completion(&text, at(7))
+                .into_iter()
+                .find(|i| i.label == "hostname")
+                .unwrap();
+            match item.text_edit {
+                Some(CompletionTextEdit::Edit(edit)) => {
+                    assert_eq!(edit.range, Range::new(Position::new(7, 0), at(7)))
+                }
+                other => panic!("expected an edit, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn diagnostics_test() {
+        let analyzer = analyzer();
+        for newline in ["\n", "\r\n", "\r"] {
+            let text = [
+                "# sample profile",
+                "hostname \"büro\"",
+                "network_interfce net0",
+                "network_interface net1 --mtu=1500",
+                "",
+            ]
+            .join(newline);
+            let diagnostics = analyzer.diagnostics(&text, None);
+            assert_eq!(diagnostics.len(), 2, "{:?}", diagnostics);
+            assert!(diagnostics[0]
+                .message
+                .starts_with("keyword network_interfce is not known"));
+            assert_eq!(
+                diagnostics[0].range,
+                Range::new(Position::new(2, 0), Position::new(2, 16))
+            );
+            assert!(diagnostics[1]
+                .message
+                .starts_with("option mtu is not known for keyword network_interface"));
+            assert_eq!(diagnostics[1].range.start.line, 3);
+            assert!(diagnostics
+                .iter()
+                .all(|d| d.severity == Some(DiagnosticSeverity::ERROR)));
+        }
+
+        assert!(analyzer
+            .diagnostics(
+                "hostname \"büro\"\rnetwork_interface net0 --type=ether\r",
+                None
+            )
+            .is_empty());
+    }
+}
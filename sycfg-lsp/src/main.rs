@@ -0,0 +1,171 @@
+mod analysis;
+
+use analysis::Analyzer;
+use anyhow::Result;
+use clap::{ArgEnum, Parser};
+use libcfgparser::FromKeyword;
+use lsp_server::{Connection, Message, Notification, Request, RequestId, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _,
+    PublishDiagnostics,
+};
+use lsp_types::request::{Completion, GotoDefinition, HoverRequest};
+use lsp_types::{
+    CompletionOptions, CompletionResponse, GotoDefinitionResponse, HoverProviderCapability, OneOf,
+    PublishDiagnosticsParams, ServerCapabilities, TextDocumentSyncCapability, TextDocumentSyncKind,
+    Url,
+};
+use std::collections::HashMap;
+
+#[derive(Copy, Clone, ArgEnum)]
+enum Keywords {
+    /// Keywords of sysconfig
+    Sysconfig,
+    /// Keywords of the installer
+    Install,
+}
+
+#[derive(Parser)]
+#[clap(author, version, about, long_about = None)]
+struct Cli {
+    // Which keywords the edited files use
+    #[clap(long, arg_enum, default_value = "sysconfig")]
+    keywords: Keywords,
+}
+
+/// The params of `req` if it is a request of type `R`
+fn cast<R>(req: &Request) -> Option<R::Params>
+where
+    R: lsp_types::request::Request,
+{
+    if req.method == R::METHOD {
+        serde_json::from_value(req.params.clone()).ok()
+    } else {
+        None
+    }
+}
+
+fn publish_diagnostics(
+    connection: &Connection,
+    analyzer: &Analyzer,
+    uri: Url,
+    text: &str,
+) -> Result<()> {
+    let path = uri.to_file_path().ok();
+    let params =
+        PublishDiagnosticsParams::new(uri, analyzer.diagnostics(text, path.as_deref()), None);
+    connection
+        .sender
+        .send(Message::Notification(Notification::new(
+            PublishDiagnostics::METHOD.into(),
+            params,
+        )))?;
+    Ok(())
+}
+
+fn handle_request(
+    analyzer: &Analyzer,
+    documents: &HashMap<Url, String>,
+    req: &Request,
+) -> Result<serde_json::Value> {
+    if let Some(params) = cast::<Completion>(req) {
+        let doc = &params.text_document_position;
+        let items = documents
+            .get(&doc.text_document.uri)
+            .map(|text| analyzer.completion(text, doc.position))
+            .unwrap_or_default();
+        return Ok(serde_json::to_value(CompletionResponse::Array(items))?);
+    }
+    if let Some(params) = cast::<HoverRequest>(req) {
+        let doc = &params.text_document_position_params;
+        let hover = documents
+            .get(&doc.text_document.uri)
+            .and_then(|text| analyzer.hover(text, doc.position));
+        return Ok(serde_json::to_value(hover)?);
+    }
+    if let Some(params) = cast::<GotoDefinition>(req) {
+        let doc = &params.text_document_position_params;
+        let locations = match (
+            documents.get(&doc.text_document.uri),
+            doc.text_document.uri.to_file_path(),
+        ) {
+            (Some(text), Ok(path)) => analyzer.definition(text, doc.position, &path),
+            _ => vec![],
+        };
+        return Ok(serde_json::to_value(GotoDefinitionResponse::Array(
+            locations,
+        ))?);
+    }
+    Ok(serde_json::Value::Null)
+}
+
+fn main() -> Result<()> {
+    let cli: Cli = Cli::parse();
+    let analyzer = Analyzer::new(match cli.keywords {
+        Keywords::Sysconfig => libsysconfig::Instruction::keyword_definitions(),
+        Keywords::Install => libinstall::Instruction::keyword_definitions(),
+    });
+
+    let (connection, io_threads) = Connection::stdio();
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(vec!["-".into(), "=".into()]),
+            ..Default::default()
+        }),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        definition_provider: Some(OneOf::Left(true)),
+        ..Default::default()
+    };
+    connection.initialize(serde_json::to_value(capabilities)?)?;
+
+    let mut documents: HashMap<Url, String> = HashMap::new();
+    for msg in &connection.receiver {
+        match msg {
+            Message::Request(req) => {
+                if connection.handle_shutdown(&req)? {
+                    break;
+                }
+                let id: RequestId = req.id.clone();
+                let response = match handle_request(&analyzer, &documents, &req) {
+                    Ok(result) => Response::new_ok(id, result),
+                    Err(e) => Response::new_err(
+                        id,
+                        lsp_server::ErrorCode::InternalError as i32,
+                        e.to_string(),
+                    ),
+                };
+                connection.sender.send(Message::Response(response))?;
+            }
+            Message::Notification(not) => match not.method.as_str() {
+                DidOpenTextDocument::METHOD => {
+                    let params: lsp_types::DidOpenTextDocumentParams =
+                        serde_json::from_value(not.params)?;
+                    let doc = params.text_document;
+                    publish_diagnostics(&connection, &analyzer, doc.uri.clone(), &doc.text)?;
+                    documents.insert(doc.uri, doc.text);
+                }
+                DidChangeTextDocument::METHOD => {
+                    let params: lsp_types::DidChangeTextDocumentParams =
+                        serde_json::from_value(not.params)?;
+                    // full sync, the last change holds the whole text
+                    if let Some(change) = params.content_changes.into_iter().last() {
+                        let uri = params.text_document.uri;
+                        publish_diagnostics(&connection, &analyzer, uri.clone(), &change.text)?;
+                        documents.insert(uri, change.text);
+                    }
+                }
+                DidCloseTextDocument::METHOD => {
+                    let params: lsp_types::DidCloseTextDocumentParams =
+                        serde_json::from_value(not.params)?;
+                    documents.remove(&params.text_document.uri);
+                }
+                _ => {}
+            },
+            Message::Response(_) => {}
+        }
+    }
+
+    io_threads.join()?;
+    Ok(())
+}
@@ -1,5 +1,6 @@
 use anyhow::Result;
 use clap::Parser;
+use libcfgparser::{docs, find_diagnostics, parse_variable_definition, Loader, SysConfigParser};
 use libsysconfig::InstructionsSet;
 use log::{debug, info, trace};
 use slog::{Drain, Logger};
@@ -7,9 +8,8 @@ use slog_async::Async;
 use slog_scope::{set_global_logger, GlobalLoggerGuard};
 use slog_syslog::Facility;
 use slog_term::{CompactFormat, TermDecorator};
-use std::fs;
-use std::fs::File;
-use std::path::PathBuf;
+use std::io::stdin;
+use std::path::{Path, PathBuf};
 use std::process::Command as PCommand;
 
 static SMF_CONFIG_FILE_PROPERTY: &str = "config/file";
@@ -18,7 +18,8 @@ static SMF_FINISHED_PROPERTY: &str = "config/finished";
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
 struct Cli {
-    // File that holds the system config to apply
+    // File that holds the system config to apply in the native format,
+    // JSON, YAML, RON or TOML. - reads the config from stdin
     #[clap(long, default_value = "/etc/sysconfig.json")]
     file: PathBuf,
 
@@ -29,6 +30,14 @@ struct Cli {
     // Alternate root
     #[clap(short = 'R', long)]
     alt_root: Option<String>,
+
+    // Variables for ${NAME} in the config file given as NAME=VALUE
+    #[clap(short = 'D', long = "define", parse(try_from_str = parse_variable_definition))]
+    variables: Vec<(String, String)>,
+
+    // Print the documentation of a keyword of the config file and exit
+    #[clap(long, value_name = "KEYWORD")]
+    help_keyword: Option<String>,
 }
 
 pub fn init_slog_logging(use_syslog: bool) -> Result<GlobalLoggerGuard> {
@@ -53,11 +62,40 @@ pub fn init_slog_logging(use_syslog: bool) -> Result<GlobalLoggerGuard> {
     }
 }
 
+/// Read the config file in any supported format, `-` reads stdin. If
+/// errors point to a location in the file print the offending lines so
+/// the operator can find them.
+fn load_config(parser: &SysConfigParser, cfg_file: &Path) -> Result<InstructionsSet> {
+    let loader = Loader::new(parser, libsysconfig::parse_keywords);
+    let instructions = if cfg_file == Path::new("-") {
+        loader.load_reader(stdin())
+    } else {
+        loader.load_file(cfg_file)
+    };
+    instructions.map_err(|e| {
+        for rendered in find_diagnostics(&e)
+            .iter()
+            .filter_map(|d| d.render_from_file())
+        {
+            eprint!("{}", rendered);
+        }
+        e
+    })
+}
+
 fn main() -> Result<()> {
     let logger_guard: GlobalLoggerGuard;
 
     let cli: Cli = Cli::parse();
 
+    if let Some(name) = &cli.help_keyword {
+        print!(
+            "{}",
+            docs::help_for(&libsysconfig::get_supported_keywords(), name)?
+        );
+        return Ok(());
+    }
+
     if let Some(smf_fmri) = cli.smf_fmri.clone() {
         logger_guard = init_slog_logging(true)?;
 
@@ -92,39 +130,18 @@ fn main() -> Result<()> {
         debug!(target: "sysconfig", "config file is given but could not decode path to something that can be logged");
     }
 
-    let mut parser = libcfgparser::SysConfigParser::default();
+    let mut parser = SysConfigParser::default();
     for (key, v) in libsysconfig::get_supported_keywords() {
         trace!(target: "sysconfig", "Adding Keyword {} to parser", &key);
         parser.add_keyword(key, v);
     }
+    for (name, value) in &cli.variables {
+        parser.set_variable(name, value);
+    }
+    parser.set_fact_provider(Box::new(libsysconfig::system_facts()));
 
     debug!(target: "sysconfig", "Parsing config file");
-    let instructions = if let Some(ext) = cfg_file.extension() {
-        if ext == "json" {
-            let f = File::open(cfg_file)?;
-            debug!(target: "sysconfig", "Parsing JSON config");
-            let set: InstructionsSet = serde_json::from_reader(f)?;
-            set
-        } else if ext == "yml" || ext == "yaml" {
-            let f = File::open(cfg_file)?;
-            debug!(target: "sysconfig", "Parsing YAML config");
-            let set: InstructionsSet = serde_yaml::from_reader(f)?;
-            set
-        } else if ext == "ron" {
-            let file_content = fs::read_to_string(cfg_file)?;
-            debug!(target: "sysconfig", "Parsing RON config");
-            let set: InstructionsSet = ron::from_str(&file_content)?;
-            set
-        } else {
-            debug!(target: "sysconfig", "Parsing Custom sysconfig format");
-            let keywords = parser.parse_config_file(cfg_file)?;
-            libsysconfig::parse_keywords(keywords)?
-        }
-    } else {
-        debug!(target: "sysconfig", "Parsing Custom sysconfig format");
-        let keywords = parser.parse_config_file(cfg_file)?;
-        libsysconfig::parse_keywords(keywords)?
-    };
+    let instructions = load_config(&parser, &cfg_file)?;
 
     // If we are nor running under SMF require an alternate root or mock
     let img = if cli.smf_fmri == None {
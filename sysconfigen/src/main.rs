@@ -1,12 +1,18 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 use clap::{ArgEnum, Parser, Subcommand};
+use libcfgparser::{
+    docs, find_diagnostics, parse_variable_definition, Document, Format, FromKeyword, Loader,
+    SysConfigParser,
+};
 use ron::ser::PrettyConfig;
 use serde::Serialize;
 use shrust::{Shell, ShellIO};
+use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
+use std::fs;
 use std::fs::File;
 use std::io::{stdout, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use thiserror::Error;
 
@@ -18,6 +24,10 @@ struct Cli {
 
     #[clap(short = 'O', long, env, default_value = "ron")]
     output_format: OutputFormat,
+
+    // Print the documentation of a keyword of the config file and exit
+    #[clap(long, value_name = "KEYWORD")]
+    help_keyword: Option<String>,
 }
 
 #[derive(Subcommand)]
@@ -27,9 +37,74 @@ enum Commands {
         #[clap(short, long, env)]
         output_file: Option<PathBuf>,
 
-        // File to read the human readable config from
+        // Variables for ${NAME} in the config file given as NAME=VALUE
+        #[clap(short = 'D', long = "define", parse(try_from_str = parse_variable_definition))]
+        variables: Vec<(String, String)>,
+
+        // Facts for if conditions given as NAME=VALUE. Can be repeated for
+        // facts with multiple values like mac
+        #[clap(short = 'F', long = "fact", parse(try_from_str = parse_variable_definition))]
+        facts: Vec<(String, String)>,
+
+        // Root of the image the config is built for. Clear text root
+        // passwords are checked and hashed with its policies
+        #[clap(short = 'R', long, default_value = "/")]
+        root: String,
+
+        // File to read the config from, usually in the native format
+        file: PathBuf,
+    },
+    // Rewrite a config file in canonical form, comments are kept
+    Fmt {
+        // Only check if the file is formatted, fail if it is not
+        #[clap(long)]
+        check: bool,
+
+        // Write the result back to the file instead of stdout
+        #[clap(short, long)]
+        in_place: bool,
+
+        file: PathBuf,
+    },
+    // Print the JSON schema of configurations in JSON, YAML or TOML
+    Schema {
+        // Which instructions the schema describes
+        #[clap(long, arg_enum, default_value = "sysconfig")]
+        keywords: KeywordSet,
+    },
+    // Check a JSON, YAML, RON or TOML configuration against its schema
+    Validate {
+        // Which instructions the file holds
+        #[clap(long, arg_enum, default_value = "sysconfig")]
+        keywords: KeywordSet,
+
         file: PathBuf,
     },
+    // Generate the reference of all keywords as man page or Markdown
+    Docs {
+        // Which keywords to document
+        #[clap(long, arg_enum, default_value = "sysconfig")]
+        keywords: KeywordSet,
+
+        #[clap(long, arg_enum, default_value = "markdown")]
+        format: DocsFormat,
+
+        // Output file for the reference
+        #[clap(short, long)]
+        output_file: Option<PathBuf>,
+    },
+}
+
+#[derive(ArgEnum, Clone, Copy)]
+enum KeywordSet {
+    Sysconfig,
+    Install,
+}
+
+#[derive(ArgEnum, Clone, Copy)]
+enum DocsFormat {
+    Man,
+    Markdown,
 }
 
 #[derive(Error, Debug)]
@@ -81,24 +156,89 @@ impl Display for OutputFormat {
     }
 }
 
+/// Check a configuration in JSON, YAML, RON or TOML. The format is
+/// taken from the extension or sniffed from the content.
+fn validate_file(keywords: KeywordSet, file: &Path) -> Result<()> {
+    let content = fs::read_to_string(file)?;
+    let format = Format::detect(file, &content);
+    if format == Format::Native {
+        bail!("only JSON, YAML, RON and TOML files have a schema");
+    }
+    let parser = SysConfigParser::default();
+    match keywords {
+        KeywordSet::Sysconfig => {
+            Loader::new(&parser, libsysconfig::parse_keywords).load_str(
+                &content,
+                format,
+                Some(file),
+            )?;
+        }
+        KeywordSet::Install => {
+            Loader::new(&parser, libinstall::parse_keywords).load_str(
+                &content,
+                format,
+                Some(file),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Print the offending lines of errors which point into a file
+fn render_diagnostics(err: &anyhow::Error) {
+    for rendered in find_diagnostics(err)
+        .iter()
+        .filter_map(|d| d.render_from_file())
+    {
+        eprint!("{}", rendered);
+    }
+}
+
 fn main() -> Result<()> {
     let cli: Cli = Cli::parse();
 
+    if let Some(name) = &cli.help_keyword {
+        print!(
+            "{}",
+            docs::help_for(&libsysconfig::get_supported_keywords(), name)?
+        );
+        return Ok(());
+    }
+
     if let Some(cmd) = cli.commands {
         match cmd {
-            Commands::Build { output_file, file } => {
+            Commands::Build {
+                output_file,
+                variables,
+                facts,
+                root,
+                file,
+            } => {
                 let mut out: Box<dyn Write> = if let Some(file) = output_file {
                     Box::new(File::create(file)?)
                 } else {
                     Box::new(stdout())
                 };
-                let mut parser = libcfgparser::SysConfigParser::default();
+                let mut parser = SysConfigParser::default();
                 for (key, v) in libsysconfig::get_supported_keywords() {
                     parser.add_keyword(key, v);
                 }
+                for (name, value) in &variables {
+                    parser.set_variable(name, value);
+                }
+                let mut fact_map: HashMap<String, Vec<String>> = HashMap::new();
+                for (name, value) in facts {
+                    fact_map.entry(name).or_default().push(value);
+                }
+                parser.set_fact_provider(Box::new(fact_map));
 
-                let keywords = parser.parse_config_file(file)?;
-                let instructions = libsysconfig::parse_keywords(keywords)?;
+                let instructions = Loader::new(&parser, libsysconfig::parse_keywords)
+                    .load_file(file)
+                    .map_err(|e| {
+                        render_diagnostics(&e);
+                        e
+                    })?;
+                let instructions = libsysconfig::hash_root_passwords(&root, instructions)?;
 
                 match cli.output_format {
                     OutputFormat::JSON => {
@@ -121,6 +261,77 @@ fn main() -> Result<()> {
                     }
                 }
             }
+            Commands::Fmt {
+                check,
+                in_place,
+                file,
+            } => {
+                let content = fs::read_to_string(&file)?;
+                let formatted = Document::parse(&content)
+                    .map_err(|e| {
+                        for diag in find_diagnostics(&e) {
+                            eprint!("{}", diag.render(&content));
+                        }
+                        e
+                    })?
+                    .formatted();
+                if check {
+                    if formatted != content {
+                        bail!("{} is not formatted", file.display());
+                    }
+                } else if in_place {
+                    fs::write(&file, formatted)?;
+                } else {
+                    print!("{}", formatted);
+                }
+            }
+            Commands::Schema { keywords } => {
+                let schema = match keywords {
+                    KeywordSet::Sysconfig => libsysconfig::instructions_schema(),
+                    KeywordSet::Install => libinstall::instructions_schema(),
+                };
+                println!("{}", serde_json::to_string_pretty(&schema)?);
+            }
+            Commands::Validate { keywords, file } => {
+                validate_file(keywords, &file).map_err(|e| {
+                    render_diagnostics(&e);
+                    e
+                })?;
+                println!("{} is valid", file.display());
+            }
+            Commands::Docs {
+                keywords,
+                format,
+                output_file,
+            } => {
+                let definitions = match keywords {
+                    KeywordSet::Sysconfig => libsysconfig::get_supported_keywords(),
+                    KeywordSet::Install => libinstall::Instruction::keyword_definitions(),
+                };
+                let reference = match keywords {
+                    KeywordSet::Sysconfig => docs::Reference {
+                        name: "sysconfig",
+                        section: "4",
+                        summary: "system configuration applied on first boot",
+                        definitions: &definitions,
+                    },
+                    KeywordSet::Install => docs::Reference {
+                        name: "install",
+                        section: "4",
+                        summary: "installation profile of the illumos installer",
+                        definitions: &definitions,
+                    },
+                };
+                let text = match format {
+                    DocsFormat::Man => reference.man_page(),
+                    DocsFormat::Markdown => reference.markdown(),
+                };
+                if let Some(file) = output_file {
+                    fs::write(file, text)?;
+                } else {
+                    print!("{}", text);
+                }
+            }
         }
     } else {
         let v = Vec::new();